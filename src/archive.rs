@@ -0,0 +1,157 @@
+//! Loading `.mlpackage` directories shipped as zip archives.
+//!
+//! The archive may either contain the package directory itself
+//! (`model.mlpackage/Manifest.json`) or the package contents at its root
+//! (`Manifest.json`). Entries are extracted into a temp dir owned by
+//! [`ExtractedPackage`], which removes it again on drop.
+
+use crate::{
+    mlmodel::CoreMLError,
+    mlpackage::{MLPackage, MANIFEST},
+};
+use std::{
+    io::{Read, Seek},
+    path::{Component, Path, PathBuf},
+};
+use tempdir::TempDir;
+
+#[derive(Debug, Clone)]
+pub enum ArchiveSource {
+    /// zip archive on disk
+    Path(PathBuf),
+    /// zip archive held in memory
+    Buffer(Vec<u8>),
+}
+
+/// An `.mlpackage` extracted from an archive, removed from disk on drop.
+#[derive(Debug)]
+pub struct ExtractedPackage {
+    dir: TempDir,
+    package: PathBuf,
+}
+
+impl ExtractedPackage {
+    /// Path to the extracted `.mlpackage` directory.
+    pub fn path(&self) -> &Path {
+        &self.package
+    }
+
+    /// Directory the archive got extracted into.
+    pub fn dir(&self) -> &Path {
+        self.dir.path()
+    }
+}
+
+impl ArchiveSource {
+    /// Extracts the `.mlpackage` inside the archive into a new temp dir under `dir`, or
+    /// the system temp dir if `dir` is empty.
+    pub fn extract(&self, dir: impl AsRef<Path>) -> Result<ExtractedPackage, CoreMLError> {
+        match self {
+            ArchiveSource::Path(path) => {
+                let file = std::fs::File::open(path).map_err(CoreMLError::IoError)?;
+                extract_mlpackage(std::io::BufReader::new(file), dir.as_ref())
+            }
+            ArchiveSource::Buffer(buf) => {
+                extract_mlpackage(std::io::Cursor::new(buf.as_slice()), dir.as_ref())
+            }
+        }
+    }
+}
+
+/// Extracts the `.mlpackage` contained in the zip archive `reader` into a temp dir under `dir`.
+///
+/// Entries with absolute paths, `..` components or symlinks are rejected, as is an archive
+/// without a valid `Manifest.json`.
+pub fn extract_mlpackage(
+    reader: impl Read + Seek,
+    dir: &Path,
+) -> Result<ExtractedPackage, CoreMLError> {
+    let mut archive = zip::ZipArchive::new(reader)
+        .map_err(|err| CoreMLError::BadArchive(format!("not a zip archive: {err}")))?;
+
+    // the package root is wherever the shallowest Manifest.json lives
+    let mut root: Option<PathBuf> = None;
+    for i in 0..archive.len() {
+        let file = archive
+            .by_index(i)
+            .map_err(|err| CoreMLError::BadArchive(err.to_string()))?;
+        let name = archive_entry(file.name())?;
+        if file.is_symlink() {
+            return Err(CoreMLError::BadArchive(format!(
+                "archive entry {} is a symlink",
+                file.name()
+            )));
+        }
+        if name.file_name().is_some_and(|n| n == MANIFEST) {
+            let parent = name.parent().unwrap_or(Path::new("")).to_path_buf();
+            if root
+                .as_ref()
+                .is_none_or(|r| parent.components().count() < r.components().count())
+            {
+                root = Some(parent);
+            }
+        }
+    }
+    let Some(root) = root else {
+        return Err(CoreMLError::BadArchive(format!(
+            "archive does not contain an mlpackage {MANIFEST}"
+        )));
+    };
+
+    let tmp = if dir.as_os_str().is_empty() {
+        TempDir::new("coreml-mlpackage")
+    } else {
+        std::fs::create_dir_all(dir).and_then(|_| TempDir::new_in(dir, "coreml-mlpackage"))
+    }
+    .map_err(CoreMLError::IoError)?;
+    let package_name = root
+        .file_name()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("model.mlpackage"));
+    let package = tmp.path().join(package_name);
+
+    for i in 0..archive.len() {
+        let mut file = archive
+            .by_index(i)
+            .map_err(|err| CoreMLError::BadArchive(err.to_string()))?;
+        let name = archive_entry(file.name())?;
+        // skip anything outside of the package, e.g. __MACOSX resource forks
+        let Ok(rel) = name.strip_prefix(&root) else {
+            continue;
+        };
+        let out = package.join(rel);
+        if file.is_dir() {
+            std::fs::create_dir_all(&out).map_err(CoreMLError::IoError)?;
+            continue;
+        }
+        if let Some(parent) = out.parent() {
+            std::fs::create_dir_all(parent).map_err(CoreMLError::IoError)?;
+        }
+        let mut w = std::fs::File::create(&out).map_err(CoreMLError::IoError)?;
+        std::io::copy(&mut file, &mut w).map_err(CoreMLError::IoError)?;
+    }
+
+    MLPackage::open(&package)?.validate()?;
+    Ok(ExtractedPackage { dir: tmp, package })
+}
+
+/// Relative path of an archive entry, rejecting anything that could escape the target dir.
+fn archive_entry(name: &str) -> Result<PathBuf, CoreMLError> {
+    enclosed_name(name).ok_or_else(|| {
+        CoreMLError::BadArchive(format!("archive entry {name} escapes the extraction dir"))
+    })
+}
+
+/// `name` as a relative path, `None` if it is absolute or has `..` components and could
+/// point outside of the dir it gets joined to.
+pub(crate) fn enclosed_name(name: &str) -> Option<PathBuf> {
+    let mut out = PathBuf::new();
+    for component in Path::new(name).components() {
+        match component {
+            Component::Normal(c) => out.push(c),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(out)
+}
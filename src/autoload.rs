@@ -0,0 +1,264 @@
+//! Lazy loading and idle unloading on top of [`ModelLifecycle`].
+//!
+//! [`AutoLoadModel`] loads the model on first use and, with an idle timeout, unloads it
+//! again from a background thread once it was not used for that long, freeing the memory
+//! (and ANE resources) it holds until the next use.
+//!
+//! ```no_run
+//! # use coreml_rs::{autoload::*, CoreMLModelOptions, CoreMLModelWithState};
+//! # use std::{sync::Arc, time::Duration};
+//! let model = CoreMLModelWithState::new("model.mlpackage", CoreMLModelOptions::default());
+//! let model = AutoLoadModel::new(
+//!     model,
+//!     AutoLoadOptions {
+//!         idle_timeout: Some(Duration::from_secs(30)),
+//!         on_transition: Some(Arc::new(|t| eprintln!("model {t:?}"))),
+//!         ..Default::default()
+//!     },
+//! );
+//! let input = ndarray::Array::<f32, _>::zeros(vec![1, 3, 512, 512]);
+//! let output = model.predict([("image", input)]).unwrap();
+//! ```
+
+use crate::{
+    batch::BatchBuilder,
+    lifecycle::{ModelKind, ModelLifecycle},
+    mlarray::MLArray,
+    mlbatchmodel::{CoreMLBatchModel, MLBatchModelOutput},
+    mlmodel::{CoreMLError, CoreMLModel, MLModelOutput},
+};
+use std::{
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transition {
+    /// Loaded on use, taking `took`
+    Loaded {
+        took: Duration,
+    },
+    LoadFailed {
+        reason: String,
+    },
+    /// Unloaded, by the idle timer or `unload`, after not being used for `idle`
+    Unloaded {
+        idle: Duration,
+    },
+    /// `unload_to_disk` failed, the model stays loaded
+    UnloadFailed {
+        reason: String,
+    },
+}
+
+/// Called after every transition, on the thread that made it.
+pub type TransitionHook = Arc<dyn Fn(&Transition) + Send + Sync>;
+
+#[derive(Clone, Default)]
+pub struct AutoLoadOptions {
+    /// Unload after the model was not used for this long, never if `None`
+    pub idle_timeout: Option<Duration>,
+    /// Unload with `unload_to_disk` instead of `unload`, moving model buffers to `cache_dir`
+    pub unload_to_disk: bool,
+    pub on_transition: Option<TransitionHook>,
+}
+
+impl std::fmt::Debug for AutoLoadOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AutoLoadOptions")
+            .field("idle_timeout", &self.idle_timeout)
+            .field("unload_to_disk", &self.unload_to_disk)
+            .finish()
+    }
+}
+
+struct State<M> {
+    model: ModelLifecycle<M>,
+    last_used: Instant,
+    shutdown: bool,
+}
+
+struct Shared<M> {
+    state: Mutex<State<M>>,
+    /// Signalled on every use and on shutdown, so the idle timer restarts
+    used: Condvar,
+    opts: AutoLoadOptions,
+}
+
+impl<M> Shared<M> {
+    fn lock(&self) -> MutexGuard<'_, State<M>> {
+        // a panic while holding the lock leaves the model in a consistent state
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn notify(&self, transition: Option<Transition>) {
+        if let (Some(transition), Some(hook)) = (transition, &self.opts.on_transition) {
+            hook(&transition);
+        }
+    }
+}
+
+pub struct AutoLoadModel<M> {
+    shared: Arc<Shared<M>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl<M: ModelKind + Send + 'static> AutoLoadModel<M> {
+    /// Wraps `model`, loaded or not, starting the idle timer if `idle_timeout` is set.
+    pub fn new(model: ModelLifecycle<M>, opts: AutoLoadOptions) -> Self {
+        let idle_timeout = opts.idle_timeout;
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                model,
+                last_used: Instant::now(),
+                shutdown: false,
+            }),
+            used: Condvar::new(),
+            opts,
+        });
+        let worker = idle_timeout.map(|timeout| {
+            let shared = shared.clone();
+            std::thread::spawn(move || unload_when_idle(&shared, timeout))
+        });
+        Self { shared, worker }
+    }
+
+    /// Runs `f` on the model, loading it first if needed.
+    pub fn with<R>(
+        &self,
+        f: impl FnOnce(&mut ModelLifecycle<M>) -> Result<R, CoreMLError>,
+    ) -> Result<R, CoreMLError> {
+        let mut state = self.shared.lock();
+        let mut transition = None;
+        if !state.model.is_loaded() {
+            let start = Instant::now();
+            if let Err(err) = state.model.load() {
+                drop(state);
+                self.shared.notify(Some(Transition::LoadFailed {
+                    reason: err.to_string(),
+                }));
+                return Err(err);
+            }
+            transition = Some(Transition::Loaded {
+                took: start.elapsed(),
+            });
+        }
+        let res = f(&mut state.model);
+        state.last_used = Instant::now();
+        self.shared.used.notify_all();
+        drop(state);
+        self.shared.notify(transition);
+        res
+    }
+
+    pub fn is_loaded(&self) -> bool {
+        self.shared.lock().model.is_loaded()
+    }
+
+    /// Unloads the model now, it is loaded again on the next use.
+    pub fn unload(&self) -> Result<(), CoreMLError> {
+        let mut state = self.shared.lock();
+        if !state.model.is_loaded() {
+            return Ok(());
+        }
+        let idle = state.last_used.elapsed();
+        let res = if self.shared.opts.unload_to_disk {
+            state.model.unload_to_disk()
+        } else {
+            state.model.unload();
+            Ok(())
+        };
+        drop(state);
+        self.shared.notify(Some(match &res {
+            Ok(()) => Transition::Unloaded { idle },
+            Err(err) => Transition::UnloadFailed {
+                reason: err.to_string(),
+            },
+        }));
+        res
+    }
+}
+
+impl<M> AutoLoadModel<M> {
+    fn stop(&mut self) {
+        self.shared.lock().shutdown = true;
+        self.shared.used.notify_all();
+        if let Some(worker) = self.worker.take() {
+            _ = worker.join();
+        }
+    }
+}
+
+impl<M> Drop for AutoLoadModel<M> {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl AutoLoadModel<CoreMLModel> {
+    /// Binds `inputs` and predicts in one go, so the model can't be unloaded in between.
+    pub fn predict(
+        &self,
+        inputs: impl IntoIterator<Item = (impl AsRef<str>, impl Into<MLArray>)>,
+    ) -> Result<MLModelOutput, CoreMLError> {
+        self.with(|model| {
+            for (tag, input) in inputs {
+                model.add_input(tag, input)?;
+            }
+            model.predict()
+        })
+    }
+}
+
+impl AutoLoadModel<CoreMLBatchModel> {
+    pub fn predict_batch(
+        &self,
+        batch: &mut BatchBuilder,
+    ) -> Result<MLBatchModelOutput, CoreMLError> {
+        self.with(|model| model.predict_batch(batch))
+    }
+}
+
+fn unload_when_idle<M: ModelKind>(shared: &Shared<M>, timeout: Duration) {
+    let mut state = shared.lock();
+    loop {
+        if state.shutdown {
+            return;
+        }
+        if !state.model.is_loaded() {
+            state = shared
+                .used
+                .wait(state)
+                .unwrap_or_else(|err| err.into_inner());
+            continue;
+        }
+        let idle = state.last_used.elapsed();
+        if idle < timeout {
+            state = shared
+                .used
+                .wait_timeout(state, timeout - idle)
+                .unwrap_or_else(|err| err.into_inner())
+                .0;
+            continue;
+        }
+        let transition = if shared.opts.unload_to_disk {
+            match state.model.unload_to_disk() {
+                Ok(()) => Transition::Unloaded { idle },
+                Err(err) => {
+                    // don't retry until the model was used again
+                    state.last_used = Instant::now();
+                    Transition::UnloadFailed {
+                        reason: err.to_string(),
+                    }
+                }
+            }
+        } else {
+            state.model.unload();
+            Transition::Unloaded { idle }
+        };
+        drop(state);
+        shared.notify(Some(transition));
+        state = shared.lock();
+    }
+}
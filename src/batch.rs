@@ -0,0 +1,185 @@
+//! Explicit construction of batches for [`CoreMLBatchModel`](crate::mlbatchmodel::CoreMLBatchModel),
+//! and conversion between batched tensors and per-item arrays.
+
+use crate::{
+    mlarray::{MLArray, MLType},
+    mlmodel::CoreMLError,
+};
+use ndarray::{Array, ArrayD, ArrayViewD, Axis};
+use std::collections::HashMap;
+
+/// Inputs of a batch, one feature map per item, indexed in push order.
+///
+/// ```no_run
+/// # use coreml_rs::{batch::BatchBuilder, mlbatchmodel::CoreMLBatchModelWithState};
+/// # use std::collections::HashMap;
+/// # fn run(model: &mut CoreMLBatchModelWithState) -> Result<(), coreml_rs::mlmodel::CoreMLError> {
+/// let mut batch = BatchBuilder::new();
+/// for _ in 0..4 {
+///     let image = ndarray::Array::<f32, _>::zeros(vec![1, 3, 512, 512]);
+///     batch.push(HashMap::from([("image".to_string(), image.into())]));
+/// }
+/// let outputs = model.predict_batch(&mut batch)?;
+/// assert!(batch.is_empty());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Default)]
+pub struct BatchBuilder {
+    items: Vec<HashMap<String, MLArray>>,
+}
+
+impl BatchBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an item to the batch, returning its index.
+    pub fn push(&mut self, inputs: HashMap<String, MLArray>) -> usize {
+        self.items.push(inputs);
+        self.items.len() - 1
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.items.clear();
+    }
+
+    pub fn items(&self) -> &[HashMap<String, MLArray>] {
+        &self.items
+    }
+
+    /// Removes the items, leaving the builder empty for the next batch.
+    pub fn drain(&mut self) -> std::vec::Drain<'_, HashMap<String, MLArray>> {
+        self.items.drain(..)
+    }
+
+    /// Checks the batch is not empty and every item has all of the `required` inputs.
+    pub fn validate(&self, required: &[String]) -> Result<(), CoreMLError> {
+        validate_items(self.items.iter().map(|item| item.keys()), required)
+    }
+}
+
+/// Checks every item of a non-empty batch has all of the `required` input names.
+pub(crate) fn validate_items<I, S>(
+    items: impl Iterator<Item = I>,
+    required: &[String],
+) -> Result<(), CoreMLError>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    let mut len = 0;
+    for (idx, names) in items.enumerate() {
+        len += 1;
+        let names: Vec<S> = names.into_iter().collect();
+        let mut missing: Vec<&str> = required
+            .iter()
+            .map(|r| r.as_str())
+            .filter(|r| !names.iter().any(|n| n.as_ref() == *r))
+            .collect();
+        if !missing.is_empty() {
+            missing.sort();
+            return Err(CoreMLError::BadInputShape(format!(
+                "batch item {idx} is missing inputs {missing:?}"
+            )));
+        }
+    }
+    if len == 0 {
+        return Err(CoreMLError::BadInputShape("batch is empty".to_string()));
+    }
+    Ok(())
+}
+
+/// Splits `array` along `axis` into one array per batch item, keeping `axis` with size 1 if
+/// `keep_axis` is set. Items are always in standard (row major) layout.
+pub fn split_batch(
+    array: MLArray,
+    axis: usize,
+    keep_axis: bool,
+) -> Result<Vec<MLArray>, CoreMLError> {
+    if axis >= array.shape().len() {
+        return Err(CoreMLError::BadInputShape(format!(
+            "batch axis {axis} is out of bounds for shape {:?}",
+            array.shape()
+        )));
+    }
+    Ok(match array {
+        MLArray::Float32Array(a) => split(a, axis, keep_axis),
+        MLArray::Float16Array(a) => split(a, axis, keep_axis),
+        MLArray::Float64Array(a) => split(a, axis, keep_axis),
+        MLArray::Int32Array(a) => split(a, axis, keep_axis),
+        MLArray::Int16Array(a) => split(a, axis, keep_axis),
+        MLArray::Int8Array(a) => split(a, axis, keep_axis),
+        MLArray::UInt32Array(a) => split(a, axis, keep_axis),
+        MLArray::UInt16Array(a) => split(a, axis, keep_axis),
+        MLArray::UInt8Array(a) => split(a, axis, keep_axis),
+    })
+}
+
+fn split<T: MLType + Clone>(array: ArrayD<T>, axis: usize, keep_axis: bool) -> Vec<MLArray> {
+    array
+        .axis_iter(Axis(axis))
+        .map(|item| {
+            let mut shape = item.shape().to_vec();
+            if keep_axis {
+                shape.insert(axis, 1);
+            }
+            let data = item.iter().cloned().collect();
+            // the element count always matches
+            Array::from_shape_vec(shape, data).unwrap().into()
+        })
+        .collect()
+}
+
+/// Stacks same typed and shaped arrays along a new leading axis.
+pub fn stack(arrays: &[&MLArray]) -> Result<MLArray, CoreMLError> {
+    let Some(first) = arrays.first() else {
+        return Err(CoreMLError::BadInputShape(
+            "cannot stack an empty batch".to_string(),
+        ));
+    };
+    if let Some(other) = arrays.iter().find(|a| a.shape() != first.shape()) {
+        return Err(CoreMLError::BadInputShape(format!(
+            "cannot stack shape {:?} with shape {:?}",
+            other.shape(),
+            first.shape()
+        )));
+    }
+    match first {
+        MLArray::Float32Array(_) => stack_as(arrays, |a| match a {
+            MLArray::Float32Array(a) => Some(a.view()),
+            _ => None,
+        }),
+        MLArray::Float16Array(_) => stack_as(arrays, |a| match a {
+            MLArray::Float16Array(a) => Some(a.view()),
+            _ => None,
+        }),
+        _ => Err(CoreMLError::UnknownErrorStatic(
+            "only f32 and f16 arrays can be stacked",
+        )),
+    }
+}
+
+fn stack_as<'a, T: MLType + Clone + 'a>(
+    arrays: &[&'a MLArray],
+    view: impl Fn(&'a MLArray) -> Option<ArrayViewD<'a, T>>,
+) -> Result<MLArray, CoreMLError> {
+    let views = arrays
+        .iter()
+        .map(|a| view(a))
+        .collect::<Option<Vec<_>>>()
+        .ok_or(CoreMLError::UnknownErrorStatic(
+            "cannot stack arrays of different types",
+        ))?;
+    ndarray::stack(Axis(0), &views)
+        .map(|a| a.into())
+        .map_err(|err| CoreMLError::BadInputShape(err.to_string()))
+}
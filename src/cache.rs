@@ -0,0 +1,402 @@
+//! Content-addressed cache for compiled `.mlmodelc` directories.
+//!
+//! Entries are keyed by the model contents, the options that influence
+//! compilation and the OS version, and live under `cache_dir/compiled`:
+//!
+//! ```text
+//! <cache_dir>/compiled/<key>/model.mlmodelc/...
+//! <cache_dir>/compiled/<key>/integrity
+//! ```
+//!
+//! The `integrity` file lists every file of the compiled model with its size
+//! and sha256 digest, it is checked before an entry is reused so partially
+//! written or purged entries are recompiled instead of failing to load.
+
+use crate::{ffi::ComputePlatform, mlmodel::CoreMLError, CoreMLModelOptions};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+const COMPILED_DIR: &str = "model.mlmodelc";
+const INTEGRITY_FILE: &str = "integrity";
+
+/// Key of a cache entry, a hex encoded sha256.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey(String);
+
+impl CacheKey {
+    /// Key for a single file `.mlmodel` held in memory.
+    pub fn from_bytes(buf: &[u8], opts: &CoreMLModelOptions) -> Self {
+        let digest = Sha256::digest(buf);
+        Self::with_options(&format!("{digest:x}"), opts)
+    }
+
+    /// Key for a model on disk, either an `.mlmodel` file or an `.mlpackage` directory.
+    pub fn from_path(
+        path: impl AsRef<Path>,
+        opts: &CoreMLModelOptions,
+    ) -> Result<Self, CoreMLError> {
+        let digest = content_digest(path.as_ref())?;
+        Ok(Self::with_options(&digest, opts))
+    }
+
+    fn with_options(content_digest: &str, opts: &CoreMLModelOptions) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(content_digest.as_bytes());
+        hasher.update(compute_platform_name(&opts.compute_platform).as_bytes());
+        hasher.update(crate::ffi::os_version().as_bytes());
+        hasher.update(env!("CARGO_PKG_VERSION").as_bytes());
+        Self(format!("{:x}", hasher.finalize()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for CacheKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CompiledModelCache {
+    root: PathBuf,
+    max_size: Option<u64>,
+}
+
+impl CompiledModelCache {
+    pub fn new(root: impl AsRef<Path>) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+            max_size: None,
+        }
+    }
+
+    /// Cache rooted at `cache_dir/compiled`, `cache_dir` defaults to the working directory
+    /// like `unload_to_disk` does.
+    pub fn from_options(opts: &CoreMLModelOptions) -> Self {
+        let cache_dir = if opts.cache_dir.as_os_str().is_empty() {
+            PathBuf::from(".")
+        } else {
+            opts.cache_dir.clone()
+        };
+        Self {
+            root: cache_dir.join("compiled"),
+            max_size: opts.compiled_cache_limit,
+        }
+    }
+
+    /// Limit the total size of the cache in bytes, enforced by `gc` after every insert.
+    pub fn with_max_size(mut self, bytes: u64) -> Self {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Path the compiled model for `key` is stored at, whether it exists or not.
+    pub fn entry_path(&self, key: &CacheKey) -> PathBuf {
+        self.root.join(key.as_str()).join(COMPILED_DIR)
+    }
+
+    /// Returns the compiled model for `key` if it is present and all of its files have the
+    /// expected sizes, entries that fail the check are removed.
+    pub fn get(&self, key: &CacheKey) -> Option<PathBuf> {
+        let entry = self.root.join(key.as_str());
+        match check_entry(&entry, false) {
+            Ok(()) => {
+                // mark as recently used for gc
+                _ = File::options()
+                    .write(true)
+                    .open(entry.join(INTEGRITY_FILE))
+                    .and_then(|f| f.set_modified(SystemTime::now()));
+                Some(entry.join(COMPILED_DIR))
+            }
+            Err(_) => {
+                _ = std::fs::remove_dir_all(&entry);
+                None
+            }
+        }
+    }
+
+    /// Like `get`'s check but also rehashes every file, slow for large models.
+    pub fn verify(&self, key: &CacheKey) -> Result<(), CoreMLError> {
+        check_entry(&self.root.join(key.as_str()), true)
+    }
+
+    /// Copies the compiled model directory at `compiled` into the cache and returns the
+    /// location of the cached copy.
+    pub fn insert(
+        &self,
+        key: &CacheKey,
+        compiled: impl AsRef<Path>,
+    ) -> Result<PathBuf, CoreMLError> {
+        let compiled = compiled.as_ref();
+        if !compiled.is_dir() {
+            return Err(CoreMLError::CacheError(format!(
+                "compiled model {} is not a directory",
+                compiled.display()
+            )));
+        }
+        std::fs::create_dir_all(&self.root).map_err(CoreMLError::IoError)?;
+
+        // stage next to the final location so the rename is atomic
+        let staging = self
+            .root
+            .join(format!(".{}.{}.tmp", key.as_str(), std::process::id()));
+        _ = std::fs::remove_dir_all(&staging);
+        let res = copy_dir_all(compiled, &staging.join(COMPILED_DIR))
+            .and_then(|_| write_integrity(&staging))
+            .and_then(|_| {
+                let entry = self.root.join(key.as_str());
+                _ = std::fs::remove_dir_all(&entry);
+                std::fs::rename(&staging, &entry).map_err(CoreMLError::IoError)
+            });
+        if let Err(err) = res {
+            _ = std::fs::remove_dir_all(&staging);
+            return Err(err);
+        }
+
+        if self.max_size.is_some() {
+            self.gc_except(Some(key))?;
+        }
+        Ok(self.entry_path(key))
+    }
+
+    pub fn remove(&self, key: &CacheKey) -> Result<(), CoreMLError> {
+        match std::fs::remove_dir_all(self.root.join(key.as_str())) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                Err(CoreMLError::IoError(err))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Total size in bytes of all entries.
+    pub fn size(&self) -> Result<u64, CoreMLError> {
+        Ok(self.entries()?.iter().map(|(_, size, _)| size).sum())
+    }
+
+    /// Evicts least recently used entries until the cache fits in the configured max size,
+    /// returns the number of bytes freed.
+    pub fn gc(&self) -> Result<u64, CoreMLError> {
+        self.gc_except(None)
+    }
+
+    fn gc_except(&self, keep: Option<&CacheKey>) -> Result<u64, CoreMLError> {
+        let Some(max_size) = self.max_size else {
+            return Ok(0);
+        };
+        let mut entries = self.entries()?;
+        entries.sort_by_key(|(_, _, used)| *used);
+        let mut total: u64 = entries.iter().map(|(_, size, _)| size).sum();
+        let mut freed = 0;
+        for (path, size, _) in entries {
+            if total <= max_size {
+                break;
+            }
+            if keep.is_some_and(|k| path.file_name().is_some_and(|n| n == k.as_str())) {
+                continue;
+            }
+            std::fs::remove_dir_all(&path).map_err(CoreMLError::IoError)?;
+            total -= size;
+            freed += size;
+        }
+        Ok(freed)
+    }
+
+    /// (entry dir, size, last used) for every entry, staging dirs are skipped.
+    fn entries(&self) -> Result<Vec<(PathBuf, u64, SystemTime)>, CoreMLError> {
+        let dir = match std::fs::read_dir(&self.root) {
+            Ok(dir) => dir,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(CoreMLError::IoError(err)),
+        };
+        let mut entries = vec![];
+        for entry in dir {
+            let entry = entry.map_err(CoreMLError::IoError)?;
+            let path = entry.path();
+            if !path.is_dir() || entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let used = std::fs::metadata(path.join(INTEGRITY_FILE))
+                .and_then(|m| m.modified())
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+            let size = files(&path)?.iter().map(|(_, size)| size).sum();
+            entries.push((path, size, used));
+        }
+        Ok(entries)
+    }
+
+    /// Returns the cached compiled model for `source`, compiling it with `compile` on a miss.
+    /// `compile` returns the location CoreML compiled the model to, which is moved into the cache.
+    pub(crate) fn get_or_compile(
+        &self,
+        source: &Path,
+        opts: &CoreMLModelOptions,
+        compile: impl FnOnce(&Path) -> Result<PathBuf, CoreMLError>,
+    ) -> Result<PathBuf, CoreMLError> {
+        let key = CacheKey::from_path(source, opts)?;
+        if let Some(path) = self.get(&key) {
+            return Ok(path);
+        }
+        let compiled = compile(source)?;
+        let res = self.insert(&key, &compiled);
+        // compiled models land in a temp dir, no need to keep both copies around
+        _ = std::fs::remove_dir_all(&compiled);
+        res
+    }
+}
+
+pub(crate) fn compute_platform_name(platform: &ComputePlatform) -> &'static str {
+    match platform {
+        ComputePlatform::Cpu => "CPU",
+        ComputePlatform::CpuAndANE => "CpuAndAne",
+        ComputePlatform::CpuAndGpu => "CpuAndGpu",
+    }
+}
+
+/// Swift hands out compiled locations as `file://` urls.
+pub(crate) fn path_from_file_url(url: &str) -> PathBuf {
+    let Some(path) = url.strip_prefix("file://") else {
+        return PathBuf::from(url);
+    };
+    let bytes = path.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let Some(b) = std::str::from_utf8(&bytes[i + 1..i + 3])
+                .ok()
+                .and_then(|h| u8::from_str_radix(h, 16).ok())
+            {
+                out.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    let path = String::from_utf8_lossy(&out);
+    PathBuf::from(path.trim_end_matches('/'))
+}
+
+/// sha256 over a file, or over the relative paths and contents of every file in a directory.
+pub(crate) fn content_digest(path: &Path) -> Result<String, CoreMLError> {
+    let mut hasher = Sha256::new();
+    if path.is_dir() {
+        for (rel, _) in files(path)? {
+            hasher.update(rel.to_string_lossy().as_bytes());
+            hasher.update([0]);
+            hash_file(&mut hasher, &path.join(rel))?;
+        }
+    } else {
+        hash_file(&mut hasher, path)?;
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+fn hash_file(hasher: &mut Sha256, path: &Path) -> Result<(), CoreMLError> {
+    let mut file = File::open(path).map_err(CoreMLError::IoError)?;
+    std::io::copy(&mut file, hasher).map_err(CoreMLError::IoError)?;
+    Ok(())
+}
+
+/// All files under `dir` as (relative path, size), sorted by path.
+pub(crate) fn files(dir: &Path) -> Result<Vec<(PathBuf, u64)>, CoreMLError> {
+    fn walk(root: &Path, dir: &Path, out: &mut Vec<(PathBuf, u64)>) -> std::io::Result<()> {
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let meta = entry.metadata()?;
+            let path = entry.path();
+            if meta.is_dir() {
+                walk(root, &path, out)?;
+            } else {
+                let rel = path.strip_prefix(root).unwrap_or(&path).to_path_buf();
+                out.push((rel, meta.len()));
+            }
+        }
+        Ok(())
+    }
+    let mut out = vec![];
+    walk(dir, dir, &mut out).map_err(CoreMLError::IoError)?;
+    out.sort();
+    Ok(out)
+}
+
+pub(crate) fn copy_dir_all(src: &Path, dst: &Path) -> Result<(), CoreMLError> {
+    std::fs::create_dir_all(dst).map_err(CoreMLError::IoError)?;
+    for (rel, _) in files(src)? {
+        let to = dst.join(&rel);
+        if let Some(parent) = to.parent() {
+            std::fs::create_dir_all(parent).map_err(CoreMLError::IoError)?;
+        }
+        std::fs::copy(src.join(&rel), to).map_err(CoreMLError::IoError)?;
+    }
+    Ok(())
+}
+
+fn write_integrity(entry: &Path) -> Result<(), CoreMLError> {
+    let compiled = entry.join(COMPILED_DIR);
+    let mut out = File::create(entry.join(INTEGRITY_FILE)).map_err(CoreMLError::IoError)?;
+    for (rel, size) in files(&compiled)? {
+        let mut hasher = Sha256::new();
+        hash_file(&mut hasher, &compiled.join(&rel))?;
+        writeln!(out, "{:x} {size} {}", hasher.finalize(), rel.display())
+            .map_err(CoreMLError::IoError)?;
+    }
+    out.sync_all().map_err(CoreMLError::IoError)
+}
+
+fn check_entry(entry: &Path, rehash: bool) -> Result<(), CoreMLError> {
+    let compiled = entry.join(COMPILED_DIR);
+    let file = File::open(entry.join(INTEGRITY_FILE)).map_err(CoreMLError::IoError)?;
+    let mut count = 0;
+    for line in BufReader::new(file).lines() {
+        let line = line.map_err(CoreMLError::IoError)?;
+        let mut parts = line.splitn(3, ' ');
+        let (Some(digest), Some(size), Some(rel)) = (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(CoreMLError::CacheError(format!(
+                "malformed integrity line: {line}"
+            )));
+        };
+        let path = compiled.join(rel);
+        let meta = std::fs::metadata(&path).map_err(|_| {
+            CoreMLError::CacheError(format!("missing cached file {}", path.display()))
+        })?;
+        if size.parse::<u64>().ok() != Some(meta.len()) {
+            return Err(CoreMLError::CacheError(format!(
+                "size mismatch for cached file {}",
+                path.display()
+            )));
+        }
+        if rehash {
+            let mut hasher = Sha256::new();
+            hash_file(&mut hasher, &path)?;
+            if format!("{:x}", hasher.finalize()) != digest {
+                return Err(CoreMLError::CacheError(format!(
+                    "digest mismatch for cached file {}",
+                    path.display()
+                )));
+            }
+        }
+        count += 1;
+    }
+    if count == 0 {
+        return Err(CoreMLError::CacheError(format!(
+            "empty cache entry {}",
+            entry.display()
+        )));
+    }
+    Ok(())
+}
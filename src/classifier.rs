@@ -0,0 +1,95 @@
+//! Helpers for classifier models, both CoreML classifiers (`classifierConfiguration`
+//! exports returning a label probability dictionary) and plain exports that
+//! return raw logits.
+
+use crate::{mlarray::MLArray, mlmodel::CoreMLError};
+
+/// Class label of a classifier, CoreML allows either strings or int64s.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Label {
+    String(String),
+    Int64(i64),
+}
+
+impl std::fmt::Display for Label {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Label::String(s) => f.write_str(s),
+            Label::Int64(i) => write!(f, "{i}"),
+        }
+    }
+}
+
+impl From<String> for Label {
+    fn from(value: String) -> Self {
+        Label::String(value)
+    }
+}
+
+impl From<&str> for Label {
+    fn from(value: &str) -> Self {
+        Label::String(value.to_string())
+    }
+}
+
+impl From<i64> for Label {
+    fn from(value: i64) -> Self {
+        Label::Int64(value)
+    }
+}
+
+/// Numerically stable softmax.
+pub fn softmax(logits: &[f32]) -> Vec<f32> {
+    let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let exp: Vec<f32> = logits.iter().map(|l| (l - max).exp()).collect();
+    let sum: f32 = exp.iter().sum();
+    exp.into_iter().map(|e| e / sum).collect()
+}
+
+/// Sorts by descending probability and keeps the first `k`, ties keep their input order.
+pub fn top_k<L>(mut scores: Vec<(L, f64)>, k: usize) -> Vec<(L, f64)> {
+    scores.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    scores.truncate(k);
+    scores
+}
+
+/// Softmax and top-k over a logits output of a model exported without a classifier config.
+///
+/// The output is flattened, so `[1, C]` and `[C]` shaped logits both work. Without `labels`
+/// the class index is used as an `Int64` label.
+pub fn classify_logits(
+    logits: &MLArray,
+    labels: Option<&[Label]>,
+    k: usize,
+) -> Result<Vec<(Label, f64)>, CoreMLError> {
+    let logits: Vec<f32> = match logits {
+        MLArray::Float32Array(array) => array.iter().copied().collect(),
+        MLArray::Float16Array(array) => array.iter().map(|v| v.to_f32()).collect(),
+        _ => {
+            return Err(CoreMLError::UnknownErrorStatic(
+                "logits must be an f32 or f16 array",
+            ))
+        }
+    };
+    if let Some(labels) = labels {
+        if labels.len() != logits.len() {
+            return Err(CoreMLError::BadInputShape(format!(
+                "expected {} logits for the class labels found {}",
+                labels.len(),
+                logits.len()
+            )));
+        }
+    }
+    let scores = softmax(&logits)
+        .into_iter()
+        .enumerate()
+        .map(|(i, p)| {
+            let label = match labels {
+                Some(labels) => labels[i].clone(),
+                None => Label::Int64(i as i64),
+            };
+            (label, p as f64)
+        })
+        .collect();
+    Ok(top_k(scores, k))
+}
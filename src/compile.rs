@@ -0,0 +1,100 @@
+//! Compiling `.mlmodel` files and `.mlpackage` directories into `.mlmodelc` directories
+//! without loading them, e.g. at install time:
+//!
+//! ```no_run
+//! # use coreml_rs::{compile_model, CoreMLModelOptions, CoreMLModelWithState};
+//! let compiled = compile_model("model.mlpackage", "/opt/app/model.mlmodelc").unwrap();
+//! // later, without compiling again
+//! let model = CoreMLModelWithState::new_compiled(&compiled, CoreMLModelOptions::default());
+//! ```
+
+use crate::{cache::copy_dir_all, mlmodel::CoreMLError};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+/// Location of a compiled `.mlmodelc` directory.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CompiledModelPath(PathBuf);
+
+impl CompiledModelPath {
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn into_path_buf(self) -> PathBuf {
+        self.0
+    }
+}
+
+impl AsRef<Path> for CompiledModelPath {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+/// Compiles the model at `src` into the directory `dest`, replacing anything already
+/// there. The model is not loaded.
+pub fn compile_model(
+    src: impl AsRef<Path>,
+    dest: impl AsRef<Path>,
+) -> Result<CompiledModelPath, CoreMLError> {
+    let (src, dest) = (src.as_ref(), dest.as_ref());
+    let compiled = compile_to_temp(src)?;
+    let res = move_dir(&compiled, dest);
+    _ = std::fs::remove_dir_all(&compiled);
+    res.map(|_| CompiledModelPath(dest.to_path_buf()))
+}
+
+/// What the Swift side hands over, the compiled location or why compiling failed.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CompileResult {
+    Error { error: String },
+    Compiled { path: PathBuf },
+}
+
+/// Compiles the model at `src` into a temp directory picked by CoreML.
+pub(crate) fn compile_to_temp(src: &Path) -> Result<PathBuf, CoreMLError> {
+    if !src.exists() {
+        return Err(CoreMLError::CompileError(format!(
+            "model {} does not exist",
+            src.display()
+        )));
+    }
+    let _stage = crate::instrument::stage!("compile");
+    match serde_json::from_str(&crate::ffi::compile_model_at(src.display().to_string())) {
+        Ok(CompileResult::Compiled { path }) => Ok(path),
+        Ok(CompileResult::Error { error }) => Err(CoreMLError::CompileError(format!(
+            "failed to compile {}: {error}",
+            src.display()
+        ))),
+        Err(err) => Err(CoreMLError::CompileError(format!(
+            "invalid compile result: {err}"
+        ))),
+    }
+}
+
+/// Moves `from` to `to`, staging next to `to` so it is replaced in one rename.
+fn move_dir(from: &Path, to: &Path) -> Result<(), CoreMLError> {
+    if let Some(parent) = to.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent).map_err(CoreMLError::IoError)?;
+    }
+    let name = to.file_name().unwrap_or_default().to_string_lossy();
+    let staging = to.with_file_name(format!(".{name}.{}.tmp", std::process::id()));
+    _ = std::fs::remove_dir_all(&staging);
+    // CoreML compiles into the system temp dir, which may be on another file system
+    let res = match std::fs::rename(from, &staging) {
+        Ok(()) => Ok(()),
+        Err(_) => copy_dir_all(from, &staging),
+    }
+    .and_then(|_| {
+        if to.exists() {
+            std::fs::remove_dir_all(to).map_err(CoreMLError::IoError)?;
+        }
+        std::fs::rename(&staging, to).map_err(CoreMLError::IoError)
+    });
+    if res.is_err() {
+        _ = std::fs::remove_dir_all(&staging);
+    }
+    res
+}
@@ -0,0 +1,131 @@
+//! Per-operation compute device placement of a loaded model, from `MLComputePlan`
+//! (macOS 14.4+).
+//!
+//! ```no_run
+//! # use coreml_rs::{computeplan::ComputeDevice, ComputePlatform, CoreMLModelOptions, CoreMLModelWithState};
+//! let mut opts = CoreMLModelOptions::default();
+//! opts.compute_platform = ComputePlatform::CpuAndANE;
+//! let mut model = CoreMLModelWithState::new("model.mlpackage", opts);
+//! model.load().unwrap();
+//! let plan = model.compute_plan().unwrap();
+//! assert!(plan.fraction_on(ComputeDevice::NeuralEngine) > 0.9);
+//! std::fs::write("plan.json", plan.to_json().unwrap()).unwrap();
+//! ```
+
+use crate::mlmodel::CoreMLError;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ComputeDevice {
+    Cpu,
+    Gpu,
+    NeuralEngine,
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OperationPlan {
+    /// ML Program function the operation belongs to, empty for neural networks
+    #[serde(default)]
+    pub function: String,
+    /// Output names of ML Program operations, layer name for neural networks
+    pub name: String,
+    /// e.g. `conv` or `ios17.linear`, layer type for neural networks
+    pub operator: String,
+    /// `None` for operations that don't run on a device, like `const`
+    #[serde(default)]
+    pub preferred: Option<ComputeDevice>,
+    #[serde(default)]
+    pub supported: Vec<ComputeDevice>,
+    /// Relative cost of the operation in `[0, 1]`, only estimated for ML Programs
+    #[serde(default)]
+    pub estimated_cost: Option<f64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ComputePlan {
+    pub operations: Vec<OperationPlan>,
+}
+
+/// What the Swift side hands over, a plan or why there is none.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PlanResult {
+    Error { error: String },
+    Plan(ComputePlan),
+}
+
+impl ComputePlan {
+    pub fn from_json(json: &str) -> Result<Self, CoreMLError> {
+        serde_json::from_str(json)
+            .map_err(|err| CoreMLError::UnknownError(format!("invalid compute plan: {err}")))
+    }
+
+    pub fn to_json(&self) -> Result<String, CoreMLError> {
+        serde_json::to_string_pretty(self).map_err(|err| {
+            CoreMLError::UnknownError(format!("failed to serialize the compute plan: {err}"))
+        })
+    }
+
+    pub(crate) fn from_bridge(json: String) -> Result<Self, CoreMLError> {
+        match serde_json::from_str(&json) {
+            Ok(PlanResult::Plan(plan)) => Ok(plan),
+            Ok(PlanResult::Error { error }) => Err(CoreMLError::UnknownError(format!(
+                "failed to load the compute plan: {error}"
+            ))),
+            Err(err) => Err(CoreMLError::UnknownError(format!(
+                "invalid compute plan: {err}"
+            ))),
+        }
+    }
+
+    /// Operations that run on a device.
+    pub fn placed(&self) -> impl Iterator<Item = &OperationPlan> {
+        self.operations.iter().filter(|op| op.preferred.is_some())
+    }
+
+    /// Number of operations preferring each device.
+    pub fn device_counts(&self) -> BTreeMap<ComputeDevice, usize> {
+        let mut counts = BTreeMap::new();
+        for device in self.placed().filter_map(|op| op.preferred) {
+            *counts.entry(device).or_default() += 1;
+        }
+        counts
+    }
+
+    /// Fraction of the placed operations preferring `device`, 0 without placed operations.
+    pub fn fraction_on(&self, device: ComputeDevice) -> f64 {
+        let total = self.placed().count();
+        if total == 0 {
+            return 0.0;
+        }
+        let on = self
+            .placed()
+            .filter(|op| op.preferred == Some(device))
+            .count();
+        on as f64 / total as f64
+    }
+
+    /// Fraction of the estimated cost spent on `device`, `None` without cost estimates.
+    pub fn cost_fraction_on(&self, device: ComputeDevice) -> Option<f64> {
+        let (mut on, mut total) = (0.0, 0.0);
+        for op in self.placed() {
+            let cost = op.estimated_cost?;
+            total += cost;
+            if op.preferred == Some(device) {
+                on += cost;
+            }
+        }
+        (total > 0.0).then_some(on / total)
+    }
+
+    /// Placed operations preferring another device than `device`, e.g. the CPU fallbacks
+    /// of a model meant to run on the neural engine.
+    pub fn not_on(&self, device: ComputeDevice) -> impl Iterator<Item = &OperationPlan> {
+        self.placed().filter(move |op| op.preferred != Some(device))
+    }
+}
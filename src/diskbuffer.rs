@@ -0,0 +1,297 @@
+//! On-disk format of model buffers moved out of memory by `unload_to_disk`.
+//!
+//! Buffers are stored under `cache_dir/buffers`, named after the sha256 of their contents
+//! so models sharing a `cache_dir` don't overwrite each other, and identical buffers are
+//! written once:
+//!
+//! ```text
+//! <cache_dir>/buffers/<sha256>.<zlib|zst|lz4|raw>
+//! ```
+//!
+//! Every file starts with a header holding the codec, the uncompressed length and the
+//! sha256 of the uncompressed buffer, which is checked when the buffer is read back.
+//!
+//! Uncompressed buffers (`BufferCompression::None`) are memory mapped on reload instead of
+//! read, so CoreML reads the model from the page cache without a copy on the heap.
+//!
+//! Buffer files are never deleted by the crate, since models with identical buffers share
+//! one file. Whoever owns `cache_dir` cleans up `cache_dir/buffers` once no model needs it.
+
+use crate::mlmodel::CoreMLError;
+use memmap2::Mmap;
+use sha2::{Digest, Sha256};
+use std::{
+    fs::File,
+    io::{BufWriter, Read, Write},
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+
+const BUFFERS_DIR: &str = "buffers";
+const MAGIC: &[u8; 6] = b"CMLBUF";
+const VERSION: u8 = 1;
+const HEADER_LEN: usize = MAGIC.len() + 2 + 8 + 32;
+/// lz4 blocks can't expand by more than this, a longer length in the header is corrupt
+const LZ4_MAX_RATIO: usize = 255;
+
+/// How model buffers are compressed on disk.
+///
+/// `None` takes the most space but is memory mapped on reload, keeping the peak memory
+/// use of reloads low.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BufferCompression {
+    None,
+    /// zlib at the given level, 0 to 9
+    Zlib(u32),
+    /// zstd at the given level, 1 to 22, 0 for zstd's default
+    Zstd(i32),
+    /// lz4 block compression, the fastest to reload
+    Lz4,
+}
+
+impl Default for BufferCompression {
+    fn default() -> Self {
+        BufferCompression::Zlib(9)
+    }
+}
+
+impl BufferCompression {
+    fn id(&self) -> u8 {
+        match self {
+            BufferCompression::None => 0,
+            BufferCompression::Zlib(_) => 1,
+            BufferCompression::Zstd(_) => 2,
+            BufferCompression::Lz4 => 3,
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            BufferCompression::None => "raw",
+            BufferCompression::Zlib(_) => "zlib",
+            BufferCompression::Zstd(_) => "zst",
+            BufferCompression::Lz4 => "lz4",
+        }
+    }
+
+    fn from_id(id: u8) -> Option<Self> {
+        // the level is only needed to compress
+        match id {
+            0 => Some(BufferCompression::None),
+            1 => Some(BufferCompression::Zlib(0)),
+            2 => Some(BufferCompression::Zstd(0)),
+            3 => Some(BufferCompression::Lz4),
+            _ => None,
+        }
+    }
+}
+
+struct Header {
+    compression: BufferCompression,
+    len: u64,
+    digest: [u8; 32],
+}
+
+impl Header {
+    fn to_bytes(&self) -> [u8; HEADER_LEN] {
+        let mut out = [0; HEADER_LEN];
+        out[..6].copy_from_slice(MAGIC);
+        out[6] = VERSION;
+        out[7] = self.compression.id();
+        out[8..16].copy_from_slice(&self.len.to_le_bytes());
+        out[16..].copy_from_slice(&self.digest);
+        out
+    }
+
+    fn from_bytes(buf: &[u8]) -> Result<Self, CoreMLError> {
+        if buf.len() < HEADER_LEN || &buf[..6] != MAGIC {
+            return Err(CoreMLError::CacheError(
+                "not a model buffer file".to_string(),
+            ));
+        }
+        if buf[6] != VERSION {
+            return Err(CoreMLError::CacheError(format!(
+                "unsupported model buffer version {}",
+                buf[6]
+            )));
+        }
+        let compression = BufferCompression::from_id(buf[7]).ok_or_else(|| {
+            CoreMLError::CacheError(format!("unknown model buffer compression {}", buf[7]))
+        })?;
+        Ok(Self {
+            compression,
+            len: u64::from_le_bytes(buf[8..16].try_into().unwrap()),
+            digest: buf[16..HEADER_LEN].try_into().unwrap(),
+        })
+    }
+
+    fn read(path: &Path) -> Result<Self, CoreMLError> {
+        let mut buf = [0; HEADER_LEN];
+        File::open(path)
+            .and_then(|mut file| file.read_exact(&mut buf))
+            .map_err(CoreMLError::IoError)?;
+        Self::from_bytes(&buf)
+    }
+}
+
+/// Writes `buf` under `cache_dir/buffers` and returns the path written to. A file with the
+/// same contents and codec is reused if its payload still matches its checksum, otherwise
+/// it is rewritten.
+pub(crate) fn write_buffer(
+    cache_dir: &Path,
+    buf: &[u8],
+    compression: BufferCompression,
+) -> Result<PathBuf, CoreMLError> {
+    let cache_dir = if cache_dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        cache_dir
+    };
+    let header = Header {
+        compression,
+        len: buf.len() as u64,
+        digest: Sha256::digest(buf).into(),
+    };
+    let dir = cache_dir.join(BUFFERS_DIR);
+    std::fs::create_dir_all(&dir).map_err(CoreMLError::IoError)?;
+    let digest: String = header.digest.iter().map(|b| format!("{b:02x}")).collect();
+    let path = dir.join(format!("{digest}.{}", compression.extension()));
+    if Header::read(&path).is_ok_and(|existing| existing.digest == header.digest)
+        && is_intact(&path)
+    {
+        return Ok(path);
+    }
+
+    // written next to the destination and renamed, so readers never see a partial file
+    let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+    let res = write_file(&tmp, &header, buf)
+        .and_then(|()| std::fs::rename(&tmp, &path).map_err(CoreMLError::IoError));
+    if res.is_err() {
+        _ = std::fs::remove_file(&tmp);
+    }
+    res.map(|()| path)
+}
+
+/// Whether the buffer at `path` reads back and matches its checksum.
+fn is_intact(path: &Path) -> bool {
+    match map_buffer(path) {
+        Ok(Some(_)) => true,
+        Ok(None) => read_buffer(path).is_ok(),
+        Err(_) => false,
+    }
+}
+
+fn write_file(path: &Path, header: &Header, buf: &[u8]) -> Result<(), CoreMLError> {
+    let mut file = BufWriter::new(File::create(path).map_err(CoreMLError::IoError)?);
+    file.write_all(&header.to_bytes())
+        .map_err(CoreMLError::IoError)?;
+    match header.compression {
+        BufferCompression::None => file.write_all(buf).map_err(CoreMLError::IoError)?,
+        BufferCompression::Zlib(level) => {
+            let mut encoder =
+                flate2::write::ZlibEncoder::new(&mut file, flate2::Compression::new(level.min(9)));
+            encoder.write_all(buf).map_err(CoreMLError::IoError)?;
+            encoder.finish().map_err(CoreMLError::IoError)?;
+        }
+        BufferCompression::Zstd(level) => {
+            zstd::stream::copy_encode(buf, &mut file, level).map_err(CoreMLError::IoError)?
+        }
+        BufferCompression::Lz4 => file
+            .write_all(&lz4_flex::block::compress(buf))
+            .map_err(CoreMLError::IoError)?,
+    }
+    file.into_inner()
+        .map_err(|err| CoreMLError::IoError(err.into_error()))?
+        .sync_all()
+        .map_err(CoreMLError::IoError)
+}
+
+/// An uncompressed model buffer mapped from disk, unmapped on drop.
+pub struct MappedBuffer {
+    map: Mmap,
+}
+
+impl Deref for MappedBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.map[HEADER_LEN..]
+    }
+}
+
+impl std::fmt::Debug for MappedBuffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MappedBuffer")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+/// Maps a buffer written by `write_buffer` if it is uncompressed, `None` if it needs to be
+/// read with `read_buffer`. Fails if it doesn't match its checksum.
+pub(crate) fn map_buffer(path: &Path) -> Result<Option<MappedBuffer>, CoreMLError> {
+    let header = Header::read(path)?;
+    if header.compression != BufferCompression::None {
+        return Ok(None);
+    }
+    let file = File::open(path).map_err(CoreMLError::IoError)?;
+    // SAFETY: buffer files are only ever replaced by renaming a new file over them, never
+    // modified in place, so the mapped contents can't change under the model
+    let map = unsafe { Mmap::map(&file) }.map_err(CoreMLError::IoError)?;
+    let buf = MappedBuffer { map };
+    if buf.len() as u64 != header.len || Sha256::digest(&*buf)[..] != header.digest {
+        return Err(checksum_mismatch(path));
+    }
+    Ok(Some(buf))
+}
+
+/// Reads a buffer written by `write_buffer`, failing if it doesn't match its checksum.
+pub(crate) fn read_buffer(path: &Path) -> Result<Vec<u8>, CoreMLError> {
+    let file = std::fs::read(path).map_err(CoreMLError::IoError)?;
+    let header = Header::from_bytes(&file)?;
+    let payload = &file[HEADER_LEN..];
+    let len = header.len as usize;
+    let buf = match header.compression {
+        BufferCompression::None => payload.to_vec(),
+        BufferCompression::Zlib(_) => {
+            decompress(flate2::read::ZlibDecoder::new(payload), header.len, path)?
+        }
+        BufferCompression::Zstd(_) => decompress(
+            zstd::stream::read::Decoder::new(payload).map_err(CoreMLError::IoError)?,
+            header.len,
+            path,
+        )?,
+        BufferCompression::Lz4 => {
+            if len > payload.len().saturating_mul(LZ4_MAX_RATIO) {
+                return Err(bad_length(path));
+            }
+            lz4_flex::block::decompress(payload, len).map_err(CoreMLError::Lz4DecompressError)?
+        }
+    };
+    if buf.len() != len || Sha256::digest(&buf)[..] != header.digest {
+        return Err(checksum_mismatch(path));
+    }
+    Ok(buf)
+}
+
+/// Streams at most one byte more than `len` out of `decoder`, so a corrupt length in the
+/// header can't make us allocate up front or inflate past it.
+fn decompress(decoder: impl Read, len: u64, path: &Path) -> Result<Vec<u8>, CoreMLError> {
+    let mut buf = vec![];
+    decoder
+        .take(len.saturating_add(1))
+        .read_to_end(&mut buf)
+        .map_err(CoreMLError::IoError)?;
+    if buf.len() as u64 != len {
+        return Err(bad_length(path));
+    }
+    Ok(buf)
+}
+
+fn bad_length(path: &Path) -> CoreMLError {
+    CoreMLError::CacheError(format!("length mismatch in {}", path.display()))
+}
+
+fn checksum_mismatch(path: &Path) -> CoreMLError {
+    CoreMLError::CacheError(format!("checksum mismatch in {}", path.display()))
+}
@@ -0,0 +1,357 @@
+//! Golden-output regression checks: record a model's outputs for a set of inputs into an
+//! `.npz` fixture once, then compare the outputs of later runs, e.g. after converting the
+//! model again or switching compute platforms, against it.
+//!
+//! ```no_run
+//! # use coreml_rs::{golden::{GoldenFixture, Tolerance, Tolerances}, CoreMLModelOptions, CoreMLModelWithState};
+//! # let inputs = vec![];
+//! let mut model = CoreMLModelWithState::new("model.mlpackage", CoreMLModelOptions::default());
+//! GoldenFixture::record(&mut model, inputs).unwrap().save("golden.npz").unwrap();
+//!
+//! let fixture = GoldenFixture::open("golden.npz").unwrap();
+//! let tolerances = Tolerances::default().output(
+//!     "logits",
+//!     Tolerance { max_abs: Some(0.05), ..Default::default() },
+//! );
+//! let report = fixture.check(&mut model, &tolerances).unwrap();
+//! assert!(report.passed(), "{report}");
+//! ```
+//!
+//! Fixtures hold one entry per array, so they can be produced or inspected with numpy:
+//!
+//! ```text
+//! inputs/<case>/<name>.npy
+//! outputs/<case>/<name>.npy
+//! ```
+
+use crate::{metrics::Metrics, mlarray::MLArray, mlmodel::CoreMLError, npy, CoreMLModelWithState};
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{Read, Seek, Write},
+    path::Path,
+};
+
+const INPUTS: &str = "inputs";
+const OUTPUTS: &str = "outputs";
+
+/// The inputs of one prediction and the outputs they produced.
+#[derive(Debug, Clone, Default)]
+pub struct GoldenCase {
+    pub inputs: BTreeMap<String, MLArray>,
+    pub outputs: BTreeMap<String, MLArray>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct GoldenFixture {
+    pub cases: Vec<GoldenCase>,
+}
+
+impl GoldenFixture {
+    /// Runs `model` on every set of inputs and records the outputs.
+    pub fn record(
+        model: &mut CoreMLModelWithState,
+        cases: impl IntoIterator<Item = Vec<(String, MLArray)>>,
+    ) -> Result<Self, CoreMLError> {
+        let cases = cases
+            .into_iter()
+            .map(|inputs| {
+                let inputs: BTreeMap<_, _> = inputs.into_iter().collect();
+                let outputs = predict(model, &inputs)?.into_iter().collect();
+                Ok(GoldenCase { inputs, outputs })
+            })
+            .collect::<Result<_, CoreMLError>>()?;
+        Ok(Self { cases })
+    }
+
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, CoreMLError> {
+        let file = std::fs::File::open(path).map_err(CoreMLError::IoError)?;
+        Self::read(std::io::BufReader::new(file))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), CoreMLError> {
+        let file = std::fs::File::create(path).map_err(CoreMLError::IoError)?;
+        self.write(std::io::BufWriter::new(file))
+    }
+
+    pub fn read(reader: impl Read + Seek) -> Result<Self, CoreMLError> {
+        let entries = npy::read_npz(reader)?;
+        let count = entries.len();
+        let mut cases: Vec<GoldenCase> = vec![];
+        for (entry, array) in entries {
+            let bad_entry = || CoreMLError::BadNpy(format!("unexpected fixture entry {entry}"));
+            let mut parts = entry.splitn(3, '/');
+            let (Some(kind), Some(case), Some(name)) = (parts.next(), parts.next(), parts.next())
+            else {
+                return Err(bad_entry());
+            };
+            // every case has an entry, so a larger index can't be valid
+            let case = case
+                .parse::<usize>()
+                .ok()
+                .filter(|case| *case < count)
+                .ok_or_else(bad_entry)?;
+            if cases.len() <= case {
+                let len = case.checked_add(1).ok_or_else(bad_entry)?;
+                cases.resize_with(len, GoldenCase::default);
+            }
+            let arrays = match kind {
+                INPUTS => &mut cases[case].inputs,
+                OUTPUTS => &mut cases[case].outputs,
+                _ => return Err(bad_entry()),
+            };
+            arrays.insert(name.to_string(), array);
+        }
+        Ok(Self { cases })
+    }
+
+    pub fn write(&self, writer: impl Write + Seek) -> Result<(), CoreMLError> {
+        let entries: Vec<_> = self
+            .cases
+            .iter()
+            .enumerate()
+            .flat_map(|(i, case)| {
+                let inputs = case.inputs.iter().map(move |(n, a)| (INPUTS, i, n, a));
+                let outputs = case.outputs.iter().map(move |(n, a)| (OUTPUTS, i, n, a));
+                inputs.chain(outputs)
+            })
+            .map(|(kind, i, name, array)| (format!("{kind}/{i}/{name}"), array))
+            .collect();
+        npy::write_npz(
+            writer,
+            entries.iter().map(|(name, array)| (name.as_str(), *array)),
+        )
+    }
+
+    /// Runs `model` on the recorded inputs and compares its outputs to the recorded ones.
+    pub fn check(
+        &self,
+        model: &mut CoreMLModelWithState,
+        tolerances: &Tolerances,
+    ) -> Result<GoldenReport, CoreMLError> {
+        let outputs = self
+            .cases
+            .iter()
+            .map(|case| predict(model, &case.inputs))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(self.compare(&outputs, tolerances))
+    }
+
+    /// Compares `outputs`, one map per recorded case, to the recorded outputs.
+    pub fn compare(
+        &self,
+        outputs: &[HashMap<String, MLArray>],
+        tolerances: &Tolerances,
+    ) -> GoldenReport {
+        let mut report = GoldenReport::default();
+        for (i, case) in self.cases.iter().enumerate() {
+            for (name, expected) in &case.outputs {
+                let actual = outputs.get(i).and_then(|outputs| outputs.get(name));
+                report.outputs.push(compare_output(
+                    i,
+                    name,
+                    expected,
+                    actual,
+                    tolerances.get(name),
+                ));
+            }
+        }
+        report
+    }
+}
+
+fn predict(
+    model: &mut CoreMLModelWithState,
+    inputs: &BTreeMap<String, MLArray>,
+) -> Result<HashMap<String, MLArray>, CoreMLError> {
+    for (name, input) in inputs {
+        model.add_input(name, input.clone())?;
+    }
+    Ok(model.predict()?.outputs)
+}
+
+/// Limits an output has to stay within, `None` skips the check.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tolerance {
+    pub max_mae: Option<f64>,
+    pub max_abs: Option<f64>,
+    pub max_rmse: Option<f64>,
+    pub min_cosine: Option<f64>,
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Self {
+            max_mae: Some(1e-3),
+            max_abs: None,
+            max_rmse: None,
+            min_cosine: Some(0.999),
+        }
+    }
+}
+
+/// A tolerance for every output, with overrides by output name.
+#[derive(Debug, Clone, Default)]
+pub struct Tolerances {
+    pub default: Tolerance,
+    pub outputs: HashMap<String, Tolerance>,
+}
+
+impl Tolerances {
+    pub fn new(default: Tolerance) -> Self {
+        Self {
+            default,
+            outputs: HashMap::new(),
+        }
+    }
+
+    pub fn output(mut self, name: impl Into<String>, tolerance: Tolerance) -> Self {
+        self.outputs.insert(name.into(), tolerance);
+        self
+    }
+
+    pub fn get(&self, name: &str) -> &Tolerance {
+        self.outputs.get(name).unwrap_or(&self.default)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mismatch {
+    Missing,
+    Shape {
+        expected: Vec<usize>,
+        actual: Vec<usize>,
+    },
+    /// `metric` is past `limit`, a maximum or for `cosine` a minimum
+    Exceeds {
+        metric: &'static str,
+        value: f64,
+        limit: f64,
+    },
+}
+
+impl std::fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Mismatch::Missing => write!(f, "missing"),
+            Mismatch::Shape { expected, actual } => {
+                write!(f, "shape {actual:?}, expected {expected:?}")
+            }
+            Mismatch::Exceeds {
+                metric,
+                value,
+                limit,
+            } => write!(f, "{metric} {value:.6} exceeds {limit}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputReport {
+    pub case: usize,
+    pub name: String,
+    /// `None` if the output is missing, empty or has another shape
+    pub metrics: Option<Metrics>,
+    pub mismatches: Vec<Mismatch>,
+}
+
+impl OutputReport {
+    pub fn passed(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GoldenReport {
+    pub outputs: Vec<OutputReport>,
+}
+
+impl GoldenReport {
+    pub fn passed(&self) -> bool {
+        self.outputs.iter().all(OutputReport::passed)
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &OutputReport> {
+        self.outputs.iter().filter(|output| !output.passed())
+    }
+}
+
+impl std::fmt::Display for GoldenReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let failed = self.failures().count();
+        writeln!(
+            f,
+            "{} of {} outputs passed",
+            self.outputs.len() - failed,
+            self.outputs.len()
+        )?;
+        for output in self.failures() {
+            let mismatches: Vec<_> = output.mismatches.iter().map(|m| m.to_string()).collect();
+            writeln!(
+                f,
+                "  case {} {}: {}",
+                output.case,
+                output.name,
+                mismatches.join(", ")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn compare_output(
+    case: usize,
+    name: &str,
+    expected: &MLArray,
+    actual: Option<&MLArray>,
+    tolerance: &Tolerance,
+) -> OutputReport {
+    let mut report = OutputReport {
+        case,
+        name: name.to_string(),
+        metrics: None,
+        mismatches: vec![],
+    };
+    let Some(actual) = actual else {
+        report.mismatches.push(Mismatch::Missing);
+        return report;
+    };
+    if actual.shape() != expected.shape() {
+        report.mismatches.push(Mismatch::Shape {
+            expected: expected.shape().to_vec(),
+            actual: actual.shape().to_vec(),
+        });
+        return report;
+    }
+
+    // nothing to compare in empty outputs
+    let Ok(metrics) = Metrics::between(actual, expected) else {
+        return report;
+    };
+    let checks = [
+        ("mae", metrics.mae, tolerance.max_mae),
+        ("max_abs", metrics.max_abs, tolerance.max_abs),
+        ("rmse", metrics.rmse, tolerance.max_rmse),
+    ];
+    for (metric, value, limit) in checks {
+        // NaN never passes
+        if let Some(limit) = limit.filter(|limit| value.is_nan() || value > *limit) {
+            report.mismatches.push(Mismatch::Exceeds {
+                metric,
+                value,
+                limit,
+            });
+        }
+    }
+    if let Some(limit) = tolerance
+        .min_cosine
+        .filter(|limit| metrics.cosine.is_nan() || metrics.cosine < *limit)
+    {
+        report.mismatches.push(Mismatch::Exceeds {
+            metric: "cosine",
+            value: metrics.cosine,
+            limit,
+        });
+    }
+    report.metrics = Some(metrics);
+    report
+}
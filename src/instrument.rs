@@ -0,0 +1,68 @@
+//! Optional instrumentation of compile, load, bind, predict and output copies.
+//!
+//! With the `tracing` feature every stage runs in a `DEBUG` span under the `coreml_rs`
+//! target, and emits an event with its duration when it ends. Data handed across the
+//! bridge is reported as events with a `bytes` field. Without the feature all of this
+//! compiles to nothing.
+
+#[cfg(feature = "tracing")]
+use std::time::Instant;
+
+/// A running stage, ends when dropped.
+#[must_use]
+pub(crate) struct Stage {
+    #[cfg(feature = "tracing")]
+    span: tracing::span::EnteredSpan,
+    #[cfg(feature = "tracing")]
+    start: Instant,
+}
+
+impl Stage {
+    #[cfg(feature = "tracing")]
+    pub(crate) fn enter(span: tracing::Span) -> Self {
+        Self {
+            span: span.entered(),
+            start: Instant::now(),
+        }
+    }
+}
+
+#[cfg(feature = "tracing")]
+impl Drop for Stage {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed();
+        let stage = self.span.metadata().map_or("", |m| m.name());
+        tracing::debug!(
+            target: "coreml_rs",
+            stage,
+            elapsed_us = elapsed.as_micros() as u64,
+            "{stage} took {elapsed:?}"
+        );
+    }
+}
+
+/// Starts a stage named by a string literal, e.g. `let _stage = stage!("predict");`.
+#[cfg(feature = "tracing")]
+macro_rules! stage {
+    ($name:literal) => {
+        $crate::instrument::Stage::enter(tracing::debug_span!(target: "coreml_rs", $name))
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! stage {
+    ($name:literal) => {
+        $crate::instrument::Stage {}
+    };
+}
+
+pub(crate) use stage;
+
+/// Records `bytes` of feature `name` passed across the bridge, `direction` is `input` or `output`.
+#[inline]
+pub(crate) fn bytes(direction: &'static str, name: &str, bytes: usize) {
+    #[cfg(feature = "tracing")]
+    tracing::debug!(target: "coreml_rs", direction, feature = name, bytes);
+    #[cfg(not(feature = "tracing"))]
+    let _ = (direction, name, bytes);
+}
@@ -1,10 +1,32 @@
+pub mod archive;
+pub mod autoload;
+pub mod batch;
+pub mod cache;
+pub mod classifier;
+pub mod compile;
+pub mod computeplan;
+pub mod diskbuffer;
+pub mod golden;
+pub mod lifecycle;
+pub mod metrics;
 pub mod mlarray;
 pub mod mlbatchmodel;
 pub mod mlmodel;
+pub mod mlmodelc;
+pub mod mlpackage;
+pub mod npy;
+pub mod postprocess;
+pub mod preprocess;
+pub mod scheduler;
+pub mod spec;
+pub mod synthetic;
+pub mod weights;
 
+mod instrument;
 mod swift;
 
 // re-exports
+pub use compile::{compile_model, CompiledModelPath};
 pub use ffi::ComputePlatform;
 pub use mlmodel::{CoreMLModelOptions, CoreMLModelWithState};
 
@@ -0,0 +1,327 @@
+//! Load and unload lifecycle shared by single and batch models.
+//!
+//! [`ModelLifecycle`] owns the options, the [`CoreMLModelLoader`] a model is (re)loaded
+//! from and the current [`ModelState`]. It is parameterized by the model kind,
+//! [`CoreMLModelWithState`](crate::CoreMLModelWithState) and
+//! [`CoreMLBatchModelWithState`](crate::mlbatchmodel::CoreMLBatchModelWithState) are
+//! aliases of it, so both get the same load, unload and error behaviour:
+//!
+//! ```text
+//! Unloaded --load--> Loading --> Loaded --unload--> Unloaded
+//!                            \-> Failed --load--> Loading ...
+//! ```
+
+use crate::{
+    cache::{path_from_file_url, CompiledModelCache},
+    compile::compile_to_temp,
+    diskbuffer::{map_buffer, read_buffer, write_buffer, MappedBuffer},
+    instrument::stage,
+    mlmodel::{CoreMLError, CoreMLModelInfo, CoreMLModelLoader},
+    mlmodelc::MLModelC,
+    synthetic::Fill,
+    CoreMLModelOptions,
+};
+use std::{ops::Deref, path::Path};
+
+/// A kind of model the lifecycle can create and load.
+pub trait ModelKind: Sized {
+    /// Model for an uncompiled `.mlmodel`/`.mlpackage`, or a compiled `.mlmodelc` at `path`.
+    fn from_path(path: String, info: &CoreMLModelInfo, compiled: bool) -> Self;
+    /// Model for the bytes of an `.mlmodel` file.
+    fn from_buffer(buf: Vec<u8>, info: &CoreMLModelInfo) -> Self;
+    /// Model for the bytes of an `.mlmodel` file mapped from disk, the mapping has to live
+    /// as long as the model. Copies the bytes into a buffer by default.
+    fn from_mapped(buf: MappedBuffer, info: &CoreMLModelInfo) -> Self {
+        Self::from_buffer(buf.to_vec(), info)
+    }
+    /// Loads the model into memory, false if CoreML failed to.
+    fn load(&mut self) -> bool;
+    /// Location of the compiled model, a `file://` url.
+    fn compiled_path(&self) -> Option<String>;
+    /// Runs `n` predictions on inputs synthesized with `fill`, a no-op by default.
+    fn warm_up(&mut self, n: usize, fill: Fill) -> Result<(), CoreMLError> {
+        _ = (n, fill);
+        Ok(())
+    }
+}
+
+/// Model bytes CoreML reads from, owned by the model kind so they are freed when it is
+/// dropped on unload.
+#[derive(Debug)]
+pub(crate) enum AssetBuffer {
+    Owned(Box<[u8]>),
+    Mapped(MappedBuffer),
+}
+
+impl Deref for AssetBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            AssetBuffer::Owned(buf) => buf,
+            AssetBuffer::Mapped(buf) => buf,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ModelState<M> {
+    Unloaded,
+    /// While `load` runs, only observable afterwards if it panicked
+    Loading,
+    Loaded(M),
+    /// The last `load` failed with the given reason, loading can be retried
+    Failed(String),
+}
+
+#[derive(Debug)]
+pub struct ModelLifecycle<M> {
+    info: CoreMLModelInfo,
+    loader: CoreMLModelLoader,
+    state: ModelState<M>,
+}
+
+impl<M: ModelKind> ModelLifecycle<M> {
+    pub fn with_loader(opts: CoreMLModelOptions, loader: CoreMLModelLoader) -> Self {
+        Self {
+            info: CoreMLModelInfo { opts },
+            loader,
+            state: ModelState::Unloaded,
+        }
+    }
+
+    pub fn new(path: impl AsRef<Path>, opts: CoreMLModelOptions) -> Self {
+        Self::with_loader(
+            opts,
+            CoreMLModelLoader::ModelPath(path.as_ref().to_path_buf()),
+        )
+    }
+
+    pub fn new_compiled(path: impl AsRef<Path>, opts: CoreMLModelOptions) -> Self {
+        Self::with_loader(
+            opts,
+            CoreMLModelLoader::CompiledPath(path.as_ref().to_path_buf()),
+        )
+    }
+
+    /// Like `new` but the compiled model is kept in a content-addressed cache under
+    /// `cache_dir`, so it survives the OS purging CoreML's temp compiled models.
+    pub fn new_cached(path: impl AsRef<Path>, opts: CoreMLModelOptions) -> Self {
+        Self::with_loader(
+            opts,
+            CoreMLModelLoader::CachedPath(path.as_ref().to_path_buf()),
+        )
+    }
+
+    pub fn from_buf(buf: Vec<u8>, opts: CoreMLModelOptions) -> Self {
+        Self::with_loader(opts, CoreMLModelLoader::Buffer(buf))
+    }
+
+    /// Model from a zip archive of an `.mlpackage` on disk, extracted under `cache_dir` on load
+    pub fn from_archive(path: impl AsRef<Path>, opts: CoreMLModelOptions) -> Self {
+        Self::with_loader(
+            opts,
+            CoreMLModelLoader::Archive(
+                crate::archive::ArchiveSource::Path(path.as_ref().to_path_buf()),
+                None,
+            ),
+        )
+    }
+
+    /// Model from a zip archive of an `.mlpackage` held in memory, extracted under `cache_dir` on load
+    pub fn from_archive_buf(buf: Vec<u8>, opts: CoreMLModelOptions) -> Self {
+        Self::with_loader(
+            opts,
+            CoreMLModelLoader::Archive(crate::archive::ArchiveSource::Buffer(buf), None),
+        )
+    }
+
+    pub fn info(&self) -> &CoreMLModelInfo {
+        &self.info
+    }
+
+    pub fn loader(&self) -> &CoreMLModelLoader {
+        &self.loader
+    }
+
+    pub fn state(&self) -> &ModelState<M> {
+        &self.state
+    }
+
+    pub fn is_loaded(&self) -> bool {
+        matches!(self.state, ModelState::Loaded(_))
+    }
+
+    pub fn model(&self) -> Result<&M, CoreMLError> {
+        match &self.state {
+            ModelState::Loaded(model) => Ok(model),
+            _ => Err(CoreMLError::ModelNotLoaded),
+        }
+    }
+
+    pub fn model_mut(&mut self) -> Result<&mut M, CoreMLError> {
+        match &mut self.state {
+            ModelState::Loaded(model) => Ok(model),
+            _ => Err(CoreMLError::ModelNotLoaded),
+        }
+    }
+
+    /// Loads the model, a no-op if it already is, and warms it up if the `warm_up` option
+    /// is set. On failure the model is left in `ModelState::Failed` and loading can be
+    /// retried.
+    pub fn load(&mut self) -> Result<(), CoreMLError> {
+        if self.is_loaded() {
+            return Ok(());
+        }
+        let _stage = stage!("load");
+        self.state = ModelState::Loading;
+        let (warm_up, fill) = (self.info.opts.warm_up, self.info.opts.warm_up_fill);
+        let loaded = self.load_model().and_then(|mut model| {
+            if warm_up > 0 {
+                model.warm_up(warm_up, fill).map_err(|err| {
+                    CoreMLError::FailedToLoad(format!("failed to warm up the model: {err}"))
+                })?;
+            }
+            Ok(model)
+        });
+        match loaded {
+            Ok(model) => {
+                self.state = ModelState::Loaded(model);
+                Ok(())
+            }
+            Err(err) => {
+                self.state = ModelState::Failed(err.to_string());
+                Err(err)
+            }
+        }
+    }
+
+    /// Loads the model if it is not loaded yet and returns it.
+    pub fn ensure_loaded(&mut self) -> Result<&mut M, CoreMLError> {
+        self.load()?;
+        self.model_mut()
+    }
+
+    fn load_model(&mut self) -> Result<M, CoreMLError> {
+        let info = &self.info;
+        match &mut self.loader {
+            CoreMLModelLoader::Archive(source, extracted) => {
+                let package = source.extract(&info.opts.cache_dir).map_err(|err| {
+                    CoreMLError::FailedToLoad(format!("failed to extract the model archive: {err}"))
+                })?;
+                let model = load(
+                    M::from_path(package.path().display().to_string(), info, false),
+                    "archive does not contain a valid mlpackage",
+                )?;
+                *extracted = Some(package);
+                Ok(model)
+            }
+            CoreMLModelLoader::CachedPath(path) => {
+                let cache = CompiledModelCache::from_options(&info.opts);
+                let compiled = cache
+                    .get_or_compile(path, &info.opts, compile_to_temp)
+                    .map_err(|err| {
+                        CoreMLError::FailedToLoad(format!(
+                            "failed to load the model from the compiled cache: {err}"
+                        ))
+                    })?;
+                load(
+                    M::from_path(compiled.display().to_string(), info, true),
+                    "cached compiled model is not valid",
+                )
+                .inspect_err(|_| {
+                    // drop the entry so the next load recompiles
+                    _ = std::fs::remove_dir_all(compiled.parent().unwrap_or(&compiled));
+                })
+            }
+            CoreMLModelLoader::ModelPath(path) => load(
+                M::from_path(path.display().to_string(), info, false),
+                "model path not valid",
+            ),
+            CoreMLModelLoader::CompiledPath(path) => load(
+                M::from_path(path.display().to_string(), info, true),
+                "compiled model cache got purged",
+            )
+            .map_err(|err| {
+                // only explain the failure, CoreML stays the judge of what loads
+                match MLModelC::open(&*path).and_then(|compiled| compiled.validate()) {
+                    Ok(()) => err,
+                    Err(invalid) => CoreMLError::FailedToLoad(format!("{err}; {invalid}")),
+                }
+            }),
+            CoreMLModelLoader::Buffer(buf) => load(
+                M::from_buffer(buf.clone(), info),
+                "likely not a CoreML mlmodel file",
+            ),
+            CoreMLModelLoader::BufferToDisk(path) => {
+                let failed = |err: CoreMLError| {
+                    CoreMLError::FailedToLoad(format!(
+                        "failed to load the model from cached buffer path: {err}"
+                    ))
+                };
+                let model = match map_buffer(path).map_err(failed)? {
+                    Some(buf) => M::from_mapped(buf, info),
+                    None => M::from_buffer(read_buffer(path).map_err(failed)?, info),
+                };
+                load(model, "likely not a CoreML mlmodel file")
+            }
+        }
+    }
+
+    /// Drops the loaded model, keeping what is needed to load it again.
+    ///
+    /// Models loaded from a path are reloaded from the location CoreML compiled them to,
+    /// extracted archives are removed and extracted again on the next load.
+    pub fn unload(&mut self) {
+        let ModelState::Loaded(model) = std::mem::replace(&mut self.state, ModelState::Unloaded)
+        else {
+            return;
+        };
+        let compiled = model.compiled_path().map(|url| path_from_file_url(&url));
+        drop(model);
+        match &mut self.loader {
+            CoreMLModelLoader::Archive(_, extracted) => {
+                // compiled from the extracted package, so neither outlives the model
+                if let Some(compiled) = compiled {
+                    _ = std::fs::remove_dir_all(compiled);
+                }
+                *extracted = None;
+            }
+            CoreMLModelLoader::ModelPath(_) => {
+                if let Some(compiled) = compiled {
+                    self.loader = CoreMLModelLoader::CompiledPath(compiled);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Unloads the model and moves a model buffer to the disk, compressed with
+    /// `buffer_compression` under `cache_dir/buffers`. If writing the buffer fails the
+    /// model stays loaded. The file outlives the model, see [`crate::diskbuffer`] for who
+    /// cleans it up.
+    pub fn unload_to_disk(&mut self) -> Result<(), CoreMLError> {
+        if let CoreMLModelLoader::Buffer(buf) = &self.loader {
+            let opts = &self.info.opts;
+            let path =
+                write_buffer(&opts.cache_dir, buf, opts.buffer_compression).map_err(|err| {
+                    CoreMLError::FailedToLoad(format!("failed to write the model buffer: {err}"))
+                })?;
+            self.unload();
+            self.loader = CoreMLModelLoader::BufferToDisk(path);
+        } else {
+            self.unload();
+        }
+        Ok(())
+    }
+}
+
+fn load<M: ModelKind>(mut model: M, reason: &str) -> Result<M, CoreMLError> {
+    if model.load() {
+        Ok(model)
+    } else {
+        Err(CoreMLError::FailedToLoad(format!(
+            "Failed to load model; {reason}"
+        )))
+    }
+}
@@ -0,0 +1,145 @@
+//! Error metrics between two arrays, e.g. a model output and its expected value.
+//!
+//! Arrays of any element type can be compared, values are promoted to `f64` first, so an
+//! f16 output can be checked against an f32 reference. Both arrays need the same shape,
+//! and every metric but [`allclose`] fails for empty arrays instead of returning NaN.
+//! `rhs` is the reference where it matters, for [`relative_error`] and [`allclose`].
+
+use crate::{mlarray::MLArray, mlmodel::CoreMLError};
+
+/// Every metric that doesn't need extra parameters, computed in one pass.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Metrics {
+    pub mae: f64,
+    pub mse: f64,
+    pub rmse: f64,
+    pub max_abs: f64,
+    pub relative: f64,
+    pub cosine: f64,
+}
+
+impl Metrics {
+    pub fn between(lhs: &MLArray, rhs: &MLArray) -> Result<Self, CoreMLError> {
+        let (lhs, rhs) = values(lhs, rhs)?;
+        let n = lhs.len() as f64;
+        let (mut abs, mut squared, mut max_abs) = (0f64, 0f64, 0f64);
+        let (mut dot, mut norm_l, mut norm_r) = (0f64, 0f64, 0f64);
+        for (&l, &r) in lhs.iter().zip(&rhs) {
+            let diff = (l - r).abs();
+            abs += diff;
+            squared += diff * diff;
+            // NaN sticks, f64::max would drop it
+            if !max_abs.is_nan() && (diff.is_nan() || diff > max_abs) {
+                max_abs = diff;
+            }
+            dot += l * r;
+            norm_l += l * l;
+            norm_r += r * r;
+        }
+        Ok(Self {
+            mae: abs / n,
+            mse: squared / n,
+            rmse: (squared / n).sqrt(),
+            max_abs,
+            relative: relative(squared, norm_r),
+            cosine: cosine(dot, norm_l, norm_r),
+        })
+    }
+}
+
+pub fn mae(lhs: &MLArray, rhs: &MLArray) -> Result<f64, CoreMLError> {
+    Ok(Metrics::between(lhs, rhs)?.mae)
+}
+
+pub fn mse(lhs: &MLArray, rhs: &MLArray) -> Result<f64, CoreMLError> {
+    Ok(Metrics::between(lhs, rhs)?.mse)
+}
+
+pub fn rmse(lhs: &MLArray, rhs: &MLArray) -> Result<f64, CoreMLError> {
+    Ok(Metrics::between(lhs, rhs)?.rmse)
+}
+
+pub fn max_abs_error(lhs: &MLArray, rhs: &MLArray) -> Result<f64, CoreMLError> {
+    Ok(Metrics::between(lhs, rhs)?.max_abs)
+}
+
+/// `||lhs - rhs|| / ||rhs||`, 0 if both are all zeros and infinite if only `rhs` is.
+pub fn relative_error(lhs: &MLArray, rhs: &MLArray) -> Result<f64, CoreMLError> {
+    Ok(Metrics::between(lhs, rhs)?.relative)
+}
+
+/// Cosine of the angle between the arrays, 1 if both are all zeros and 0 if only one is.
+pub fn cosine_similarity(lhs: &MLArray, rhs: &MLArray) -> Result<f64, CoreMLError> {
+    Ok(Metrics::between(lhs, rhs)?.cosine)
+}
+
+/// Peak signal-to-noise ratio in dB for values up to `max_value`, e.g. 1.0 or 255.0 for
+/// images, infinite for equal arrays.
+pub fn psnr(lhs: &MLArray, rhs: &MLArray, max_value: f64) -> Result<f64, CoreMLError> {
+    let mse = mse(lhs, rhs)?;
+    Ok(10.0 * (max_value * max_value / mse).log10())
+}
+
+/// Whether `|lhs - rhs| <= atol + rtol * |rhs|` for every element, like `numpy.allclose`.
+/// NaNs are never close, empty arrays always are.
+pub fn allclose(lhs: &MLArray, rhs: &MLArray, rtol: f64, atol: f64) -> Result<bool, CoreMLError> {
+    check_shapes(lhs, rhs)?;
+    let (lhs, rhs) = (to_f64(lhs), to_f64(rhs));
+    Ok(lhs
+        .iter()
+        .zip(&rhs)
+        .all(|(l, r)| (l - r).abs() <= atol + rtol * r.abs()))
+}
+
+fn relative(squared: f64, norm_r: f64) -> f64 {
+    match (squared, norm_r) {
+        (0.0, _) => 0.0,
+        (_, 0.0) => f64::INFINITY,
+        _ => (squared / norm_r).sqrt(),
+    }
+}
+
+fn cosine(dot: f64, norm_l: f64, norm_r: f64) -> f64 {
+    match (norm_l, norm_r) {
+        (0.0, 0.0) => 1.0,
+        (0.0, _) | (_, 0.0) => 0.0,
+        _ => dot / (norm_l.sqrt() * norm_r.sqrt()),
+    }
+}
+
+fn check_shapes(lhs: &MLArray, rhs: &MLArray) -> Result<(), CoreMLError> {
+    if lhs.shape() != rhs.shape() {
+        return Err(CoreMLError::BadInputShape(format!(
+            "can't compare arrays of shape {:?} and {:?}",
+            lhs.shape(),
+            rhs.shape()
+        )));
+    }
+    Ok(())
+}
+
+fn values(lhs: &MLArray, rhs: &MLArray) -> Result<(Vec<f64>, Vec<f64>), CoreMLError> {
+    check_shapes(lhs, rhs)?;
+    if lhs.is_empty() {
+        return Err(CoreMLError::BadInputShape(format!(
+            "can't compute metrics of empty arrays of shape {:?}",
+            lhs.shape()
+        )));
+    }
+    Ok((to_f64(lhs), to_f64(rhs)))
+}
+
+/// Elements in logical order, whatever the memory layout.
+fn to_f64(array: &MLArray) -> Vec<f64> {
+    match array {
+        MLArray::Float32Array(a) => a.iter().map(|&v| v as f64).collect(),
+        MLArray::Float16Array(a) => a.iter().map(|v| v.to_f64()).collect(),
+        MLArray::Float64Array(a) => a.iter().copied().collect(),
+        MLArray::Int32Array(a) => a.iter().map(|&v| v as f64).collect(),
+        MLArray::Int16Array(a) => a.iter().map(|&v| v as f64).collect(),
+        MLArray::Int8Array(a) => a.iter().map(|&v| v as f64).collect(),
+        MLArray::UInt32Array(a) => a.iter().map(|&v| v as f64).collect(),
+        MLArray::UInt16Array(a) => a.iter().map(|&v| v as f64).collect(),
+        MLArray::UInt8Array(a) => a.iter().map(|&v| v as f64).collect(),
+    }
+}
@@ -1,10 +1,15 @@
 use half::f16;
 use ndarray::{Array, ArrayBase, Dim, IxDynImpl, OwnedRepr};
 
-#[derive(Debug)]
+/// A multi array passed to or returned by CoreML, one variant per element type.
+///
+/// More element types may be added, so matches need a wildcard arm.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
 pub enum MLArray {
     Float32Array(ArrayBase<OwnedRepr<f32>, Dim<IxDynImpl>>),
     Float16Array(ArrayBase<OwnedRepr<f16>, Dim<IxDynImpl>>),
+    Float64Array(ArrayBase<OwnedRepr<f64>, Dim<IxDynImpl>>),
     Int32Array(ArrayBase<OwnedRepr<i32>, Dim<IxDynImpl>>),
     Int16Array(ArrayBase<OwnedRepr<i16>, Dim<IxDynImpl>>),
     Int8Array(ArrayBase<OwnedRepr<i8>, Dim<IxDynImpl>>),
@@ -18,6 +23,7 @@ impl MLArray {
         match self {
             MLArray::Float32Array(array_base) => array_base.shape(),
             MLArray::Float16Array(array_base) => array_base.shape(),
+            MLArray::Float64Array(array_base) => array_base.shape(),
             MLArray::Int32Array(array_base) => array_base.shape(),
             MLArray::Int16Array(array_base) => array_base.shape(),
             MLArray::Int8Array(array_base) => array_base.shape(),
@@ -26,6 +32,25 @@ impl MLArray {
             MLArray::UInt8Array(array_base) => array_base.shape(),
         }
     }
+
+    pub fn len(&self) -> usize {
+        self.shape().iter().product()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Size of the elements in bytes
+    pub fn nbytes(&self) -> usize {
+        let size = match self {
+            MLArray::Float64Array(_) => 8,
+            MLArray::Float32Array(_) | MLArray::Int32Array(_) | MLArray::UInt32Array(_) => 4,
+            MLArray::Float16Array(_) | MLArray::Int16Array(_) | MLArray::UInt16Array(_) => 2,
+            MLArray::Int8Array(_) | MLArray::UInt8Array(_) => 1,
+        };
+        self.len() * size
+    }
 }
 
 pub fn mean_absolute_error_bytes<
@@ -47,6 +72,8 @@ pub fn mean_absolute_error_bytes<
     mean_absolute_error::<T>(lhs, rhs)
 }
 
+/// Mean absolute error of equal length slices, 0 if both are empty. Panics if the lengths
+/// differ, see [`crate::metrics`] for checked metrics of whole arrays.
 pub fn mean_absolute_error<
     T: core::ops::Sub<Output = T>
         + PartialOrd
@@ -57,13 +84,17 @@ pub fn mean_absolute_error<
     lhs: impl AsRef<[T]>,
     rhs: impl AsRef<[T]>,
 ) -> f64 {
-    let (sum, count) = lhs
-        .as_ref()
+    let (lhs, rhs) = (lhs.as_ref(), rhs.as_ref());
+    assert_eq!(lhs.len(), rhs.len(), "lhs and rhs have different lengths");
+    if lhs.is_empty() {
+        return 0.0;
+    }
+    let sum = lhs
         .iter()
-        .zip(rhs.as_ref())
+        .zip(rhs)
         .map(|(&l, &r)| if l > r { l - r } else { r - l })
-        .fold((0f64, 0usize), |(acc, count), x| (acc + x.as_(), count + 1));
-    sum / count as f64
+        .fold(0f64, |acc, x| acc + x.as_());
+    sum / lhs.len() as f64
 }
 
 pub trait MLType {
@@ -94,9 +125,9 @@ impl MLType for i8 {
 impl MLType for u32 {
     const TY: usize = 7;
 }
-// impl MLType for i8 {
-//     const TY: usize = 8;
-// }
+impl MLType for f64 {
+    const TY: usize = 8;
+}
 
 impl<T: MLType> From<ArrayBase<OwnedRepr<T>, Dim<IxDynImpl>>> for MLArray {
     fn from(value: ArrayBase<OwnedRepr<T>, Dim<IxDynImpl>>) -> Self {
@@ -110,6 +141,10 @@ impl<T: MLType> From<ArrayBase<OwnedRepr<T>, Dim<IxDynImpl>>> for MLArray {
                 5 => MLArray::Int16Array(std::mem::transmute(value)),
                 6 => MLArray::Int8Array(std::mem::transmute(value)),
                 7 => MLArray::UInt32Array(std::mem::transmute(value)),
+                8 => MLArray::Float64Array(std::mem::transmute::<
+                    ArrayBase<OwnedRepr<T>, Dim<IxDynImpl>>,
+                    ArrayBase<OwnedRepr<f64>, Dim<IxDynImpl>>,
+                >(value)),
                 _ => panic!("not supported"),
             }
         }
@@ -122,6 +157,10 @@ impl MLArray {
             match self {
                 MLArray::Float32Array(fm) => std::mem::transmute(fm),
                 MLArray::Float16Array(fm) => std::mem::transmute(fm),
+                MLArray::Float64Array(fm) => std::mem::transmute::<
+                    ArrayBase<OwnedRepr<f64>, Dim<IxDynImpl>>,
+                    Array<T, Dim<IxDynImpl>>,
+                >(fm),
 
                 MLArray::Int32Array(im) => std::mem::transmute(im),
                 MLArray::Int16Array(im) => std::mem::transmute(im),
@@ -1,220 +1,86 @@
 use crate::{
+    batch::{split_batch, validate_items, BatchBuilder},
+    computeplan::ComputePlan,
+    diskbuffer::MappedBuffer,
     ffi::{modelWithAssetsBatch, modelWithPathBatch, BatchModel},
+    instrument::{self, stage},
+    lifecycle::{AssetBuffer, ModelKind, ModelLifecycle},
     mlarray::MLArray,
-    mlmodel::{CoreMLError, CoreMLModelInfo, CoreMLModelLoader},
-    swift::MLBatchModelOutput,
-    CoreMLModelOptions,
+    mlmodel::{metadata_from_description, warm_up_specs, CoreMLError, CoreMLModelInfo},
+    spec::{ArrayDataType, ModelMetadata},
+    synthetic::{Fill, InputGenerator},
 };
-use flate2::Compression;
 use ndarray::Array;
-use std::{
-    collections::HashMap,
-    io::{Read, Write},
-    path::{Path, PathBuf},
-};
-use tempdir::TempDir;
+use std::collections::{HashMap, HashSet};
 
-pub use crate::swift::MLModelOutput;
+pub use crate::swift::{MLBatchModelOutput, MLModelOutput};
 
-#[derive(Debug)]
-pub enum CoreMLBatchModelWithState {
-    Unloaded(CoreMLModelInfo, CoreMLModelLoader),
-    Loaded(CoreMLBatchModel, CoreMLModelInfo, CoreMLModelLoader),
-}
+pub type CoreMLBatchModelWithState = ModelLifecycle<CoreMLBatchModel>;
 
-impl CoreMLBatchModelWithState {
-    pub fn new(path: impl AsRef<Path>, opts: CoreMLModelOptions) -> Self {
-        Self::Unloaded(
-            CoreMLModelInfo { opts },
-            CoreMLModelLoader::ModelPath(path.as_ref().to_path_buf()),
-        )
+impl ModelLifecycle<CoreMLBatchModel> {
+    pub fn description(&self) -> Result<HashMap<&str, Vec<String>>, CoreMLError> {
+        Ok(self.model()?.description())
     }
-    pub fn new_compiled(path: impl AsRef<Path>, opts: CoreMLModelOptions) -> Self {
-        Self::Unloaded(
-            CoreMLModelInfo { opts },
-            CoreMLModelLoader::CompiledPath(path.as_ref().to_path_buf()),
-        )
+
+    pub fn metadata(&self) -> Result<ModelMetadata, CoreMLError> {
+        Ok(self.model()?.metadata())
     }
 
-    pub fn from_buf(buf: Vec<u8>, opts: CoreMLModelOptions) -> Self {
-        Self::Unloaded(CoreMLModelInfo { opts }, CoreMLModelLoader::Buffer(buf))
+    pub fn compute_plan(&self) -> Result<ComputePlan, CoreMLError> {
+        self.model()?.compute_plan()
     }
 
-    pub fn load(self) -> Result<Self, CoreMLError> {
-        let Self::Unloaded(info, loader) = self else {
-            return Ok(self);
-        };
-        match loader {
-            CoreMLModelLoader::ModelPath(path_buf) => {
-                let mut coreml_model = CoreMLBatchModel::load_from_path(
-                    path_buf.display().to_string(),
-                    info.clone(),
-                    false,
-                );
-                coreml_model.model.load();
-                let loader = CoreMLModelLoader::ModelPath(path_buf);
-                if coreml_model.model.failed() {
-                    return Err(CoreMLError::FailedToLoadBatchStatic(
-                        "Failed to load model; likely not a CoreML model file",
-                        Self::Unloaded(info, loader),
-                    ));
-                }
-                Ok(Self::Loaded(coreml_model, info, loader))
-            }
-            CoreMLModelLoader::CompiledPath(path_buf) => {
-                let mut coreml_model = CoreMLBatchModel::load_from_path(
-                    path_buf.display().to_string(),
-                    info.clone(),
-                    true,
-                );
-                coreml_model.model.load();
-                let loader = CoreMLModelLoader::CompiledPath(path_buf);
-                if coreml_model.model.failed() {
-                    return Err(CoreMLError::FailedToLoadBatchStatic(
-                        "Failed to load model; likely not a CoreML model file",
-                        Self::Unloaded(info, loader),
-                    ));
-                }
-                Ok(Self::Loaded(coreml_model, info, loader))
-            }
-            CoreMLModelLoader::Buffer(vec) => {
-                let mut coreml_model = CoreMLBatchModel::load_buffer(vec.clone(), info.clone());
-                coreml_model.model.load();
-                if coreml_model.model.failed() {
-                    return Err(CoreMLError::FailedToLoadBatchStatic(
-                        "Failed to load model; likely not a CoreML mlmodel file",
-                        Self::Unloaded(info, CoreMLModelLoader::Buffer(vec)),
-                    ));
-                }
-                let loader = CoreMLModelLoader::Buffer(vec);
-                Ok(Self::Loaded(coreml_model, info, loader))
-            }
-            CoreMLModelLoader::BufferToDisk(u) => {
-                match std::fs::File::open(&u)
-                    .map_err(|io| CoreMLError::IoError(io))
-                    .and_then(|file| {
-                        let mut vec = vec![];
-                        _ = flate2::read::ZlibDecoder::new(file)
-                            .read_to_end(&mut vec)
-                            .map_err(|io| CoreMLError::IoError(io))?;
-                        Ok(vec)
-                    }) {
-                    Ok(vec) => {
-                        let mut coreml_model = CoreMLBatchModel::load_buffer(vec, info.clone());
-                        coreml_model.model.load();
-                        let loader = CoreMLModelLoader::BufferToDisk(u);
-                        Ok(Self::Loaded(coreml_model, info, loader))
-                    }
-                    Err(err) => Err(CoreMLError::FailedToBatchLoad(
-                        format!("failed to load the model from cached buffer path: {err}"),
-                        CoreMLBatchModelWithState::Unloaded(
-                            info,
-                            CoreMLModelLoader::BufferToDisk(u),
-                        ),
-                    )),
-                }
-            }
-        }
+    pub fn add_input(
+        &mut self,
+        tag: impl AsRef<str>,
+        input: impl Into<MLArray>,
+        idx: isize,
+    ) -> Result<(), CoreMLError> {
+        self.model_mut()?.add_input(tag, input, idx)
     }
 
-    /// Might fail if system disk space too low(very unlikely)
-    pub fn unload(self) -> Result<Self, CoreMLError> {
-        if let Self::Loaded(_, info, loader) = self {
-            Ok(Self::Unloaded(
-                info,
-                match loader {
-                    CoreMLModelLoader::Buffer(v) => {
-                        let t = TempDir::new("coreml").map_err(CoreMLError::IoError)?;
-                        _ = std::fs::remove_dir_all(&t);
-                        _ = std::fs::create_dir_all(&t);
-                        let path = t.path().join("mlmodel_cache");
-                        std::fs::write(&path, v).unwrap();
-                        CoreMLModelLoader::Buffer(
-                            std::fs::read(&path).map_err(CoreMLError::IoError)?,
-                        )
-                    }
-                    x => x,
-                },
-            ))
-        } else {
-            Ok(self)
-        }
+    pub fn add_batched_input(
+        &mut self,
+        tag: impl AsRef<str>,
+        input: impl Into<MLArray>,
+        axis: usize,
+    ) -> Result<(), CoreMLError> {
+        self.model_mut()?.add_batched_input(tag, input, axis)
     }
 
-    /// Unloads the model buffer to the disk, at cache_dir
-    pub fn unload_to_disk(self) -> Result<Self, CoreMLError> {
-        match self {
-            Self::Loaded(_, mut info, loader) | Self::Unloaded(mut info, loader) => {
-                let loader = {
-                    match loader {
-                        CoreMLModelLoader::Buffer(vec) => {
-                            if info.opts.cache_dir.as_os_str().is_empty() {
-                                info.opts.cache_dir = PathBuf::from(".");
-                            }
-                            if !info.opts.cache_dir.exists() {
-                                _ = std::fs::remove_dir_all(&info.opts.cache_dir);
-                                _ = std::fs::create_dir_all(&info.opts.cache_dir);
-                            }
-                            // pick the file specified, if it's a folder/dir append model_cache
-                            let m = if !info.opts.cache_dir.is_dir() {
-                                info.opts.cache_dir.clone()
-                            } else {
-                                info.opts.cache_dir.join("model_cache")
-                            };
-                            match std::fs::File::create(&m)
-                                .map_err(|io| CoreMLError::IoError(io))
-                                .map(|file| {
-                                    flate2::write::ZlibEncoder::new(file, Compression::best())
-                                        .write_all(&vec)
-                                        .map_err(CoreMLError::IoError)
-                                }) {
-                                Ok(_) => {}
-                                Err(err) => {
-                                    return Err(CoreMLError::FailedToBatchLoad(
-                                        format!("failed to load the model from the buffer: {err}"),
-                                        CoreMLBatchModelWithState::Unloaded(
-                                            info,
-                                            CoreMLModelLoader::Buffer(vec),
-                                        ),
-                                    ));
-                                }
-                            };
-                            CoreMLModelLoader::BufferToDisk(m)
-                        }
-                        loader => loader,
-                    }
-                };
-                Ok(Self::Unloaded(info, loader))
-            }
-        }
+    /// See [`CoreMLBatchModel::check_item`].
+    pub fn check_item(&self, item: &HashMap<String, MLArray>) -> Result<(), CoreMLError> {
+        self.model()?.check_item(item)
     }
 
-    pub fn description(&self) -> Result<HashMap<&str, Vec<String>>, CoreMLError> {
-        match self {
-            CoreMLBatchModelWithState::Unloaded(_, _) => Err(CoreMLError::ModelNotLoaded),
-            CoreMLBatchModelWithState::Loaded(core_mlmodel, _, _) => Ok(core_mlmodel.description()),
-        }
+    /// Number of batch items with inputs bound, 0 while unloaded
+    pub fn batch_len(&self) -> usize {
+        self.model().map_or(0, CoreMLBatchModel::batch_len)
     }
 
-    pub fn add_input(
-        &mut self,
-        tag: impl AsRef<str>,
-        input: impl Into<MLArray>,
-        idx: isize,
-    ) -> Result<(), CoreMLError> {
-        match self {
-            CoreMLBatchModelWithState::Unloaded(_, _) => Err(CoreMLError::ModelNotLoaded),
-            CoreMLBatchModelWithState::Loaded(core_mlmodel, _, _) => {
-                core_mlmodel.add_input(tag, input, idx)
-            }
+    pub fn clear_inputs(&mut self) {
+        if let Ok(model) = self.model_mut() {
+            model.clear_inputs();
         }
     }
 
     pub fn predict(&mut self) -> Result<MLBatchModelOutput, CoreMLError> {
-        match self {
-            CoreMLBatchModelWithState::Unloaded(_, _) => Err(CoreMLError::ModelNotLoaded),
-            CoreMLBatchModelWithState::Loaded(core_mlmodel, _, _) => core_mlmodel.predict(),
-        }
+        self.model_mut()?.predict()
+    }
+
+    /// Runs `n` single item batches of inputs filled with the `warm_up_fill` option, so
+    /// CoreML specializes the model for the compute device before the first real
+    /// prediction instead of during it.
+    pub fn warm_up(&mut self, n: usize) -> Result<(), CoreMLError> {
+        let fill = self.info().opts.warm_up_fill;
+        self.model_mut()?.warm_up(n, fill)
+    }
+
+    pub fn predict_batch(
+        &mut self,
+        batch: &mut BatchBuilder,
+    ) -> Result<MLBatchModelOutput, CoreMLError> {
+        self.model_mut()?.predict_batch(batch)
     }
 }
 
@@ -223,10 +89,41 @@ pub struct CoreMLBatchModel {
     model: BatchModel,
     // save_path: Option<PathBuf>,
     outputs: HashMap<String, (&'static str, Vec<usize>)>,
+    /// Input names bound for each batch index
+    bound: Vec<HashSet<String>>,
+    /// Bytes of models loaded from memory, declared after `model` so they are only freed
+    /// once CoreML released them
+    _asset: Option<AssetBuffer>,
 }
 
 unsafe impl Send for CoreMLBatchModel {}
 
+impl ModelKind for CoreMLBatchModel {
+    fn from_path(path: String, info: &CoreMLModelInfo, compiled: bool) -> Self {
+        Self::load_from_path(path, info.clone(), compiled)
+    }
+
+    fn from_buffer(buf: Vec<u8>, info: &CoreMLModelInfo) -> Self {
+        Self::load_buffer(buf, info.clone())
+    }
+
+    fn from_mapped(buf: MappedBuffer, info: &CoreMLModelInfo) -> Self {
+        Self::load_mapped(buf, info.clone())
+    }
+
+    fn load(&mut self) -> bool {
+        self.model.load() && !self.model.failed()
+    }
+
+    fn compiled_path(&self) -> Option<String> {
+        self.model.compiled_path()
+    }
+
+    fn warm_up(&mut self, n: usize, fill: Fill) -> Result<(), CoreMLError> {
+        CoreMLBatchModel::warm_up(self, n, fill)
+    }
+}
+
 impl std::fmt::Debug for BatchModel {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("BatchModel").finish()
@@ -235,26 +132,43 @@ impl std::fmt::Debug for BatchModel {
 
 impl CoreMLBatchModel {
     pub fn load_from_path(path: String, info: CoreMLModelInfo, compiled: bool) -> Self {
+        // uncompiled models are compiled on creation
+        let _stage = if compiled {
+            None
+        } else {
+            Some(stage!("compile"))
+        };
         let coreml_model = Self {
             model: modelWithPathBatch(path, info.opts.compute_platform, compiled),
             // save_path: None,
             outputs: Default::default(),
+            bound: Default::default(),
+            _asset: None,
         };
         coreml_model
     }
 
-    pub fn load_buffer(mut buf: Vec<u8>, info: CoreMLModelInfo) -> Self {
-        let coreml_model = Self {
+    pub fn load_buffer(buf: Vec<u8>, info: CoreMLModelInfo) -> Self {
+        Self::load_asset(AssetBuffer::Owned(buf.into_boxed_slice()), info)
+    }
+
+    /// Model for a buffer mapped from disk, CoreML reads it without copying it to the heap.
+    pub fn load_mapped(buf: MappedBuffer, info: CoreMLModelInfo) -> Self {
+        Self::load_asset(AssetBuffer::Mapped(buf), info)
+    }
+
+    fn load_asset(asset: AssetBuffer, info: CoreMLModelInfo) -> Self {
+        Self {
+            // only borrowed by CoreML, freed with the model
             model: modelWithAssetsBatch(
-                buf.as_mut_ptr(),
-                buf.len() as isize,
+                asset.as_ptr() as *mut u8,
+                asset.len() as isize,
                 info.opts.compute_platform,
             ),
-            // save_path: None,
             outputs: Default::default(),
-        };
-        std::mem::forget(buf);
-        coreml_model
+            bound: Default::default(),
+            _asset: Some(asset),
+        }
     }
 
     pub fn add_input(
@@ -266,26 +180,42 @@ impl CoreMLBatchModel {
         // route input correctly
         let input: MLArray = input.into();
         let name = tag.as_ref().to_string();
-        let desc = self.model.description();
-        let shape: Vec<usize> = input.shape().to_vec();
-        let arr = desc.input_shape(name.clone());
-        if arr.len() != shape.len() || !arr.iter().eq(shape.iter()) {
-            if arr.len() == 0 {
-                return Err(CoreMLError::BadInputShape(format!(
-                    "Input feature name '{name}' not expected!"
-                )));
-            }
+        if idx < 0 || idx as usize > self.bound.len() {
             return Err(CoreMLError::BadInputShape(format!(
-                "expected shape {arr:?} found {shape:?}"
+                "batch index {idx} is out of order, the next index is {}",
+                self.bound.len()
             )));
         }
+        let idx_usize = idx as usize;
+        self.check_input(&name, &input, idx_usize)?;
+        let shape = input.shape().to_vec();
+        let _stage = stage!("bind");
+        instrument::bytes("input", &name, input.nbytes());
         match input {
             MLArray::Float32Array(array_base) => {
                 let mut data = array_base.into_raw_vec();
-                if !self
-                    .model
-                    .bindInputF32(shape, name, data.as_mut_ptr(), data.capacity(), idx)
-                {
+                if !self.model.bindInputF32(
+                    shape,
+                    name.clone(),
+                    data.as_mut_ptr(),
+                    data.capacity(),
+                    idx,
+                ) {
+                    return Err(CoreMLError::UnknownErrorStatic(
+                        "failed to bind input to model",
+                    ));
+                }
+                std::mem::forget(data);
+            }
+            MLArray::Float16Array(array_base) => {
+                let mut data = array_base.into_raw_vec();
+                if !self.model.bindInputU16(
+                    shape,
+                    name.clone(),
+                    data.as_mut_ptr() as *mut u16,
+                    data.capacity(),
+                    idx,
+                ) {
                     return Err(CoreMLError::UnknownErrorStatic(
                         "failed to bind input to model",
                     ));
@@ -298,11 +228,171 @@ impl CoreMLBatchModel {
                 ));
             }
         }
+        if idx_usize == self.bound.len() {
+            self.bound.push(HashSet::new());
+        }
+        self.bound[idx_usize].insert(name);
+        Ok(())
+    }
+
+    /// Everything `add_input` checks before binding, but the batch index order.
+    fn check_input(&self, name: &str, input: &MLArray, idx: usize) -> Result<(), CoreMLError> {
+        if self
+            .bound
+            .get(idx)
+            .is_some_and(|names| names.contains(name))
+        {
+            return Err(CoreMLError::BadInputShape(format!(
+                "input '{name}' is already bound for batch index {idx}"
+            )));
+        }
+        self.check_value(name, input)
+    }
+
+    /// Whether `input` has the shape and a type the model input `name` can be bound with.
+    fn check_value(&self, name: &str, input: &MLArray) -> Result<(), CoreMLError> {
+        let shape = input.shape();
+        let arr = self.model.description().input_shape(name.to_string());
+        if arr.len() != shape.len() || !arr.iter().eq(shape.iter()) {
+            if arr.len() == 0 {
+                return Err(CoreMLError::BadInputShape(format!(
+                    "Input feature name '{name}' not expected!"
+                )));
+            }
+            return Err(CoreMLError::BadInputShape(format!(
+                "expected shape {arr:?} found {shape:?}"
+            )));
+        }
+        if !matches!(input, MLArray::Float32Array(_) | MLArray::Float16Array(_)) {
+            return Err(CoreMLError::UnknownErrorStatic(
+                "failed to bind input to model",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks one batch item against the model description without binding it, every
+    /// required input has to be there with its expected shape and a type that can be bound.
+    pub fn check_item(&self, item: &HashMap<String, MLArray>) -> Result<(), CoreMLError> {
+        let mut missing: Vec<_> = self
+            .model
+            .description()
+            .required_input_names()
+            .into_iter()
+            .filter(|name| !item.contains_key(name))
+            .collect();
+        if !missing.is_empty() {
+            missing.sort();
+            return Err(CoreMLError::BadInputShape(format!(
+                "missing inputs {missing:?}"
+            )));
+        }
+        item.iter()
+            .try_for_each(|(name, input)| self.check_value(name, input))
+    }
+
+    /// Binds an input that already has a batch dimension, item `i` along `axis` going to
+    /// batch index `i`.
+    ///
+    /// The axis is kept with size 1 if the model input has the same rank as `input`, e.g. a
+    /// `[N, 3, 512, 512]` input for a model taking `[1, 3, 512, 512]`, and dropped otherwise.
+    ///
+    /// Every item is checked before any is bound, so a bad input leaves the bound inputs
+    /// as they were. If CoreML fails to bind an item anyway, all bound inputs are dropped.
+    pub fn add_batched_input(
+        &mut self,
+        tag: impl AsRef<str>,
+        input: impl Into<MLArray>,
+        axis: usize,
+    ) -> Result<(), CoreMLError> {
+        let input: MLArray = input.into();
+        let name = tag.as_ref();
+        let expected = self.model.description().input_shape(name.to_string());
+        if expected.is_empty() {
+            return Err(CoreMLError::BadInputShape(format!(
+                "Input feature name '{name}' not expected!"
+            )));
+        }
+        let keep_axis = expected.len() == input.shape().len();
+        let items = split_batch(input, axis, keep_axis)?;
+        for (idx, item) in items.iter().enumerate() {
+            self.check_input(name, item, idx)?;
+        }
+        for (idx, item) in items.into_iter().enumerate() {
+            if let Err(err) = self.add_input(name, item, idx as isize) {
+                self.clear_inputs();
+                return Err(err);
+            }
+        }
         Ok(())
     }
 
+    pub fn batch_len(&self) -> usize {
+        self.bound.len()
+    }
+
+    /// Drops all bound inputs, `predict` does this after every run.
+    pub fn clear_inputs(&mut self) {
+        self.model.clearInputs();
+        self.bound.clear();
+    }
+
+    /// Binds every item of `batch` and predicts, leaving `batch` empty.
+    ///
+    /// Inputs bound through `add_input` before are dropped.
+    pub fn predict_batch(
+        &mut self,
+        batch: &mut BatchBuilder,
+    ) -> Result<MLBatchModelOutput, CoreMLError> {
+        batch.validate(&self.model.description().required_input_names())?;
+        self.clear_inputs();
+        for (idx, item) in batch.drain().enumerate() {
+            for (name, input) in item {
+                if let Err(err) = self.add_input(name, input, idx as isize) {
+                    self.clear_inputs();
+                    return Err(err);
+                }
+            }
+        }
+        self.predict()
+    }
+
+    /// Runs `n` single item batches of inputs filled with `fill`, so CoreML specializes the
+    /// model for the compute device before the first real prediction. Inputs bound before
+    /// are dropped.
+    pub fn warm_up(&mut self, n: usize, fill: Fill) -> Result<(), CoreMLError> {
+        let _stage = stage!("warm_up");
+        let specs = warm_up_specs(
+            &self.model.description(),
+            &[ArrayDataType::Float32, ArrayDataType::Float16],
+        )?;
+        let generator = InputGenerator::new(fill);
+        self.clear_inputs();
+        for i in 0..n {
+            for (name, input) in generator.clone().seed(i as u64).generate(&specs)? {
+                if let Err(err) = self.add_input(name, input, 0) {
+                    self.clear_inputs();
+                    return Err(err);
+                }
+            }
+            self.predict()?;
+        }
+        Ok(())
+    }
+
+    /// Runs the batch bound through `add_input`, every index must have all required inputs.
+    ///
+    /// Bound inputs are cleared afterwards whether the prediction succeeded or not, so a
+    /// smaller batch never re-runs stale items.
     pub fn predict(&mut self) -> Result<MLBatchModelOutput, CoreMLError> {
+        let result = self.predict_bound();
+        self.clear_inputs();
+        result
+    }
+
+    fn predict_bound(&mut self) -> Result<MLBatchModelOutput, CoreMLError> {
         let desc = self.model.description();
+        validate_items(self.bound.iter(), &desc.required_input_names())?;
         for name in desc.output_names() {
             let shape = desc.output_shape(name.clone());
             let ty = desc.output_type(name.clone());
@@ -318,10 +408,14 @@ impl CoreMLBatchModel {
             }
         }
 
-        let output = self.model.predict();
+        let output = {
+            let _stage = stage!("predict");
+            self.model.predict()
+        };
         if let Some(err) = output.getError() {
             return Err(CoreMLError::UnknownError(err));
         }
+        let _stage = stage!("output_copy");
         let n = output.count();
         Ok(MLBatchModelOutput {
             outputs: (0..n).into_iter().map(|i|
@@ -339,6 +433,7 @@ impl CoreMLBatchModel {
                         }
                         let name = key.clone();
                         let out = output.outputF32(name);
+                        instrument::bytes("output", &key, out.len() * 4);
                         let array = Array::from_shape_vec(shape, out).ok()?;
                         Some((key, array.into()))
                     })
@@ -354,4 +449,13 @@ impl CoreMLBatchModel {
         map.insert("output", desc.outputs());
         map
     }
+
+    pub fn metadata(&self) -> ModelMetadata {
+        metadata_from_description(&self.model.description())
+    }
+
+    /// Device placement of every operation, needs macOS 14.4 or newer.
+    pub fn compute_plan(&self) -> Result<ComputePlan, CoreMLError> {
+        ComputePlan::from_bridge(self.model.compute_plan_json())
+    }
 }
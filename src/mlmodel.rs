@@ -1,16 +1,17 @@
 use crate::{
-    ffi::{modelWithAssets, modelWithPath, ComputePlatform, Model},
+    archive::{ArchiveSource, ExtractedPackage},
+    classifier::{top_k, Label},
+    computeplan::ComputePlan,
+    diskbuffer::{BufferCompression, MappedBuffer},
+    ffi::{modelWithAssets, modelWithPath, ComputePlatform, Model, ModelDescription},
+    instrument::{self, stage},
+    lifecycle::{AssetBuffer, ModelKind, ModelLifecycle},
     mlarray::MLArray,
-    mlbatchmodel::CoreMLBatchModelWithState,
+    spec::{ArrayDataType, ModelMetadata, ModelSpec, ShapeFlexibility},
+    synthetic::{Fill, InputGenerator, InputSpec},
 };
-use flate2::Compression;
 use ndarray::Array;
-use std::{
-    collections::HashMap,
-    io::{Read, Write},
-    path::{Path, PathBuf},
-};
-use tempdir::TempDir;
+use std::{cell::OnceCell, collections::HashMap, path::PathBuf};
 
 pub use crate::swift::MLModelOutput;
 
@@ -23,28 +24,48 @@ pub enum CoreMLError {
     IoError(std::io::Error),
     #[error("BadInputShape: {0}")]
     BadInputShape(String),
-    // #[error("Lz4 Decompression Error: {0}")]
-    // Lz4DecompressError(DecompressError),
+    #[error("Lz4 Decompression Error: {0}")]
+    Lz4DecompressError(lz4_flex::block::DecompressError),
+    #[error("BadArchive: {0}")]
+    BadArchive(String),
+    #[error("BadPackage: {0}")]
+    BadPackage(String),
+    #[error("BadCompiledModel: {0}")]
+    BadCompiledModel(String),
+    #[error("BadWeightFile: {0}")]
+    BadWeightFile(String),
+    #[error("BadSpec: {0}")]
+    BadSpec(String),
+    #[error("BadNpy: {0}")]
+    BadNpy(String),
+    #[error("CacheError: {0}")]
+    CacheError(String),
+    #[error("CompileError: {0}")]
+    CompileError(String),
     #[error("UnknownError: {0}")]
     UnknownError(String),
     #[error("UnknownError: {0}")]
     UnknownErrorStatic(&'static str),
     #[error("ModelNotLoaded: coreml model not loaded into session")]
     ModelNotLoaded,
+    #[error("NotAClassifier: coreml model has no class probabilities output")]
+    NotAClassifier,
     #[error("FailedToLoad: coreml model couldn't be loaded: {0}")]
-    FailedToLoadStatic(&'static str, CoreMLModelWithState),
-    #[error("FailedToLoad: coreml model couldn't be loaded: {0}")]
-    FailedToLoad(String, CoreMLModelWithState),
-    #[error("FailedToLoadBatch: coreml model couldn't be loaded: {0}")]
-    FailedToLoadBatchStatic(&'static str, CoreMLBatchModelWithState),
-    #[error("FailedToLoadBatch: coreml model couldn't be loaded: {0}")]
-    FailedToBatchLoad(String, CoreMLBatchModelWithState),
+    FailedToLoad(String),
 }
 
 #[derive(Default, Clone)]
 pub struct CoreMLModelOptions {
     pub compute_platform: ComputePlatform,
     pub cache_dir: PathBuf,
+    /// Max size in bytes of the compiled model cache under `cache_dir`, unbounded if `None`
+    pub compiled_cache_limit: Option<u64>,
+    /// Codec of model buffers written by `unload_to_disk`
+    pub buffer_compression: BufferCompression,
+    /// Predictions to run after every load, see `warm_up`
+    pub warm_up: usize,
+    /// Values of the inputs warm ups predict on, zeros by default
+    pub warm_up_fill: Fill,
 }
 
 impl std::fmt::Debug for CoreMLModelOptions {
@@ -58,6 +79,11 @@ impl std::fmt::Debug for CoreMLModelOptions {
                     ComputePlatform::CpuAndGpu => &"CpuAndGpu",
                 },
             )
+            .field("cache_dir", &self.cache_dir)
+            .field("compiled_cache_limit", &self.compiled_cache_limit)
+            .field("buffer_compression", &self.buffer_compression)
+            .field("warm_up", &self.warm_up)
+            .field("warm_up_fill", &self.warm_up_fill)
             .finish()
     }
 }
@@ -71,207 +97,77 @@ pub enum CoreMLModelLoader {
     /// Model with buffer to manage the buffer locally
     Buffer(Vec<u8>),
     BufferToDisk(PathBuf),
+    /// Model to be loaded from the given path, compiled once into the cache under cache_dir
+    CachedPath(PathBuf),
+    /// Zipped mlpackage, with the extracted package kept around while the model is loaded
+    Archive(ArchiveSource, Option<ExtractedPackage>),
 }
 
-#[derive(Debug)]
-pub enum CoreMLModelWithState {
-    Unloaded(CoreMLModelInfo, CoreMLModelLoader),
-    Loaded(CoreMLModel, CoreMLModelInfo, CoreMLModelLoader),
-}
+pub type CoreMLModelWithState = ModelLifecycle<CoreMLModel>;
 
-impl CoreMLModelWithState {
-    pub fn new(path: impl AsRef<Path>, opts: CoreMLModelOptions) -> Self {
-        Self::Unloaded(
-            CoreMLModelInfo { opts },
-            CoreMLModelLoader::ModelPath(path.as_ref().to_path_buf()),
-        )
+impl ModelLifecycle<CoreMLModel> {
+    pub fn description(&self) -> Result<HashMap<&str, Vec<String>>, CoreMLError> {
+        Ok(self.model()?.description())
     }
-    pub fn new_compiled(path: impl AsRef<Path>, opts: CoreMLModelOptions) -> Self {
-        Self::Unloaded(
-            CoreMLModelInfo { opts },
-            CoreMLModelLoader::CompiledPath(path.as_ref().to_path_buf()),
-        )
+
+    pub fn metadata(&self) -> Result<ModelMetadata, CoreMLError> {
+        Ok(self.model()?.metadata())
     }
 
-    pub fn from_buf(buf: Vec<u8>, opts: CoreMLModelOptions) -> Self {
-        Self::Unloaded(CoreMLModelInfo { opts }, CoreMLModelLoader::Buffer(buf))
+    pub fn compute_plan(&self) -> Result<ComputePlan, CoreMLError> {
+        self.model()?.compute_plan()
     }
 
-    pub fn load(self) -> Result<Self, CoreMLError> {
-        let Self::Unloaded(info, loader) = self else {
-            return Ok(self);
-        };
-        match loader {
-            CoreMLModelLoader::ModelPath(path_buf) => {
-                let mut coreml_model = CoreMLModel::load_from_path(
-                    path_buf.display().to_string(),
-                    info.clone(),
-                    false,
-                );
-                if !coreml_model.model.load() {
-                    return Err(CoreMLError::FailedToLoadStatic(
-                        "Failed to load model; model path not valid",
-                        Self::Unloaded(info, CoreMLModelLoader::ModelPath(path_buf)),
-                    ));
-                }
-                Ok(Self::Loaded(
-                    coreml_model,
-                    info,
-                    CoreMLModelLoader::ModelPath(path_buf),
-                ))
-            }
-            CoreMLModelLoader::CompiledPath(path_buf) => {
-                let mut coreml_model =
-                    CoreMLModel::load_from_path(path_buf.display().to_string(), info.clone(), true);
-                if !coreml_model.model.load() {
-                    return Err(CoreMLError::FailedToLoadStatic(
-                        "Failed to load model; compiled model cache got purged",
-                        Self::Unloaded(info, CoreMLModelLoader::CompiledPath(path_buf)),
-                    ));
-                }
-                Ok(Self::Loaded(
-                    coreml_model,
-                    info,
-                    CoreMLModelLoader::CompiledPath(path_buf),
-                ))
-            }
-            CoreMLModelLoader::Buffer(vec) => {
-                let mut coreml_model = CoreMLModel::load_buffer(vec.clone(), info.clone());
-                coreml_model.model.load();
-                if coreml_model.model.failed() {
-                    return Err(CoreMLError::FailedToLoadStatic(
-                        "Failed to load model; likely not a CoreML mlmodel file",
-                        Self::Unloaded(info, CoreMLModelLoader::Buffer(vec)),
-                    ));
-                }
-                let loader = CoreMLModelLoader::Buffer(vec);
-                Ok(Self::Loaded(coreml_model, info, loader))
-            }
-            CoreMLModelLoader::BufferToDisk(u) => {
-                match std::fs::File::open(&u)
-                    .map_err(|io| CoreMLError::IoError(io))
-                    .and_then(|file| {
-                        let mut vec = vec![];
-                        _ = flate2::read::ZlibDecoder::new(file)
-                            .read_to_end(&mut vec)
-                            .map_err(|io| CoreMLError::IoError(io))?;
-                        Ok(vec)
-                    }) {
-                    Ok(vec) => {
-                        let mut coreml_model = CoreMLModel::load_buffer(vec, info.clone());
-                        coreml_model.model.load();
-                        let loader = CoreMLModelLoader::BufferToDisk(u);
-                        Ok(Self::Loaded(coreml_model, info, loader))
-                    }
-                    Err(err) => Err(CoreMLError::FailedToLoad(
-                        format!("failed to load the model from cached buffer path: {err}"),
-                        CoreMLModelWithState::Unloaded(info, CoreMLModelLoader::BufferToDisk(u)),
-                    )),
-                }
-            }
-        }
+    pub fn add_input(
+        &mut self,
+        tag: impl AsRef<str>,
+        input: impl Into<MLArray>,
+    ) -> Result<(), CoreMLError> {
+        self.model_mut()?.add_input(tag, input)
     }
 
-    /// Might fail irrecoverably if the system is too low on disk space(very unlikely)
-    pub fn unload(self) -> Result<Self, CoreMLError> {
-        if let Self::Loaded(model, info, loader) = self {
-            Ok(Self::Unloaded(
-                info,
-                match loader {
-                    CoreMLModelLoader::Buffer(v) => {
-                        let t = TempDir::new("coreml").map_err(CoreMLError::IoError)?;
-                        _ = std::fs::remove_dir_all(&t);
-                        _ = std::fs::create_dir_all(&t);
-                        let path = t.path().join("mlmodel_cache");
-                        _ = std::fs::write(&path, v).map_err(CoreMLError::IoError)?;
-                        let res = std::fs::read(&path).map_err(CoreMLError::IoError)?;
-                        _ = std::fs::remove_dir_all(&t);
-                        CoreMLModelLoader::Buffer(res)
-                    }
-                    CoreMLModelLoader::ModelPath(_) => {
-                        // if the model is loaded from modelPath it has to have compiled path
-                        let path = model.model.compiled_path().unwrap();
-                        CoreMLModelLoader::CompiledPath(path.into())
-                    }
-                    x => x,
-                },
-            ))
-        } else {
-            Ok(self)
-        }
+    pub fn predict(&mut self) -> Result<MLModelOutput, CoreMLError> {
+        self.model_mut()?.predict()
     }
 
-    /// Unloads the model buffer to the disk, at cache_dir
-    pub fn unload_to_disk(self) -> Result<Self, CoreMLError> {
-        match self {
-            Self::Loaded(_, mut info, loader) | Self::Unloaded(mut info, loader) => {
-                let loader = {
-                    match loader {
-                        CoreMLModelLoader::Buffer(vec) => {
-                            if info.opts.cache_dir.as_os_str().is_empty() {
-                                info.opts.cache_dir = PathBuf::from(".");
-                            }
-                            if !info.opts.cache_dir.exists() {
-                                _ = std::fs::remove_dir_all(&info.opts.cache_dir);
-                                _ = std::fs::create_dir_all(&info.opts.cache_dir);
-                            }
-                            // pick the file specified, if it's a folder/dir append model_cache
-                            let m = if !info.opts.cache_dir.is_dir() {
-                                info.opts.cache_dir.clone()
-                            } else {
-                                info.opts.cache_dir.join("model_cache")
-                            };
-                            match std::fs::File::create(&m)
-                                .map_err(|io| CoreMLError::IoError(io))
-                                .map(|file| {
-                                    flate2::write::ZlibEncoder::new(file, Compression::best())
-                                        .write_all(&vec)
-                                        .map_err(CoreMLError::IoError)
-                                }) {
-                                Ok(_) => {}
-                                Err(err) => {
-                                    return Err(CoreMLError::FailedToLoad(
-                                        format!("failed to load the model from the buffer: {err}"),
-                                        CoreMLModelWithState::Unloaded(
-                                            info,
-                                            CoreMLModelLoader::Buffer(vec),
-                                        ),
-                                    ));
-                                }
-                            };
-                            CoreMLModelLoader::BufferToDisk(m)
-                        }
-                        loader => loader,
-                    }
-                };
-                Ok(Self::Unloaded(info, loader))
-            }
+    pub fn clear_inputs(&mut self) {
+        if let Ok(model) = self.model_mut() {
+            model.clear_inputs();
         }
     }
 
-    pub fn description(&self) -> Result<HashMap<&str, Vec<String>>, CoreMLError> {
-        match self {
-            CoreMLModelWithState::Unloaded(_, _) => Err(CoreMLError::ModelNotLoaded),
-            CoreMLModelWithState::Loaded(core_mlmodel, _, _) => Ok(core_mlmodel.description()),
-        }
+    /// Runs `n` predictions on inputs filled with the `warm_up_fill` option, so CoreML
+    /// specializes the model for the compute device before the first real prediction
+    /// instead of during it. Inputs bound before are dropped.
+    pub fn warm_up(&mut self, n: usize) -> Result<(), CoreMLError> {
+        let fill = self.info().opts.warm_up_fill;
+        self.model_mut()?.warm_up(n, fill)
     }
 
-    pub fn add_input(
+    /// Required inputs with their default shape, to generate inputs with
+    /// [`InputGenerator`](crate::synthetic::InputGenerator).
+    pub fn input_specs(&self) -> Result<Vec<InputSpec>, CoreMLError> {
+        input_specs(&self.model()?.model.description())
+    }
+
+    /// Binds `inputs` and runs a classifier model, returning the `k` most likely labels
+    /// sorted by descending probability.
+    pub fn classify(
         &mut self,
-        tag: impl AsRef<str>,
-        input: impl Into<MLArray>,
-    ) -> Result<(), CoreMLError> {
-        match self {
-            CoreMLModelWithState::Unloaded(_, _) => Err(CoreMLError::ModelNotLoaded),
-            CoreMLModelWithState::Loaded(core_mlmodel, _, _) => core_mlmodel.add_input(tag, input),
+        inputs: impl IntoIterator<Item = (impl AsRef<str>, impl Into<MLArray>)>,
+        k: usize,
+    ) -> Result<Vec<(Label, f64)>, CoreMLError> {
+        let model = self.model_mut()?;
+        for (tag, input) in inputs {
+            model.add_input(tag, input)?;
         }
+        model.classify(k)
     }
 
-    pub fn predict(&mut self) -> Result<MLModelOutput, CoreMLError> {
-        match self {
-            CoreMLModelWithState::Unloaded(_, _) => Err(CoreMLError::ModelNotLoaded),
-            CoreMLModelWithState::Loaded(core_mlmodel, _, _) => core_mlmodel.predict(),
-        }
+    /// Class labels of a classifier model, empty for other models. Before macOS 14 they are
+    /// read from the model spec, which compiled models don't have.
+    pub fn class_labels(&self) -> Result<Vec<Label>, CoreMLError> {
+        Ok(self.model()?.class_labels())
     }
 }
 
@@ -285,10 +181,42 @@ pub struct CoreMLModelInfo {
 pub struct CoreMLModel {
     model: Model,
     outputs: HashMap<String, (&'static str, Vec<usize>)>,
+    /// Bytes of models loaded from memory, declared after `model` so they are only freed
+    /// once CoreML released them
+    asset: Option<AssetBuffer>,
+    /// Uncompiled model the spec can be read from
+    source: Option<PathBuf>,
+    class_labels: OnceCell<Vec<Label>>,
 }
 
 unsafe impl Send for CoreMLModel {}
 
+impl ModelKind for CoreMLModel {
+    fn from_path(path: String, info: &CoreMLModelInfo, compiled: bool) -> Self {
+        Self::load_from_path(path, info.clone(), compiled)
+    }
+
+    fn from_buffer(buf: Vec<u8>, info: &CoreMLModelInfo) -> Self {
+        Self::load_buffer(buf, info.clone())
+    }
+
+    fn from_mapped(buf: MappedBuffer, info: &CoreMLModelInfo) -> Self {
+        Self::load_mapped(buf, info.clone())
+    }
+
+    fn load(&mut self) -> bool {
+        self.model.load() && !self.model.failed()
+    }
+
+    fn compiled_path(&self) -> Option<String> {
+        self.model.compiled_path()
+    }
+
+    fn warm_up(&mut self, n: usize, fill: Fill) -> Result<(), CoreMLError> {
+        CoreMLModel::warm_up(self, n, fill)
+    }
+}
+
 impl std::fmt::Debug for Model {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Model").finish()
@@ -297,26 +225,45 @@ impl std::fmt::Debug for Model {
 
 impl CoreMLModel {
     pub fn load_from_path(path: String, info: CoreMLModelInfo, compiled: bool) -> Self {
+        // uncompiled models are compiled on creation
+        let _stage = if compiled {
+            None
+        } else {
+            Some(stage!("compile"))
+        };
         let coreml_model = Self {
+            source: (!compiled).then(|| PathBuf::from(&path)),
             model: modelWithPath(path, info.opts.compute_platform, compiled),
             // save_path: None,
             outputs: Default::default(),
+            asset: None,
+            class_labels: OnceCell::new(),
         };
         coreml_model
     }
 
-    pub fn load_buffer(mut buf: Vec<u8>, info: CoreMLModelInfo) -> Self {
-        let coreml_model = Self {
+    pub fn load_buffer(buf: Vec<u8>, info: CoreMLModelInfo) -> Self {
+        Self::load_asset(AssetBuffer::Owned(buf.into_boxed_slice()), info)
+    }
+
+    /// Model for a buffer mapped from disk, CoreML reads it without copying it to the heap.
+    pub fn load_mapped(buf: MappedBuffer, info: CoreMLModelInfo) -> Self {
+        Self::load_asset(AssetBuffer::Mapped(buf), info)
+    }
+
+    fn load_asset(asset: AssetBuffer, info: CoreMLModelInfo) -> Self {
+        Self {
+            // only borrowed by CoreML, freed with the model
             model: modelWithAssets(
-                buf.as_mut_ptr(),
-                buf.len() as isize,
+                asset.as_ptr() as *mut u8,
+                asset.len() as isize,
                 info.opts.compute_platform,
             ),
-            // save_path: None,
             outputs: Default::default(),
-        };
-        std::mem::forget(buf);
-        coreml_model
+            asset: Some(asset),
+            source: None,
+            class_labels: OnceCell::new(),
+        }
     }
 
     pub fn add_input(
@@ -340,6 +287,8 @@ impl CoreMLModel {
                 "expected shape {arr:?} found {shape:?}"
             )));
         }
+        let _stage = stage!("bind");
+        instrument::bytes("input", &name, input.nbytes());
         match input {
             MLArray::Float32Array(array_base) => {
                 let mut data = array_base.into_raw_vec();
@@ -392,6 +341,39 @@ impl CoreMLModel {
         Ok(())
     }
 
+    /// Runs `n` predictions on inputs filled with `fill`, see
+    /// `CoreMLModelWithState::warm_up`. Inputs bound before are dropped.
+    pub fn warm_up(&mut self, n: usize, fill: Fill) -> Result<(), CoreMLError> {
+        let _stage = stage!("warm_up");
+        let specs = warm_up_specs(
+            &self.model.description(),
+            &[
+                ArrayDataType::Float32,
+                ArrayDataType::Float16,
+                ArrayDataType::Int32,
+            ],
+        )?;
+        let generator = InputGenerator::new(fill);
+        self.clear_inputs();
+        for i in 0..n {
+            let inputs = generator.clone().seed(i as u64).generate(&specs)?;
+            let res = inputs
+                .into_iter()
+                .try_for_each(|(name, input)| self.add_input(name, input))
+                .and_then(|()| self.predict());
+            if let Err(err) = res {
+                self.clear_inputs();
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Drops all bound inputs, `predict` does this after every successful run.
+    pub fn clear_inputs(&mut self) {
+        self.model.clearInputs();
+    }
+
     pub fn add_output_f32(&mut self, tag: impl AsRef<str>, out: impl Into<MLArray>) -> bool {
         let arr: MLArray = out.into();
         let shape = arr.shape();
@@ -425,10 +407,14 @@ impl CoreMLModel {
                 }
             }
         }
-        let output = self.model.predict();
+        let output = {
+            let _stage = stage!("predict");
+            self.model.predict()
+        };
         if let Some(err) = output.getError() {
             return Err(CoreMLError::UnknownError(err));
         }
+        let _stage = stage!("output_copy");
         Ok(MLModelOutput {
             outputs: self
                 .outputs
@@ -439,11 +425,13 @@ impl CoreMLModel {
                     match ty {
                         "f32" => {
                             let out = output.outputF32(name);
+                            instrument::bytes("output", &key, out.len() * 4);
                             let array = Array::from_shape_vec(shape, out).ok()?;
                             Some((key, array.into()))
                         }
                         "f16" => {
                             let out = output.outputU16(name);
+                            instrument::bytes("output", &key, out.len() * 2);
                             let array = reinterpret_u16_to_f16(Array::from_shape_vec(shape, out).ok()?);
                             Some((key, array.into()))
                         }
@@ -464,6 +452,90 @@ impl CoreMLModel {
         map.insert("output", desc.outputs());
         map
     }
+
+    pub fn metadata(&self) -> ModelMetadata {
+        metadata_from_description(&self.model.description())
+    }
+
+    /// Device placement of every operation, needs macOS 14.4 or newer.
+    pub fn compute_plan(&self) -> Result<ComputePlan, CoreMLError> {
+        ComputePlan::from_bridge(self.model.compute_plan_json())
+    }
+
+    pub fn class_labels(&self) -> Vec<Label> {
+        self.labels().to_vec()
+    }
+
+    fn labels(&self) -> &[Label] {
+        self.class_labels.get_or_init(|| {
+            let desc = self.model.description();
+            let labels = desc.class_labels_int();
+            if !labels.is_empty() {
+                return labels.into_iter().map(Label::Int64).collect();
+            }
+            let labels = desc.class_labels_string();
+            if !labels.is_empty() {
+                return labels.into_iter().map(Label::String).collect();
+            }
+            if desc.predicted_probabilities_name().is_none() {
+                return vec![];
+            }
+            // CoreML only lists class labels from macOS 14 on
+            let spec = match (&self.asset, &self.source) {
+                (Some(asset), _) => ModelSpec::from_bytes(asset),
+                (None, Some(path)) => ModelSpec::open(path),
+                (None, None) => return vec![],
+            };
+            spec.ok()
+                .and_then(|spec| spec.class_labels)
+                .unwrap_or_default()
+        })
+    }
+
+    pub fn classify(&mut self, k: usize) -> Result<Vec<(Label, f64)>, CoreMLError> {
+        let desc = self.model.description();
+        let Some(name) = desc.predicted_probabilities_name() else {
+            return Err(CoreMLError::NotAClassifier);
+        };
+        let int_labels = matches!(self.labels().first(), Some(Label::Int64(_)));
+        let output = {
+            let _stage = stage!("predict");
+            self.model.predict_classifier()
+        };
+        if let Some(err) = output.getError() {
+            return Err(CoreMLError::UnknownError(err));
+        }
+        let scores = output
+            .dictionaryKeys(name.clone())
+            .into_iter()
+            .zip(output.dictionaryValues(name))
+            .map(|(key, p)| {
+                let label = match key.parse::<i64>() {
+                    Ok(i) if int_labels => Label::Int64(i),
+                    _ => Label::String(key),
+                };
+                (label, p)
+            })
+            .collect();
+        Ok(top_k(scores, k))
+    }
+}
+
+pub(crate) fn metadata_from_description(desc: &ModelDescription) -> ModelMetadata {
+    ModelMetadata {
+        short_description: desc.metadata("description".to_string()),
+        version: desc.metadata("version".to_string()),
+        author: desc.metadata("author".to_string()),
+        license: desc.metadata("license".to_string()),
+        user_defined: desc
+            .user_defined_keys()
+            .into_iter()
+            .filter_map(|key| {
+                let value = desc.user_defined_value(key.clone())?;
+                Some((key, value))
+            })
+            .collect(),
+    }
 }
 
 fn reinterpret_u16_to_f16(input: ndarray::ArrayD<u16>) -> ndarray::ArrayD<half::f16> {
@@ -487,3 +559,50 @@ fn reinterpret_u16_to_f16(input: ndarray::ArrayD<u16>) -> ndarray::ArrayD<half::
     // TODO SA: we know unwrap won't cause ShapeError, but avoid unwrap regardless
     ndarray::ArrayD::from_shape_vec(ndarray::IxDyn(&shape), raw_vec_f16).unwrap()
 }
+
+/// Required inputs as listed by the loaded model, with their default shape.
+pub(crate) fn input_specs(desc: &ModelDescription) -> Result<Vec<InputSpec>, CoreMLError> {
+    desc.required_input_names()
+        .into_iter()
+        .map(|name| {
+            let data_type = match desc.input_type(name.clone()).as_str() {
+                "f32" => ArrayDataType::Float32,
+                "f16" => ArrayDataType::Float16,
+                "i32" => ArrayDataType::Int32,
+                "f64" => ArrayDataType::Float64,
+                _ => {
+                    return Err(CoreMLError::UnknownError(format!(
+                        "can't synthesize input '{name}', not a multi array"
+                    )))
+                }
+            };
+            Ok(InputSpec {
+                shape: desc.input_shape(name.clone()),
+                name,
+                data_type,
+                flexibility: ShapeFlexibility::Fixed,
+                is_optional: false,
+            })
+        })
+        .collect()
+}
+
+/// [`input_specs`] for a warm up, failing up front if an input has a type outside of
+/// `bindable` so nothing is bound for models warm ups can't run on.
+pub(crate) fn warm_up_specs(
+    desc: &ModelDescription,
+    bindable: &[ArrayDataType],
+) -> Result<Vec<InputSpec>, CoreMLError> {
+    let specs = input_specs(desc)?;
+    if let Some(spec) = specs
+        .iter()
+        .find(|spec| !bindable.contains(&spec.data_type))
+    {
+        return Err(CoreMLError::UnknownError(format!(
+            "can't warm up, input '{}' is {} which can't be bound yet",
+            spec.name,
+            desc.input_type(spec.name.clone())
+        )));
+    }
+    Ok(specs)
+}
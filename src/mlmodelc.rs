@@ -0,0 +1,311 @@
+//! Pure-Rust checks of the compiled `.mlmodelc` directory layout, to detect partial or
+//! stale compiled models without asking CoreML to load them.
+//!
+//! ```text
+//! model.mlmodelc/
+//!     coremldata.bin              compiled spec, always present
+//!     metadata.json               description, inputs and outputs
+//!     model.mil                   ML Program
+//!     weights/weight.bin          ML Program weights referenced from model.mil
+//!     model.espresso.net          neural network
+//!     model.espresso.shape
+//!     model.espresso.weights
+//!     model0/, model1/, ...       pipeline sub models, laid out the same
+//! ```
+
+use crate::{archive::enclosed_name, mlmodel::CoreMLError, weights::WeightFile};
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+};
+
+pub const COREMLDATA: &str = "coremldata.bin";
+pub const METADATA: &str = "metadata.json";
+pub const MIL: &str = "model.mil";
+const ESPRESSO_NET: &str = "model.espresso.net";
+const ESPRESSO_SHAPE: &str = "model.espresso.shape";
+const ESPRESSO_WEIGHTS: &str = "model.espresso.weights";
+const MODEL_PATH: &str = "@model_path/";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompiledModelKind {
+    MLProgram,
+    NeuralNetwork,
+    Pipeline,
+    /// Models that only need `coremldata.bin`, like tree ensembles or GLMs
+    Other,
+}
+
+/// Something wrong with a file of the compiled model, paths are relative to its root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    Missing(PathBuf),
+    Corrupt(PathBuf, String),
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Problem::Missing(path) => write!(f, "{} is missing", path.display()),
+            Problem::Corrupt(path, reason) => write!(f, "{} is corrupt: {reason}", path.display()),
+        }
+    }
+}
+
+/// An input or output as listed in `metadata.json`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct FeatureSchema {
+    pub name: String,
+    /// e.g. `MultiArray`, `Image` or `Dictionary`
+    #[serde(rename = "type")]
+    pub feature_type: String,
+    /// e.g. `Float32`, empty for non array features
+    pub data_type: String,
+    /// Human readable type, e.g. `MultiArray (Float32 1 × 3 × 224 × 224)`
+    pub formatted_type: String,
+    pub short_description: String,
+    shape: String,
+    is_optional: String,
+}
+
+impl FeatureSchema {
+    /// Shape of array features, `None` if `metadata.json` has none.
+    pub fn shape(&self) -> Option<Vec<usize>> {
+        let dims = self.shape.trim().strip_prefix('[')?.strip_suffix(']')?;
+        if dims.trim().is_empty() {
+            return Some(vec![]);
+        }
+        dims.split(',').map(|d| d.trim().parse().ok()).collect()
+    }
+
+    pub fn is_optional(&self) -> bool {
+        self.is_optional == "1"
+    }
+}
+
+/// The parts of `metadata.json` describing the model.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CompiledMetadata {
+    pub specification_version: Option<i64>,
+    pub short_description: Option<String>,
+    pub author: Option<String>,
+    pub license: Option<String>,
+    pub version: Option<String>,
+    pub user_defined_metadata: HashMap<String, String>,
+    pub input_schema: Vec<FeatureSchema>,
+    pub output_schema: Vec<FeatureSchema>,
+    model_type: BTreeMap<String, String>,
+}
+
+impl CompiledMetadata {
+    /// e.g. `MLModelType_mlProgram` or `MLModelType_neuralNetwork`.
+    pub fn model_type(&self) -> Option<&str> {
+        self.model_type.get("name").map(String::as_str)
+    }
+}
+
+/// An `.mlmodelc` directory on disk.
+#[derive(Debug, Clone)]
+pub struct MLModelC {
+    path: PathBuf,
+    metadata: CompiledMetadata,
+}
+
+impl MLModelC {
+    /// Reads `metadata.json` of the compiled model at `path`, the rest of the layout is not
+    /// checked, use `validate` or `problems` for that.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, CoreMLError> {
+        let path = path.as_ref().to_path_buf();
+        if !path.is_dir() {
+            return Err(CoreMLError::BadCompiledModel(format!(
+                "{} is not a directory",
+                path.display()
+            )));
+        }
+        let buf = std::fs::read(path.join(METADATA)).map_err(|err| {
+            CoreMLError::BadCompiledModel(format!(
+                "failed to read {}: {err}",
+                path.join(METADATA).display()
+            ))
+        })?;
+        // a list with one entry per model, pipelines included
+        let metadata = serde_json::from_slice::<Vec<CompiledMetadata>>(&buf)
+            .map_err(|err| err.to_string())
+            .and_then(|list| list.into_iter().next().ok_or("no entries".to_string()))
+            .map_err(|err| CoreMLError::BadCompiledModel(format!("invalid {METADATA}: {err}")))?;
+        Ok(Self { path, metadata })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn metadata(&self) -> &CompiledMetadata {
+        &self.metadata
+    }
+
+    pub fn inputs(&self) -> &[FeatureSchema] {
+        &self.metadata.input_schema
+    }
+
+    pub fn outputs(&self) -> &[FeatureSchema] {
+        &self.metadata.output_schema
+    }
+
+    pub fn kind(&self) -> CompiledModelKind {
+        model_kind(&self.path)
+    }
+
+    /// Every missing or corrupt file, empty if the layout is complete.
+    pub fn problems(&self) -> Vec<Problem> {
+        let mut problems = vec![];
+        check_dir(&self.path, Path::new(""), &mut problems);
+        problems
+    }
+
+    /// Fails with all problems found if the layout is not complete.
+    pub fn validate(&self) -> Result<(), CoreMLError> {
+        let problems = self.problems();
+        if problems.is_empty() {
+            return Ok(());
+        }
+        let problems: Vec<_> = problems.iter().map(Problem::to_string).collect();
+        Err(CoreMLError::BadCompiledModel(format!(
+            "{}: {}",
+            self.path.display(),
+            problems.join(", ")
+        )))
+    }
+}
+
+fn model_kind(dir: &Path) -> CompiledModelKind {
+    if dir.join(MIL).exists() {
+        CompiledModelKind::MLProgram
+    } else if [ESPRESSO_NET, ESPRESSO_SHAPE, ESPRESSO_WEIGHTS]
+        .iter()
+        .any(|name| dir.join(name).exists())
+    {
+        CompiledModelKind::NeuralNetwork
+    } else if dir.join("model0").is_dir() {
+        CompiledModelKind::Pipeline
+    } else {
+        CompiledModelKind::Other
+    }
+}
+
+/// Checks the model in `root.join(rel)`, recursing into pipeline sub models.
+fn check_dir(root: &Path, rel: &Path, problems: &mut Vec<Problem>) {
+    let dir = root.join(rel);
+    match std::fs::metadata(dir.join(COREMLDATA)) {
+        Ok(meta) if meta.len() == 0 => {
+            problems.push(Problem::Corrupt(rel.join(COREMLDATA), "empty".to_string()))
+        }
+        Ok(_) => {}
+        Err(_) => problems.push(Problem::Missing(rel.join(COREMLDATA))),
+    }
+    match model_kind(&dir) {
+        CompiledModelKind::MLProgram => check_program(&dir, rel, problems),
+        CompiledModelKind::NeuralNetwork => {
+            for name in [ESPRESSO_NET, ESPRESSO_SHAPE] {
+                match std::fs::read(dir.join(name)) {
+                    Ok(buf) => {
+                        if let Err(err) = serde_json::from_slice::<serde_json::Value>(&buf) {
+                            problems.push(Problem::Corrupt(rel.join(name), err.to_string()));
+                        }
+                    }
+                    Err(_) => problems.push(Problem::Missing(rel.join(name))),
+                }
+            }
+            if !dir.join(ESPRESSO_WEIGHTS).is_file() {
+                problems.push(Problem::Missing(rel.join(ESPRESSO_WEIGHTS)));
+            }
+        }
+        CompiledModelKind::Pipeline => {
+            for i in 0.. {
+                let sub = rel.join(format!("model{i}"));
+                if !root.join(&sub).is_dir() {
+                    break;
+                }
+                check_dir(root, &sub, problems);
+            }
+        }
+        CompiledModelKind::Other => {}
+    }
+}
+
+/// Checks that every weight file `model.mil` refers to exists and has a blob at the
+/// referenced offset.
+fn check_program(dir: &Path, rel: &Path, problems: &mut Vec<Problem>) {
+    let mil = match std::fs::read(dir.join(MIL)).map(String::from_utf8) {
+        Ok(Ok(mil)) => mil,
+        Ok(Err(_)) => {
+            problems.push(Problem::Corrupt(rel.join(MIL), "not utf8".to_string()));
+            return;
+        }
+        Err(_) => {
+            problems.push(Problem::Missing(rel.join(MIL)));
+            return;
+        }
+    };
+    if !mil.contains("program(") {
+        problems.push(Problem::Corrupt(
+            rel.join(MIL),
+            "no program declaration".to_string(),
+        ));
+        return;
+    }
+
+    let mut offsets: BTreeMap<&str, Vec<u64>> = BTreeMap::new();
+    for (file, offset) in weight_references(&mil) {
+        offsets.entry(file).or_default().extend(offset);
+    }
+    for (file, offsets) in offsets {
+        let Some(file) = enclosed_name(file) else {
+            problems.push(Problem::Corrupt(
+                rel.join(MIL),
+                format!("weight file {file} is outside of the model"),
+            ));
+            continue;
+        };
+        let path = rel.join(&file);
+        if !dir.join(&file).is_file() {
+            problems.push(Problem::Missing(path));
+            continue;
+        }
+        let blobs = match WeightFile::read_blobs(dir.join(file)) {
+            Ok(blobs) => blobs,
+            Err(err) => {
+                problems.push(Problem::Corrupt(path, err.to_string()));
+                continue;
+            }
+        };
+        if let Some(offset) = offsets
+            .iter()
+            .find(|o| !blobs.iter().any(|blob| blob.offset == **o))
+        {
+            problems.push(Problem::Corrupt(
+                path,
+                format!("no blob at offset {offset} referenced from {MIL}"),
+            ));
+        }
+    }
+}
+
+/// (file relative to the model, blob offset) of every
+/// `BLOBFILE(path = string("@model_path/..."), offset = uint64(...))` in `mil`.
+fn weight_references(mil: &str) -> impl Iterator<Item = (&str, Option<u64>)> {
+    mil.match_indices(MODEL_PATH).filter_map(|(start, _)| {
+        let rest = &mil[start + MODEL_PATH.len()..];
+        let file = &rest[..rest.find('"')?];
+        let after = &rest[file.len()..];
+        let statement = &after[..after.find(';').unwrap_or(after.len())];
+        let offset = statement.find("uint64(").and_then(|i| {
+            let digits = &statement[i + "uint64(".len()..];
+            digits[..digits.find(')')?].trim().parse().ok()
+        });
+        Some((file, offset))
+    })
+}
@@ -0,0 +1,290 @@
+//! Pure-Rust reader and writer for the `.mlpackage` directory layout.
+//!
+//! ```text
+//! model.mlpackage/
+//!     Manifest.json
+//!     Data/com.apple.CoreML/model.mlmodel
+//!     Data/com.apple.CoreML/weights/weight.bin
+//! ```
+//!
+//! Every item under `Data/` is listed in `Manifest.json`, the spec is the item
+//! named by `rootModelIdentifier`.
+
+use crate::{archive::enclosed_name, mlmodel::CoreMLError};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+pub const MANIFEST: &str = "Manifest.json";
+pub const DATA_DIR: &str = "Data";
+pub const AUTHOR: &str = "com.apple.CoreML";
+const SPEC_DESCRIPTION: &str = "CoreML Model Specification";
+const WEIGHTS_DESCRIPTION: &str = "CoreML Model Weights";
+const WEIGHTS_NAME: &str = "weights";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Manifest {
+    pub file_format_version: String,
+    pub item_info_entries: BTreeMap<String, ItemInfo>,
+    pub root_model_identifier: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ItemInfo {
+    pub author: String,
+    pub description: String,
+    pub name: String,
+    /// Relative to the package `Data` dir
+    pub path: String,
+}
+
+impl Manifest {
+    pub fn from_slice(buf: &[u8]) -> Result<Self, CoreMLError> {
+        serde_json::from_slice(buf)
+            .map_err(|err| CoreMLError::BadPackage(format!("invalid {MANIFEST}: {err}")))
+    }
+
+    pub fn to_vec(&self) -> Result<Vec<u8>, CoreMLError> {
+        serde_json::to_vec_pretty(self)
+            .map_err(|err| CoreMLError::BadPackage(format!("failed to write {MANIFEST}: {err}")))
+    }
+
+    pub fn root_item(&self) -> Result<&ItemInfo, CoreMLError> {
+        self.item_info_entries
+            .get(&self.root_model_identifier)
+            .ok_or_else(|| {
+                CoreMLError::BadPackage(format!(
+                    "{MANIFEST} has no item for the root model {}",
+                    self.root_model_identifier
+                ))
+            })
+    }
+
+    /// The weights item, if the model has external weights.
+    pub fn weights_item(&self) -> Option<&ItemInfo> {
+        self.item_info_entries
+            .values()
+            .find(|item| item.name == WEIGHTS_NAME || item.description == WEIGHTS_DESCRIPTION)
+    }
+}
+
+/// An `.mlpackage` directory on disk.
+#[derive(Debug, Clone)]
+pub struct MLPackage {
+    path: PathBuf,
+    manifest: Manifest,
+}
+
+impl MLPackage {
+    /// Reads `Manifest.json` of the package at `path`, the items it lists are not checked,
+    /// use `validate` for that.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, CoreMLError> {
+        let path = path.as_ref().to_path_buf();
+        let buf = std::fs::read(path.join(MANIFEST)).map_err(|err| {
+            CoreMLError::BadPackage(format!(
+                "failed to read {}: {err}",
+                path.join(MANIFEST).display()
+            ))
+        })?;
+        let manifest = Manifest::from_slice(&buf)?;
+        Ok(Self { path, manifest })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    /// Path on disk of an item listed in the manifest.
+    pub fn item_path(&self, item: &ItemInfo) -> Result<PathBuf, CoreMLError> {
+        Ok(self.path.join(DATA_DIR).join(relative_path(&item.path)?))
+    }
+
+    /// Path of the root model spec, usually `Data/com.apple.CoreML/model.mlmodel`.
+    pub fn spec_path(&self) -> Result<PathBuf, CoreMLError> {
+        self.item_path(self.manifest.root_item()?)
+    }
+
+    pub fn read_spec(&self) -> Result<Vec<u8>, CoreMLError> {
+        std::fs::read(self.spec_path()?).map_err(CoreMLError::IoError)
+    }
+
+    /// Weights dir of ML Program models, `None` for models with weights inside the spec.
+    pub fn weights_dir(&self) -> Result<Option<PathBuf>, CoreMLError> {
+        self.manifest
+            .weights_item()
+            .map(|item| self.item_path(item))
+            .transpose()
+    }
+
+    /// Weight blob files as (name relative to the weights dir, size in bytes), sorted by name.
+    pub fn weight_files(&self) -> Result<Vec<(PathBuf, u64)>, CoreMLError> {
+        match self.weights_dir()? {
+            Some(dir) if dir.is_dir() => crate::cache::files(&dir),
+            _ => Ok(vec![]),
+        }
+    }
+
+    /// Checks that the root model and every other item listed in the manifest exist.
+    pub fn validate(&self) -> Result<(), CoreMLError> {
+        let spec = self.spec_path()?;
+        if !spec.is_file() {
+            return Err(CoreMLError::BadPackage(format!(
+                "root model {} listed in {MANIFEST} is missing",
+                spec.display()
+            )));
+        }
+        for (id, item) in &self.manifest.item_info_entries {
+            if !self.item_path(item)?.exists() {
+                return Err(CoreMLError::BadPackage(format!(
+                    "item {id} ({}) listed in {MANIFEST} is missing",
+                    item.path
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Assembles a new `.mlpackage` from a spec and weight blobs.
+///
+/// ```no_run
+/// # use coreml_rs::mlpackage::MLPackageBuilder;
+/// let spec = std::fs::read("model.mlmodel").unwrap();
+/// let weights = std::fs::read("weight.bin").unwrap();
+/// MLPackageBuilder::new(spec)
+///     .weight("weight.bin", weights)
+///     .write("model.mlpackage")
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct MLPackageBuilder {
+    spec: Vec<u8>,
+    spec_name: String,
+    author: String,
+    description: String,
+    weights: Vec<(String, Vec<u8>)>,
+}
+
+impl MLPackageBuilder {
+    pub fn new(spec: Vec<u8>) -> Self {
+        Self {
+            spec,
+            spec_name: "model.mlmodel".to_string(),
+            author: AUTHOR.to_string(),
+            description: SPEC_DESCRIPTION.to_string(),
+            weights: vec![],
+        }
+    }
+
+    /// Author of the spec item, defaults to `com.apple.CoreML`.
+    pub fn author(mut self, author: impl Into<String>) -> Self {
+        self.author = author.into();
+        self
+    }
+
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.description = description.into();
+        self
+    }
+
+    /// Adds a weight blob stored as `weights/<name>`, ML Program specs reference the
+    /// default blob as `@model_path/weights/weight.bin`.
+    pub fn weight(mut self, name: impl Into<String>, blob: Vec<u8>) -> Self {
+        self.weights.push((name.into(), blob));
+        self
+    }
+
+    pub fn manifest(&self) -> Manifest {
+        let spec_path = format!("{AUTHOR}/{}", self.spec_name);
+        let spec_id = item_identifier(&spec_path);
+        let mut item_info_entries = BTreeMap::new();
+        item_info_entries.insert(
+            spec_id.clone(),
+            ItemInfo {
+                author: self.author.clone(),
+                description: self.description.clone(),
+                name: self.spec_name.clone(),
+                path: spec_path,
+            },
+        );
+        if !self.weights.is_empty() {
+            let weights_path = format!("{AUTHOR}/{WEIGHTS_NAME}");
+            item_info_entries.insert(
+                item_identifier(&weights_path),
+                ItemInfo {
+                    author: AUTHOR.to_string(),
+                    description: WEIGHTS_DESCRIPTION.to_string(),
+                    name: WEIGHTS_NAME.to_string(),
+                    path: weights_path,
+                },
+            );
+        }
+        Manifest {
+            file_format_version: "1.0.0".to_string(),
+            item_info_entries,
+            root_model_identifier: spec_id,
+        }
+    }
+
+    /// Writes the package to `path`, which must not exist yet.
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<MLPackage, CoreMLError> {
+        let path = path.as_ref();
+        if path.exists() {
+            return Err(CoreMLError::BadPackage(format!(
+                "{} already exists",
+                path.display()
+            )));
+        }
+        let manifest = self.manifest();
+        let data = path.join(DATA_DIR).join(AUTHOR);
+        std::fs::create_dir_all(&data).map_err(CoreMLError::IoError)?;
+        std::fs::write(data.join(&self.spec_name), &self.spec).map_err(CoreMLError::IoError)?;
+        if !self.weights.is_empty() {
+            let weights = data.join(WEIGHTS_NAME);
+            std::fs::create_dir_all(&weights).map_err(CoreMLError::IoError)?;
+            for (name, blob) in &self.weights {
+                std::fs::write(weights.join(relative_path(name)?), blob)
+                    .map_err(CoreMLError::IoError)?;
+            }
+        }
+        std::fs::write(path.join(MANIFEST), manifest.to_vec()?).map_err(CoreMLError::IoError)?;
+        let package = MLPackage {
+            path: path.to_path_buf(),
+            manifest,
+        };
+        package.validate()?;
+        Ok(package)
+    }
+}
+
+/// Deterministic uppercase UUID style identifier, so the same inputs produce identical packages.
+fn item_identifier(path: &str) -> String {
+    let digest = Sha256::digest(path.as_bytes());
+    let hex = digest[..16]
+        .iter()
+        .map(|b| format!("{b:02X}"))
+        .collect::<String>();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+/// Rejects item paths that would point outside of the package.
+pub(crate) fn relative_path(path: &str) -> Result<PathBuf, CoreMLError> {
+    enclosed_name(path).ok_or_else(|| {
+        CoreMLError::BadPackage(format!("item path {path} points outside of the package"))
+    })
+}
@@ -0,0 +1,208 @@
+//! Reading and writing arrays in numpy's `.npy` format, and `.npz` archives of them,
+//! so fixtures can be produced and inspected with `np.load` / `np.savez`.
+//!
+//! ```text
+//! .npy: "\x93NUMPY", u8 major, u8 minor, u16 (v1) or u32 (v2, v3) header length,
+//!       header "{'descr': '<f4', 'fortran_order': False, 'shape': (1, 3), }" padded
+//!       with spaces to a multiple of 64 and ending in '\n', then the data in C order
+//! .npz: zip archive of `<name>.npy` entries
+//! ```
+//!
+//! Only little endian arrays of the element types `MLArray` holds are supported.
+
+use crate::{mlarray::MLArray, mlmodel::CoreMLError};
+use half::f16;
+use ndarray::{Array, IxDyn};
+use std::io::{Read, Seek, Write};
+
+const MAGIC: &[u8; 6] = b"\x93NUMPY";
+const ALIGNMENT: usize = 64;
+
+/// Encodes `array` as a version 1.0 `.npy` file.
+pub fn to_npy(array: &MLArray) -> Vec<u8> {
+    let shape = match array.shape() {
+        [dim] => format!("({dim},)"),
+        shape => format!(
+            "({})",
+            shape
+                .iter()
+                .map(|d| d.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+    let mut header = format!(
+        "{{'descr': '{}', 'fortran_order': False, 'shape': {shape}, }}",
+        descr(array)
+    );
+    let unpadded = MAGIC.len() + 4 + header.len() + 1;
+    header.push_str(&" ".repeat(unpadded.next_multiple_of(ALIGNMENT) - unpadded));
+    header.push('\n');
+
+    let mut out = Vec::with_capacity(MAGIC.len() + 4 + header.len() + array.nbytes());
+    out.extend(MAGIC);
+    out.extend([1, 0]);
+    out.extend((header.len() as u16).to_le_bytes());
+    out.extend(header.as_bytes());
+    match array {
+        MLArray::Float32Array(a) => a.iter().for_each(|v| out.extend(v.to_le_bytes())),
+        MLArray::Float16Array(a) => a.iter().for_each(|v| out.extend(v.to_le_bytes())),
+        MLArray::Float64Array(a) => a.iter().for_each(|v| out.extend(v.to_le_bytes())),
+        MLArray::Int32Array(a) => a.iter().for_each(|v| out.extend(v.to_le_bytes())),
+        MLArray::Int16Array(a) => a.iter().for_each(|v| out.extend(v.to_le_bytes())),
+        MLArray::Int8Array(a) => a.iter().for_each(|v| out.extend(v.to_le_bytes())),
+        MLArray::UInt32Array(a) => a.iter().for_each(|v| out.extend(v.to_le_bytes())),
+        MLArray::UInt16Array(a) => a.iter().for_each(|v| out.extend(v.to_le_bytes())),
+        MLArray::UInt8Array(a) => a.iter().for_each(|v| out.extend(v.to_le_bytes())),
+    }
+    out
+}
+
+fn descr(array: &MLArray) -> &'static str {
+    match array {
+        MLArray::Float32Array(_) => "<f4",
+        MLArray::Float16Array(_) => "<f2",
+        MLArray::Float64Array(_) => "<f8",
+        MLArray::Int32Array(_) => "<i4",
+        MLArray::Int16Array(_) => "<i2",
+        MLArray::Int8Array(_) => "|i1",
+        MLArray::UInt32Array(_) => "<u4",
+        MLArray::UInt16Array(_) => "<u2",
+        MLArray::UInt8Array(_) => "|u1",
+    }
+}
+
+/// Decodes a `.npy` file.
+pub fn from_npy(buf: &[u8]) -> Result<MLArray, CoreMLError> {
+    if buf.len() < MAGIC.len() + 4 || &buf[..MAGIC.len()] != MAGIC {
+        return Err(bad_npy("not an npy file"));
+    }
+    let (header_len, header_start) = match buf[6] {
+        1 => (u16::from_le_bytes([buf[8], buf[9]]) as usize, 10),
+        2 | 3 if buf.len() >= 12 => (
+            u32::from_le_bytes(buf[8..12].try_into().unwrap()) as usize,
+            12,
+        ),
+        major => return Err(bad_npy(&format!("unsupported npy version {major}"))),
+    };
+    let header = buf
+        .get(header_start..header_start + header_len)
+        .and_then(|h| std::str::from_utf8(h).ok())
+        .ok_or_else(|| bad_npy("truncated header"))?;
+    let data = &buf[header_start + header_len..];
+
+    let descr = header_value(header, "descr")
+        .map(|v| v.trim_matches(|c| c == '\'' || c == '"'))
+        .ok_or_else(|| bad_npy("header has no descr"))?;
+    if header_value(header, "fortran_order") != Some("False") {
+        return Err(bad_npy("fortran ordered arrays are not supported"));
+    }
+    let shape = header_value(header, "shape")
+        .and_then(|v| {
+            v.trim_matches(|c| c == '(' || c == ')')
+                .split(',')
+                .map(str::trim)
+                .filter(|d| !d.is_empty())
+                .map(|d| d.parse::<usize>().ok())
+                .collect::<Option<Vec<_>>>()
+        })
+        .ok_or_else(|| bad_npy("header has no valid shape"))?;
+
+    let array = match descr {
+        "<f4" => decode(data, &shape, f32::from_le_bytes)?.into(),
+        "<f2" => decode(data, &shape, f16::from_le_bytes)?.into(),
+        "<f8" => decode(data, &shape, f64::from_le_bytes)?.into(),
+        "<i4" => decode(data, &shape, i32::from_le_bytes)?.into(),
+        "<i2" => decode(data, &shape, i16::from_le_bytes)?.into(),
+        "|i1" | "<i1" => decode(data, &shape, i8::from_le_bytes)?.into(),
+        "<u4" => decode(data, &shape, u32::from_le_bytes)?.into(),
+        "<u2" => decode(data, &shape, u16::from_le_bytes)?.into(),
+        "|u1" | "<u1" => decode(data, &shape, u8::from_le_bytes)?.into(),
+        descr => return Err(bad_npy(&format!("unsupported dtype {descr}"))),
+    };
+    Ok(array)
+}
+
+/// The raw value of `key` in the header dict, values are either quoted, a tuple or a word.
+fn header_value<'a>(header: &'a str, key: &str) -> Option<&'a str> {
+    let start = header
+        .find(&format!("'{key}'"))
+        .or_else(|| header.find(&format!("\"{key}\"")))?;
+    let rest = header[start + key.len() + 2..].trim_start();
+    let rest = rest.strip_prefix(':')?.trim_start();
+    let end = match rest.chars().next()? {
+        '(' => rest.find(')')? + 1,
+        q @ ('\'' | '"') => rest[1..].find(q)? + 2,
+        _ => rest.find([',', '}'])?,
+    };
+    Some(rest[..end].trim())
+}
+
+fn decode<T: Copy, const N: usize>(
+    data: &[u8],
+    shape: &[usize],
+    from_le_bytes: fn([u8; N]) -> T,
+) -> Result<Array<T, IxDyn>, CoreMLError> {
+    let size = shape
+        .iter()
+        .try_fold(N, |size, dim| size.checked_mul(*dim))
+        .ok_or_else(|| bad_npy(&format!("shape {shape:?} is too large")))?;
+    if data.len() < size {
+        return Err(bad_npy(&format!(
+            "expected {size} bytes of data, found {}",
+            data.len()
+        )));
+    }
+    let values = data[..size]
+        .chunks_exact(N)
+        .map(|c| from_le_bytes(c.try_into().unwrap()))
+        .collect();
+    Ok(Array::from_shape_vec(IxDyn(shape), values).unwrap())
+}
+
+/// Writes `arrays` as an `.npz` archive, each as `<name>.npy`.
+pub fn write_npz<'a>(
+    writer: impl Write + Seek,
+    arrays: impl IntoIterator<Item = (&'a str, &'a MLArray)>,
+) -> Result<(), CoreMLError> {
+    let mut zip = zip::ZipWriter::new(writer);
+    let options = zip::write::SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated)
+        .large_file(true);
+    for (name, array) in arrays {
+        zip.start_file(format!("{name}.npy"), options)
+            .map_err(|err| bad_npy(&err.to_string()))?;
+        zip.write_all(&to_npy(array))
+            .map_err(CoreMLError::IoError)?;
+    }
+    zip.finish().map_err(|err| bad_npy(&err.to_string()))?;
+    Ok(())
+}
+
+/// Reads every `.npy` entry of an `.npz` archive, named without the extension.
+pub fn read_npz(reader: impl Read + Seek) -> Result<Vec<(String, MLArray)>, CoreMLError> {
+    let mut archive = zip::ZipArchive::new(reader)
+        .map_err(|err| bad_npy(&format!("not an npz archive: {err}")))?;
+    let mut arrays = Vec::with_capacity(archive.len());
+    for i in 0..archive.len() {
+        let mut file = archive
+            .by_index(i)
+            .map_err(|err| bad_npy(&err.to_string()))?;
+        let Some(name) = file.name().strip_suffix(".npy").map(str::to_string) else {
+            continue;
+        };
+        // the size in the archive isn't trusted for an allocation up front
+        let mut buf = vec![];
+        file.read_to_end(&mut buf).map_err(CoreMLError::IoError)?;
+        let array = from_npy(&buf).map_err(|err| match err {
+            CoreMLError::BadNpy(reason) => bad_npy(&format!("{name}: {reason}")),
+            err => err,
+        })?;
+        arrays.push((name, array));
+    }
+    Ok(arrays)
+}
+
+fn bad_npy(reason: &str) -> CoreMLError {
+    CoreMLError::BadNpy(reason.to_string())
+}
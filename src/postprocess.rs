@@ -0,0 +1,250 @@
+//! Postprocessing of segmentation and detection outputs.
+//!
+//! ```no_run
+//! # use coreml_rs::{mlmodel::MLModelOutput, postprocess};
+//! # fn run(output: MLModelOutput) -> Result<(), coreml_rs::mlmodel::CoreMLError> {
+//! // [1, C, H, W] logits to a [1, H, W] class map
+//! let classes = postprocess::argmax(output.get("mask")?, 1)?;
+//! // [1, 1, H, W] logits to a binary mask
+//! let mask = postprocess::threshold(&postprocess::sigmoid(output.get("mask")?), 0.5);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{classifier, mlarray::MLArray, mlmodel::CoreMLError, preprocess::Letterbox};
+use ndarray::{ArrayD, Axis};
+
+/// Copies any array to f32, f16 is widened, f64 narrowed and integers are cast.
+pub fn to_f32(array: &MLArray) -> ArrayD<f32> {
+    match array {
+        MLArray::Float32Array(a) => a.clone(),
+        MLArray::Float16Array(a) => a.mapv(|v| v.to_f32()),
+        MLArray::Float64Array(a) => a.mapv(|v| v as f32),
+        MLArray::Int32Array(a) => a.mapv(|v| v as f32),
+        MLArray::Int16Array(a) => a.mapv(|v| v as f32),
+        MLArray::Int8Array(a) => a.mapv(|v| v as f32),
+        MLArray::UInt32Array(a) => a.mapv(|v| v as f32),
+        MLArray::UInt16Array(a) => a.mapv(|v| v as f32),
+        MLArray::UInt8Array(a) => a.mapv(|v| v as f32),
+    }
+}
+
+fn check_axis(array: &ArrayD<f32>, axis: usize) -> Result<(), CoreMLError> {
+    if axis >= array.ndim() || array.shape()[axis] == 0 {
+        return Err(CoreMLError::BadInputShape(format!(
+            "axis {axis} is out of bounds or empty for shape {:?}",
+            array.shape()
+        )));
+    }
+    Ok(())
+}
+
+/// Index of the largest value along `axis`, which is removed from the shape.
+///
+/// Ties resolve to the lowest index and NaNs are never picked over numbers.
+pub fn argmax(array: &MLArray, axis: usize) -> Result<MLArray, CoreMLError> {
+    let array = to_f32(array);
+    check_axis(&array, axis)?;
+    let out = array.map_axis(Axis(axis), |lane| {
+        let mut best = 0;
+        for (i, v) in lane.iter().enumerate() {
+            if *v > lane[best] || (lane[best].is_nan() && !v.is_nan()) {
+                best = i;
+            }
+        }
+        best as u32
+    });
+    Ok(out.into())
+}
+
+pub fn sigmoid(array: &MLArray) -> MLArray {
+    to_f32(array).mapv(|v| 1.0 / (1.0 + (-v).exp())).into()
+}
+
+/// Softmax along `axis`, e.g. over the class channel of `[1, C, H, W]` logits.
+pub fn softmax(array: &MLArray, axis: usize) -> Result<MLArray, CoreMLError> {
+    let mut array = to_f32(array);
+    check_axis(&array, axis)?;
+    let mut lane_buf = Vec::with_capacity(array.shape()[axis]);
+    for mut lane in array.lanes_mut(Axis(axis)) {
+        lane_buf.clear();
+        lane_buf.extend(lane.iter().copied());
+        for (dst, p) in lane.iter_mut().zip(classifier::softmax(&lane_buf)) {
+            *dst = p;
+        }
+    }
+    Ok(array.into())
+}
+
+/// Binary u8 mask, 1 where the value is above `threshold`.
+pub fn threshold(array: &MLArray, threshold: f32) -> MLArray {
+    to_f32(array).mapv(|v| (v > threshold) as u8).into()
+}
+
+/// Layout of the 4 box coordinates in a detection output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BoxFormat {
+    /// Corners `x1, y1, x2, y2`
+    #[default]
+    Xyxy,
+    /// Top left corner and size `x, y, w, h`
+    Xywh,
+    /// Center and size `cx, cy, w, h`
+    Cxcywh,
+}
+
+/// Axis aligned box by its corners.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingBox {
+    pub x1: f32,
+    pub y1: f32,
+    pub x2: f32,
+    pub y2: f32,
+}
+
+impl BoundingBox {
+    pub fn decode(coords: [f32; 4], format: BoxFormat) -> Self {
+        let [a, b, c, d] = coords;
+        match format {
+            BoxFormat::Xyxy => Self {
+                x1: a,
+                y1: b,
+                x2: c,
+                y2: d,
+            },
+            BoxFormat::Xywh => Self {
+                x1: a,
+                y1: b,
+                x2: a + c,
+                y2: b + d,
+            },
+            BoxFormat::Cxcywh => Self {
+                x1: a - c / 2.0,
+                y1: b - d / 2.0,
+                x2: a + c / 2.0,
+                y2: b + d / 2.0,
+            },
+        }
+    }
+
+    pub fn area(&self) -> f32 {
+        (self.x2 - self.x1).max(0.0) * (self.y2 - self.y1).max(0.0)
+    }
+
+    /// Intersection over union, 0 for boxes without area.
+    pub fn iou(&self, other: &BoundingBox) -> f32 {
+        let w = (self.x2.min(other.x2) - self.x1.max(other.x1)).max(0.0);
+        let h = (self.y2.min(other.y2) - self.y1.max(other.y1)).max(0.0);
+        let intersection = w * h;
+        let union = self.area() + other.area() - intersection;
+        if union <= 0.0 {
+            0.0
+        } else {
+            intersection / union
+        }
+    }
+
+    /// Maps a box in model input coordinates back onto the source image of a preprocessed input.
+    pub fn to_source(&self, letterbox: &Letterbox) -> Self {
+        let (x1, y1) = letterbox.to_source(self.x1, self.y1);
+        let (x2, y2) = letterbox.to_source(self.x2, self.y2);
+        Self { x1, y1, x2, y2 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Detection {
+    pub bbox: BoundingBox,
+    pub score: f32,
+    pub class: usize,
+}
+
+/// Decodes `[N, 4]` boxes and `[N, C]` class scores into detections scoring above
+/// `score_threshold`, keeping the best class of each box.
+///
+/// Leading dims of size 1 (`[1, N, 4]`) are accepted.
+pub fn decode_boxes(
+    boxes: &MLArray,
+    scores: &MLArray,
+    format: BoxFormat,
+    score_threshold: f32,
+) -> Result<Vec<Detection>, CoreMLError> {
+    let boxes = rows(boxes, Some(4))?;
+    let scores = rows(scores, None)?;
+    if boxes.nrows() != scores.nrows() {
+        return Err(CoreMLError::BadInputShape(format!(
+            "found {} boxes but {} score rows",
+            boxes.nrows(),
+            scores.nrows()
+        )));
+    }
+    let mut out = vec![];
+    for (b, s) in boxes.outer_iter().zip(scores.outer_iter()) {
+        let mut class = 0;
+        for (i, v) in s.iter().enumerate() {
+            if *v > s[class] {
+                class = i;
+            }
+        }
+        let Some(&score) = s.get(class) else {
+            continue;
+        };
+        if score > score_threshold {
+            out.push(Detection {
+                bbox: BoundingBox::decode([b[0], b[1], b[2], b[3]], format),
+                score,
+                class,
+            });
+        }
+    }
+    Ok(out)
+}
+
+/// Views an array as 2d, dropping leading dims of size 1.
+fn rows(array: &MLArray, cols: Option<usize>) -> Result<ndarray::Array2<f32>, CoreMLError> {
+    let array = to_f32(array);
+    let shape = array.shape();
+    let leading = shape.len().saturating_sub(2);
+    let valid = shape.len() >= 2
+        && shape[..leading].iter().all(|d| *d == 1)
+        && cols.is_none_or(|c| shape[shape.len() - 1] == c);
+    if !valid {
+        return Err(CoreMLError::BadInputShape(format!(
+            "expected a [N, {}] array found {shape:?}",
+            cols.map_or("C".to_string(), |c| c.to_string())
+        )));
+    }
+    let (n, c) = (shape[shape.len() - 2], shape[shape.len() - 1]);
+    array
+        .into_shape_with_order((n, c))
+        .map_err(|err| CoreMLError::BadInputShape(err.to_string()))
+}
+
+/// Greedy non-maximum suppression, dropping boxes overlapping a higher scoring box of the
+/// same class by more than `iou_threshold`. The result is sorted by descending score.
+pub fn nms(detections: Vec<Detection>, iou_threshold: f32) -> Vec<Detection> {
+    suppress(detections, iou_threshold, false)
+}
+
+/// Like [`nms`] but boxes suppress each other regardless of their class.
+pub fn nms_class_agnostic(detections: Vec<Detection>, iou_threshold: f32) -> Vec<Detection> {
+    suppress(detections, iou_threshold, true)
+}
+
+fn suppress(
+    mut detections: Vec<Detection>,
+    iou_threshold: f32,
+    class_agnostic: bool,
+) -> Vec<Detection> {
+    detections.sort_by(|a, b| b.score.total_cmp(&a.score));
+    let mut keep: Vec<Detection> = Vec::with_capacity(detections.len());
+    for det in detections {
+        let suppressed = keep.iter().any(|k| {
+            (class_agnostic || k.class == det.class) && k.bbox.iou(&det.bbox) > iou_threshold
+        });
+        if !suppressed {
+            keep.push(det);
+        }
+    }
+    keep
+}
@@ -0,0 +1,306 @@
+//! Turning raw interleaved (HWC) u8 pixel buffers into model inputs.
+//!
+//! ```no_run
+//! # use coreml_rs::preprocess::{ImageBuffer, ImagePreprocessor, Layout};
+//! # let pixels = vec![0u8; 640 * 480 * 3];
+//! let preprocessor = ImagePreprocessor::new(512, 512)
+//!     .layout(Layout::Nchw)
+//!     .mean_std([0.485, 0.456, 0.406], [0.229, 0.224, 0.225])
+//!     .letterbox(114);
+//! let (input, letterbox) = preprocessor
+//!     .process(&ImageBuffer::new(&pixels, 640, 480, 3))
+//!     .unwrap();
+//! ```
+
+use crate::{
+    mlarray::MLArray,
+    mlmodel::CoreMLError,
+    spec::{ColorSpace, FeatureType, ModelSpec},
+};
+use ndarray::Array;
+
+/// Interleaved u8 pixels, 1 (gray), 3 (RGB) or 4 (RGBA) channels per pixel.
+#[derive(Debug, Clone, Copy)]
+pub struct ImageBuffer<'a> {
+    pub data: &'a [u8],
+    pub width: usize,
+    pub height: usize,
+    pub channels: usize,
+}
+
+impl<'a> ImageBuffer<'a> {
+    pub fn new(data: &'a [u8], width: usize, height: usize, channels: usize) -> Self {
+        Self {
+            data,
+            width,
+            height,
+            channels,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Layout {
+    /// `[1, C, H, W]`
+    #[default]
+    Nchw,
+    /// `[1, H, W, C]`
+    Nhwc,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ChannelOrder {
+    #[default]
+    Rgb,
+    Bgr,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Resize {
+    /// Input must already have the target size
+    #[default]
+    None,
+    /// Bilinear resize ignoring the aspect ratio
+    Stretch,
+    /// Bilinear resize keeping the aspect ratio, padding the rest with `fill`
+    Letterbox { fill: u8 },
+}
+
+/// Where the source image ended up in the model input, to map outputs back onto it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Letterbox {
+    pub scale_x: f32,
+    pub scale_y: f32,
+    pub pad_x: f32,
+    pub pad_y: f32,
+}
+
+impl Letterbox {
+    /// Maps a point in model input coordinates back to source image coordinates.
+    pub fn to_source(&self, x: f32, y: f32) -> (f32, f32) {
+        (
+            (x - self.pad_x) / self.scale_x,
+            (y - self.pad_y) / self.scale_y,
+        )
+    }
+}
+
+/// Converts [`ImageBuffer`]s to f32 `MLArray`s, applying `pixel * scale[c] + bias[c]` per channel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImagePreprocessor {
+    pub width: usize,
+    pub height: usize,
+    pub layout: Layout,
+    pub channel_order: ChannelOrder,
+    /// Output a single gray channel instead of three color channels
+    pub grayscale: bool,
+    pub scale: [f32; 3],
+    pub bias: [f32; 3],
+    pub resize: Resize,
+}
+
+impl ImagePreprocessor {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            layout: Layout::Nchw,
+            channel_order: ChannelOrder::Rgb,
+            grayscale: false,
+            scale: [1.0; 3],
+            bias: [0.0; 3],
+            resize: Resize::None,
+        }
+    }
+
+    /// Preprocessing CoreML applies to the image input `name`, from its size, color space and
+    /// neural network image scaler.
+    pub fn from_spec(spec: &ModelSpec, name: &str) -> Result<Self, CoreMLError> {
+        let Some(FeatureType::Image(image)) = spec.input(name).map(|f| &f.feature_type) else {
+            return Err(CoreMLError::BadInputShape(format!(
+                "input feature '{name}' is not an image"
+            )));
+        };
+        let mut out = Self::new(image.width as usize, image.height as usize);
+        match image.color_space {
+            ColorSpace::Bgr => out.channel_order = ChannelOrder::Bgr,
+            ColorSpace::Grayscale | ColorSpace::GrayscaleFloat16 => out.grayscale = true,
+            ColorSpace::Rgb => {}
+        }
+        if let Some(scaler) = spec.image_scalers.get(name) {
+            out.scale = [scaler.channel_scale; 3];
+            // bias is in output channel order
+            out.bias = match (out.grayscale, out.channel_order) {
+                (true, _) => [scaler.gray_bias; 3],
+                (false, ChannelOrder::Rgb) => {
+                    [scaler.red_bias, scaler.green_bias, scaler.blue_bias]
+                }
+                (false, ChannelOrder::Bgr) => {
+                    [scaler.blue_bias, scaler.green_bias, scaler.red_bias]
+                }
+            };
+        }
+        Ok(out)
+    }
+
+    pub fn layout(mut self, layout: Layout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    pub fn channel_order(mut self, channel_order: ChannelOrder) -> Self {
+        self.channel_order = channel_order;
+        self
+    }
+
+    /// CoreML style scale and bias, `bias` in output channel order.
+    pub fn scale_bias(mut self, scale: f32, bias: [f32; 3]) -> Self {
+        self.scale = [scale; 3];
+        self.bias = bias;
+        self
+    }
+
+    /// torchvision style normalization with mean and std in the `[0, 1]` range.
+    pub fn mean_std(mut self, mean: [f32; 3], std: [f32; 3]) -> Self {
+        for c in 0..3 {
+            self.scale[c] = 1.0 / (255.0 * std[c]);
+            self.bias[c] = -mean[c] / std[c];
+        }
+        self
+    }
+
+    pub fn stretch(mut self) -> Self {
+        self.resize = Resize::Stretch;
+        self
+    }
+
+    pub fn letterbox(mut self, fill: u8) -> Self {
+        self.resize = Resize::Letterbox { fill };
+        self
+    }
+
+    fn channels(&self) -> usize {
+        if self.grayscale {
+            1
+        } else {
+            3
+        }
+    }
+
+    /// Shape of the produced input.
+    pub fn shape(&self) -> Vec<usize> {
+        match self.layout {
+            Layout::Nchw => vec![1, self.channels(), self.height, self.width],
+            Layout::Nhwc => vec![1, self.height, self.width, self.channels()],
+        }
+    }
+
+    pub fn process(&self, image: &ImageBuffer) -> Result<(MLArray, Letterbox), CoreMLError> {
+        if !matches!(image.channels, 1 | 3 | 4) {
+            return Err(CoreMLError::BadInputShape(format!(
+                "expected 1, 3 or 4 channels found {}",
+                image.channels
+            )));
+        }
+        let expected = image.width * image.height * image.channels;
+        if image.data.len() != expected {
+            return Err(CoreMLError::BadInputShape(format!(
+                "expected {expected} bytes for a {}x{}x{} image found {}",
+                image.width,
+                image.height,
+                image.channels,
+                image.data.len()
+            )));
+        }
+        if image.width == 0 || image.height == 0 {
+            return Err(CoreMLError::BadInputShape("empty image".to_string()));
+        }
+        if self.width == 0 || self.height == 0 {
+            return Err(CoreMLError::BadInputShape(format!(
+                "can't resize to an empty {}x{} input",
+                self.width, self.height
+            )));
+        }
+
+        // where the image lands in the output, (x, y, w, h)
+        let (rect, fill) = match self.resize {
+            Resize::None => {
+                if image.width != self.width || image.height != self.height {
+                    return Err(CoreMLError::BadInputShape(format!(
+                        "expected a {}x{} image found {}x{}",
+                        self.width, self.height, image.width, image.height
+                    )));
+                }
+                ((0, 0, self.width, self.height), 0)
+            }
+            Resize::Stretch => ((0, 0, self.width, self.height), 0),
+            Resize::Letterbox { fill } => {
+                let s = (self.width as f32 / image.width as f32)
+                    .min(self.height as f32 / image.height as f32);
+                let w = ((image.width as f32 * s).round() as usize).clamp(1, self.width);
+                let h = ((image.height as f32 * s).round() as usize).clamp(1, self.height);
+                (((self.width - w) / 2, (self.height - h) / 2, w, h), fill)
+            }
+        };
+        let (rx, ry, rw, rh) = rect;
+        let letterbox = Letterbox {
+            scale_x: rw as f32 / image.width as f32,
+            scale_y: rh as f32 / image.height as f32,
+            pad_x: rx as f32,
+            pad_y: ry as f32,
+        };
+
+        let channels = self.channels();
+        let mut out = vec![0f32; channels * self.width * self.height];
+        let mut pixel = [0f32; 3];
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let inside = x >= rx && x < rx + rw && y >= ry && y < ry + rh;
+                if inside {
+                    // sample at pixel centers
+                    let sx = ((x - rx) as f32 + 0.5) / letterbox.scale_x - 0.5;
+                    let sy = ((y - ry) as f32 + 0.5) / letterbox.scale_y - 0.5;
+                    sample_rgb(image, sx, sy, &mut pixel);
+                } else {
+                    pixel = [fill as f32; 3];
+                }
+                if self.grayscale {
+                    pixel[0] = 0.299 * pixel[0] + 0.587 * pixel[1] + 0.114 * pixel[2];
+                } else if self.channel_order == ChannelOrder::Bgr {
+                    pixel.swap(0, 2);
+                }
+                for (c, value) in pixel.iter().take(channels).enumerate() {
+                    let idx = match self.layout {
+                        Layout::Nchw => (c * self.height + y) * self.width + x,
+                        Layout::Nhwc => (y * self.width + x) * channels + c,
+                    };
+                    out[idx] = value * self.scale[c] + self.bias[c];
+                }
+            }
+        }
+        let array = Array::from_shape_vec(self.shape(), out)
+            .map_err(|err| CoreMLError::BadInputShape(err.to_string()))?;
+        Ok((array.into(), letterbox))
+    }
+}
+
+/// Bilinear sample at (x, y) as RGB, gray is replicated and alpha dropped.
+fn sample_rgb(image: &ImageBuffer, x: f32, y: f32, out: &mut [f32; 3]) {
+    let x = x.clamp(0.0, (image.width - 1) as f32);
+    let y = y.clamp(0.0, (image.height - 1) as f32);
+    let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+    let (x1, y1) = (
+        (x0 + 1).min(image.width - 1),
+        (y0 + 1).min(image.height - 1),
+    );
+    let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+    let at = |px: usize, py: usize, c: usize| {
+        let c = if image.channels == 1 { 0 } else { c };
+        image.data[(py * image.width + px) * image.channels + c] as f32
+    };
+    for (c, value) in out.iter_mut().enumerate() {
+        let top = at(x0, y0, c) * (1.0 - fx) + at(x1, y0, c) * fx;
+        let bottom = at(x0, y1, c) * (1.0 - fx) + at(x1, y1, c) * fx;
+        *value = top * (1.0 - fy) + bottom * fy;
+    }
+}
@@ -0,0 +1,298 @@
+//! Dynamic batching on top of [`CoreMLBatchModelWithState`].
+//!
+//! Requests submitted from any thread are queued, grouped into batches of up to
+//! `max_batch_size` requests, waiting at most `max_latency` after the first request of a
+//! batch arrived, and run with a single `predict()` on a worker thread owning the model.
+//!
+//! ```no_run
+//! # use coreml_rs::{mlbatchmodel::CoreMLBatchModelWithState, scheduler::*, CoreMLModelOptions};
+//! # use std::{collections::HashMap, time::Duration};
+//! let mut model = CoreMLBatchModelWithState::new("model.mlpackage", CoreMLModelOptions::default());
+//! model.load().unwrap();
+//! let scheduler = BatchScheduler::new(
+//!     model,
+//!     BatchSchedulerOptions {
+//!         max_batch_size: 8,
+//!         max_latency: Duration::from_millis(5),
+//!     },
+//! );
+//! let input = ndarray::Array::<f32, _>::zeros(vec![1, 3, 512, 512]);
+//! let output = scheduler
+//!     .submit(HashMap::from([("image".to_string(), input.into())]))
+//!     .wait()
+//!     .unwrap();
+//! ```
+
+use crate::{
+    batch::BatchBuilder, mlarray::MLArray, mlbatchmodel::CoreMLBatchModelWithState,
+    mlmodel::CoreMLError,
+};
+use std::{
+    collections::HashMap,
+    future::Future,
+    panic::AssertUnwindSafe,
+    pin::Pin,
+    sync::{mpsc, Arc, Condvar, Mutex, MutexGuard, PoisonError},
+    task::{Context, Poll, Waker},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+pub type Features = HashMap<String, MLArray>;
+
+/// Runs a whole batch, returning one output per input in order.
+pub trait BatchPredictor {
+    fn run_batch(&mut self, inputs: Vec<Features>) -> Result<Vec<Features>, CoreMLError>;
+
+    /// Checks one request before it joins a batch, so a bad request fails on its own
+    /// instead of failing every request batched with it. Accepts anything by default.
+    fn check(&self, inputs: &Features) -> Result<(), CoreMLError> {
+        _ = inputs;
+        Ok(())
+    }
+}
+
+impl BatchPredictor for CoreMLBatchModelWithState {
+    fn check(&self, inputs: &Features) -> Result<(), CoreMLError> {
+        self.check_item(inputs)
+    }
+
+    fn run_batch(&mut self, inputs: Vec<Features>) -> Result<Vec<Features>, CoreMLError> {
+        let mut batch = BatchBuilder::new();
+        for features in inputs {
+            batch.push(features);
+        }
+        Ok(self.predict_batch(&mut batch)?.outputs)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BatchSchedulerOptions {
+    pub max_batch_size: usize,
+    /// How long the first request of a batch waits for more requests
+    pub max_latency: Duration,
+}
+
+impl Default for BatchSchedulerOptions {
+    fn default() -> Self {
+        Self {
+            max_batch_size: 8,
+            max_latency: Duration::from_millis(5),
+        }
+    }
+}
+
+struct Request {
+    inputs: Features,
+    slot: Pending,
+}
+
+/// A slot the worker still has to resolve. Dropping it unresolved, e.g. when the worker
+/// stops with requests still queued, resolves it with an error so no caller waits forever.
+struct Pending(Option<Arc<Slot>>);
+
+impl Pending {
+    fn resolve(mut self, result: Result<Features, CoreMLError>) {
+        if let Some(slot) = self.0.take() {
+            slot.resolve(result);
+        }
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        if let Some(slot) = self.0.take() {
+            slot.resolve(Err(CoreMLError::UnknownErrorStatic(
+                "batch scheduler stopped before running the request",
+            )));
+        }
+    }
+}
+
+#[derive(Default)]
+struct Slot {
+    state: Mutex<SlotState>,
+    ready: Condvar,
+}
+
+#[derive(Default)]
+struct SlotState {
+    result: Option<Result<Features, CoreMLError>>,
+    waker: Option<Waker>,
+}
+
+impl Slot {
+    /// The slot only holds plain data, so it is still usable after a panic poisoned it.
+    fn lock(&self) -> MutexGuard<'_, SlotState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn resolve(&self, result: Result<Features, CoreMLError>) {
+        let mut state = self.lock();
+        state.result = Some(result);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+        self.ready.notify_all();
+    }
+}
+
+/// Output of a submitted request, either `.await` it or block on it with `wait`.
+pub struct Prediction {
+    slot: Arc<Slot>,
+}
+
+impl Prediction {
+    fn resolved(result: Result<Features, CoreMLError>) -> Self {
+        let slot = Arc::new(Slot::default());
+        slot.resolve(result);
+        Self { slot }
+    }
+
+    pub fn wait(self) -> Result<Features, CoreMLError> {
+        let mut state = self.slot.lock();
+        loop {
+            if let Some(result) = state.result.take() {
+                return result;
+            }
+            state = self
+                .slot
+                .ready
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.slot.lock().result.is_some()
+    }
+}
+
+impl Future for Prediction {
+    type Output = Result<Features, CoreMLError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.slot.lock();
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Owns a batch model on a worker thread and batches requests submitted to it.
+///
+/// Dropping the scheduler finishes the queued requests and joins the worker.
+pub struct BatchScheduler {
+    sender: Option<mpsc::Sender<Request>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl BatchScheduler {
+    pub fn new<P: BatchPredictor + Send + 'static>(
+        mut predictor: P,
+        opts: BatchSchedulerOptions,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel::<Request>();
+        let max_batch_size = opts.max_batch_size.max(1);
+        let worker = std::thread::spawn(move || {
+            // blocks until the first request of the next batch, exits once all senders are gone
+            while let Ok(first) = receiver.recv() {
+                let deadline = Instant::now() + opts.max_latency;
+                let mut batch = vec![first];
+                while batch.len() < max_batch_size {
+                    let timeout = deadline.saturating_duration_since(Instant::now());
+                    match receiver.recv_timeout(timeout) {
+                        Ok(request) => batch.push(request),
+                        Err(_) => break,
+                    }
+                }
+                dispatch(&mut predictor, batch);
+            }
+        });
+        Self {
+            sender: Some(sender),
+            worker: Some(worker),
+        }
+    }
+
+    /// Queues a single request, `inputs` without a batch dimension as for `CoreMLModel`.
+    pub fn submit(&self, inputs: Features) -> Prediction {
+        let slot = Arc::new(Slot::default());
+        let request = Request {
+            inputs,
+            slot: Pending(Some(slot.clone())),
+        };
+        match self.sender.as_ref().map(|sender| sender.send(request)) {
+            Some(Ok(())) => Prediction { slot },
+            _ => Prediction::resolved(Err(CoreMLError::UnknownErrorStatic(
+                "batch scheduler worker is not running",
+            ))),
+        }
+    }
+}
+
+impl Drop for BatchScheduler {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        if let Some(worker) = self.worker.take() {
+            _ = worker.join();
+        }
+    }
+}
+
+fn dispatch<P: BatchPredictor>(predictor: &mut P, batch: Vec<Request>) {
+    let (inputs, slots): (Vec<_>, Vec<_>) = batch
+        .into_iter()
+        .filter_map(|request| match predictor.check(&request.inputs) {
+            Ok(()) => Some((request.inputs, request.slot)),
+            Err(err) => {
+                request.slot.resolve(Err(err));
+                None
+            }
+        })
+        .unzip();
+    if slots.is_empty() {
+        return;
+    }
+    // a panicking predictor fails its batch instead of taking down the worker
+    let result = std::panic::catch_unwind(AssertUnwindSafe(|| predictor.run_batch(inputs)))
+        .unwrap_or_else(|panic| {
+            let reason = panic
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+            Err(CoreMLError::UnknownError(format!(
+                "batch predictor panicked: {reason}"
+            )))
+        });
+    match result {
+        Ok(outputs) if outputs.len() == slots.len() => {
+            for (slot, output) in slots.into_iter().zip(outputs) {
+                slot.resolve(Ok(output));
+            }
+        }
+        Ok(outputs) => {
+            let expected = slots.len();
+            for slot in slots {
+                slot.resolve(Err(CoreMLError::UnknownError(format!(
+                    "expected {expected} outputs from the batch found {}",
+                    outputs.len()
+                ))));
+            }
+        }
+        Err(err) => {
+            // the error is shared by the whole batch
+            let err = err.to_string();
+            for slot in slots {
+                slot.resolve(Err(CoreMLError::UnknownError(format!(
+                    "batch prediction failed: {err}"
+                ))));
+            }
+        }
+    }
+}
@@ -0,0 +1,556 @@
+//! Pure-Rust reader for the parts of the CoreML `Model` protobuf spec we need,
+//! without a Mac or the full generated protobuf types.
+//!
+//! Only the fields below are decoded, everything else is skipped:
+//!
+//! ```text
+//! Model            { int32 specificationVersion = 1; ModelDescription description = 2; }
+//! ModelDescription { repeated FeatureDescription input = 1; repeated FeatureDescription output = 10;
+//!                    string predictedFeatureName = 11; string predictedProbabilitiesName = 12;
+//!                    Metadata metadata = 100; }
+//! Metadata         { string shortDescription = 1; string versionString = 2; string author = 3;
+//!                    string license = 4; map<string, string> userDefined = 100; }
+//! *Classifier      { StringVector stringClassLabels = 100; Int64Vector int64ClassLabels = 101; }
+//! FeatureDescription { string name = 1; string shortDescription = 2; FeatureType type = 3; }
+//! FeatureType      { ImageFeatureType imageType = 4; ArrayFeatureType multiArrayType = 5;
+//!                    bool isOptional = 1000; }
+//! ImageFeatureType { int64 width = 1; int64 height = 2; ColorSpace colorSpace = 3; }
+//! ArrayFeatureType { repeated int64 shape = 1; ArrayDataType dataType = 2;
+//!                    EnumeratedShapes enumeratedShapes = 21; ShapeRange shapeRange = 31; }
+//! EnumeratedShapes { repeated Shape shapes = 1; }  Shape { repeated int64 shape = 1; }
+//! ShapeRange       { repeated SizeRange sizeRanges = 1; }
+//! SizeRange        { uint64 lowerBound = 1; int64 upperBound = 2; }
+//! NeuralNetwork*   { repeated NeuralNetworkPreprocessing preprocessing = 2; }
+//! NeuralNetworkPreprocessing { string featureName = 1; NeuralNetworkImageScaler scaler = 10; }
+//! NeuralNetworkImageScaler   { float channelScale = 10; float blueBias = 20; float greenBias = 21;
+//!                              float redBias = 22; float grayBias = 30; }
+//! ```
+//!
+//! Class labels are read from whichever classifier the model is (fields
+//! 400..=404 of `Model`), or the last model of a `pipelineClassifier` (200).
+//! Image scalers come from neural network models (fields 303, 403 and 500).
+
+use crate::{classifier::Label, mlmodel::CoreMLError, mlpackage::MLPackage};
+use std::{collections::HashMap, path::Path};
+
+/// Model metadata, as set by coremltools through `model.author`, `model.version`,
+/// `model.user_defined_metadata` and friends.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ModelMetadata {
+    pub short_description: Option<String>,
+    pub version: Option<String>,
+    pub author: Option<String>,
+    pub license: Option<String>,
+    pub user_defined: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColorSpace {
+    Grayscale,
+    #[default]
+    Rgb,
+    Bgr,
+    GrayscaleFloat16,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImageFeatureType {
+    pub width: u64,
+    pub height: u64,
+    pub color_space: ColorSpace,
+}
+
+/// Element type of a multi array feature.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ArrayDataType {
+    #[default]
+    Float32,
+    Float64,
+    Float16,
+    Int32,
+    Int8,
+    /// Raw value of types not known (yet)
+    Unknown(u64),
+}
+
+impl ArrayDataType {
+    fn from_proto(v: u64) -> Self {
+        match v {
+            0x10020 => ArrayDataType::Float32,
+            0x10040 => ArrayDataType::Float64,
+            0x10010 => ArrayDataType::Float16,
+            0x20020 => ArrayDataType::Int32,
+            0x20008 => ArrayDataType::Int8,
+            v => ArrayDataType::Unknown(v),
+        }
+    }
+}
+
+/// Shapes a multi array feature accepts besides its default shape.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ShapeFlexibility {
+    #[default]
+    Fixed,
+    Enumerated(Vec<Vec<usize>>),
+    /// Lower and upper bound of every dimension, `None` if unbounded
+    Range(Vec<(usize, Option<usize>)>),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ArrayFeatureType {
+    /// Default shape, may be empty for flexible shapes
+    pub shape: Vec<usize>,
+    pub data_type: ArrayDataType,
+    pub flexibility: ShapeFlexibility,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum FeatureType {
+    Image(ImageFeatureType),
+    MultiArray(ArrayFeatureType),
+    /// Feature types not decoded (yet)
+    #[default]
+    Other,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FeatureDescription {
+    pub name: String,
+    pub short_description: String,
+    pub feature_type: FeatureType,
+    pub is_optional: bool,
+}
+
+/// Per channel `pixel * channel_scale + bias` CoreML applies to image inputs of
+/// neural network models.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImageScaler {
+    pub channel_scale: f32,
+    pub red_bias: f32,
+    pub green_bias: f32,
+    pub blue_bias: f32,
+    pub gray_bias: f32,
+}
+
+impl Default for ImageScaler {
+    fn default() -> Self {
+        Self {
+            channel_scale: 1.0,
+            red_bias: 0.0,
+            green_bias: 0.0,
+            blue_bias: 0.0,
+            gray_bias: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ModelDescription {
+    pub inputs: Vec<FeatureDescription>,
+    pub outputs: Vec<FeatureDescription>,
+    pub predicted_feature_name: Option<String>,
+    pub predicted_probabilities_name: Option<String>,
+    pub metadata: ModelMetadata,
+}
+
+/// Decoded CoreML model spec.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ModelSpec {
+    pub specification_version: i32,
+    pub description: ModelDescription,
+    /// Class labels of classifier models
+    pub class_labels: Option<Vec<Label>>,
+    /// Image preprocessing of neural network models, by input name
+    pub image_scalers: HashMap<String, ImageScaler>,
+}
+
+impl ModelSpec {
+    /// Reads the spec of an `.mlmodel` file or `.mlpackage` directory.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, CoreMLError> {
+        let path = path.as_ref();
+        let buf = if path.is_dir() {
+            MLPackage::open(path)?.read_spec()?
+        } else {
+            std::fs::read(path).map_err(CoreMLError::IoError)?
+        };
+        Self::from_bytes(&buf)
+    }
+
+    pub fn from_bytes(buf: &[u8]) -> Result<Self, CoreMLError> {
+        let mut spec = Self::default();
+        let mut reader = ProtoReader::new(buf);
+        while let Some((field, value)) = reader.next_field()? {
+            match (field, value) {
+                (1, ProtoValue::Varint(v)) => spec.specification_version = v as i32,
+                (2, ProtoValue::Bytes(b)) => spec.description = parse_description(b)?,
+                (200, ProtoValue::Bytes(b)) => spec.class_labels = parse_pipeline_labels(b)?,
+                (400..=404, ProtoValue::Bytes(b)) => spec.class_labels = parse_class_labels(b)?,
+                _ => {}
+            }
+            if let (303 | 403 | 500, ProtoValue::Bytes(b)) = (field, value) {
+                spec.image_scalers = parse_image_scalers(b)?;
+            }
+        }
+        Ok(spec)
+    }
+
+    pub fn metadata(&self) -> &ModelMetadata {
+        &self.description.metadata
+    }
+
+    pub fn input(&self, name: &str) -> Option<&FeatureDescription> {
+        self.description.inputs.iter().find(|f| f.name == name)
+    }
+}
+
+fn parse_image_scalers(buf: &[u8]) -> Result<HashMap<String, ImageScaler>, CoreMLError> {
+    let mut scalers = HashMap::new();
+    let mut reader = ProtoReader::new(buf);
+    while let Some((field, value)) = reader.next_field()? {
+        let (2, ProtoValue::Bytes(b)) = (field, value) else {
+            continue;
+        };
+        let mut name = String::new();
+        let mut scaler = None;
+        let mut preprocessing = ProtoReader::new(b);
+        while let Some((field, value)) = preprocessing.next_field()? {
+            match (field, value) {
+                (1, ProtoValue::Bytes(b)) => name = string(b)?,
+                (10, ProtoValue::Bytes(b)) => {
+                    let mut out = ImageScaler {
+                        channel_scale: 0.0,
+                        ..Default::default()
+                    };
+                    let mut fields = ProtoReader::new(b);
+                    while let Some((field, value)) = fields.next_field()? {
+                        let ProtoValue::Fixed32(v) = value else {
+                            continue;
+                        };
+                        let v = f32::from_bits(v);
+                        match field {
+                            10 => out.channel_scale = v,
+                            20 => out.blue_bias = v,
+                            21 => out.green_bias = v,
+                            22 => out.red_bias = v,
+                            30 => out.gray_bias = v,
+                            _ => {}
+                        }
+                    }
+                    // an unset scale is treated as 1 by CoreML
+                    if out.channel_scale == 0.0 {
+                        out.channel_scale = 1.0;
+                    }
+                    scaler = Some(out);
+                }
+                _ => {}
+            }
+        }
+        if let Some(scaler) = scaler {
+            scalers.insert(name, scaler);
+        }
+    }
+    Ok(scalers)
+}
+
+/// PipelineClassifier { Pipeline pipeline = 1; } Pipeline { repeated Model models = 1; }
+fn parse_pipeline_labels(buf: &[u8]) -> Result<Option<Vec<Label>>, CoreMLError> {
+    let mut labels = None;
+    let mut reader = ProtoReader::new(buf);
+    while let Some((field, value)) = reader.next_field()? {
+        if let (1, ProtoValue::Bytes(pipeline)) = (field, value) {
+            let mut models = ProtoReader::new(pipeline);
+            while let Some((field, value)) = models.next_field()? {
+                if let (1, ProtoValue::Bytes(model)) = (field, value) {
+                    labels = ModelSpec::from_bytes(model)?.class_labels.or(labels);
+                }
+            }
+        }
+    }
+    Ok(labels)
+}
+
+fn parse_class_labels(buf: &[u8]) -> Result<Option<Vec<Label>>, CoreMLError> {
+    let mut labels = None;
+    let mut reader = ProtoReader::new(buf);
+    while let Some((field, value)) = reader.next_field()? {
+        match (field, value) {
+            (100, ProtoValue::Bytes(b)) => {
+                let mut out = vec![];
+                let mut vector = ProtoReader::new(b);
+                while let Some((field, value)) = vector.next_field()? {
+                    if let (1, ProtoValue::Bytes(b)) = (field, value) {
+                        out.push(Label::String(string(b)?));
+                    }
+                }
+                labels = Some(out);
+            }
+            (101, ProtoValue::Bytes(b)) => {
+                let mut out = vec![];
+                let mut vector = ProtoReader::new(b);
+                while let Some((field, value)) = vector.next_field()? {
+                    if field == 1 {
+                        out.extend(repeated_varint(value)?.map(|v| Label::Int64(v as i64)));
+                    }
+                }
+                labels = Some(out);
+            }
+            _ => {}
+        }
+    }
+    Ok(labels)
+}
+
+fn parse_description(buf: &[u8]) -> Result<ModelDescription, CoreMLError> {
+    let mut desc = ModelDescription::default();
+    let mut reader = ProtoReader::new(buf);
+    while let Some((field, value)) = reader.next_field()? {
+        match (field, value) {
+            (1, ProtoValue::Bytes(b)) => desc.inputs.push(parse_feature(b)?),
+            (10, ProtoValue::Bytes(b)) => desc.outputs.push(parse_feature(b)?),
+            (11, ProtoValue::Bytes(b)) => desc.predicted_feature_name = Some(string(b)?),
+            (12, ProtoValue::Bytes(b)) => desc.predicted_probabilities_name = Some(string(b)?),
+            (100, ProtoValue::Bytes(b)) => desc.metadata = parse_metadata(b)?,
+            _ => {}
+        }
+    }
+    Ok(desc)
+}
+
+fn parse_feature(buf: &[u8]) -> Result<FeatureDescription, CoreMLError> {
+    let mut feature = FeatureDescription::default();
+    let mut reader = ProtoReader::new(buf);
+    while let Some((field, value)) = reader.next_field()? {
+        match (field, value) {
+            (1, ProtoValue::Bytes(b)) => feature.name = string(b)?,
+            (2, ProtoValue::Bytes(b)) => feature.short_description = string(b)?,
+            (3, ProtoValue::Bytes(b)) => {
+                (feature.feature_type, feature.is_optional) = parse_feature_type(b)?
+            }
+            _ => {}
+        }
+    }
+    Ok(feature)
+}
+
+fn parse_feature_type(buf: &[u8]) -> Result<(FeatureType, bool), CoreMLError> {
+    let mut feature_type = FeatureType::Other;
+    let mut is_optional = false;
+    let mut reader = ProtoReader::new(buf);
+    while let Some((field, value)) = reader.next_field()? {
+        match (field, value) {
+            (4, ProtoValue::Bytes(b)) => feature_type = FeatureType::Image(parse_image_type(b)?),
+            (5, ProtoValue::Bytes(b)) => {
+                feature_type = FeatureType::MultiArray(parse_array_type(b)?)
+            }
+            (1000, ProtoValue::Varint(v)) => is_optional = v != 0,
+            _ => {}
+        }
+    }
+    Ok((feature_type, is_optional))
+}
+
+fn parse_image_type(buf: &[u8]) -> Result<ImageFeatureType, CoreMLError> {
+    let mut image = ImageFeatureType::default();
+    let mut fields = ProtoReader::new(buf);
+    while let Some((field, value)) = fields.next_field()? {
+        match (field, value) {
+            (1, ProtoValue::Varint(v)) => image.width = v,
+            (2, ProtoValue::Varint(v)) => image.height = v,
+            (3, ProtoValue::Varint(v)) => {
+                image.color_space = match v {
+                    10 => ColorSpace::Grayscale,
+                    30 => ColorSpace::Bgr,
+                    40 => ColorSpace::GrayscaleFloat16,
+                    _ => ColorSpace::Rgb,
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(image)
+}
+
+fn parse_array_type(buf: &[u8]) -> Result<ArrayFeatureType, CoreMLError> {
+    let mut array = ArrayFeatureType::default();
+    let mut reader = ProtoReader::new(buf);
+    while let Some((field, value)) = reader.next_field()? {
+        match (field, value) {
+            (1, value) => array
+                .shape
+                .extend(repeated_varint(value)?.map(|v| v as usize)),
+            (2, ProtoValue::Varint(v)) => array.data_type = ArrayDataType::from_proto(v),
+            (21, ProtoValue::Bytes(b)) => {
+                let mut shapes = vec![];
+                let mut fields = ProtoReader::new(b);
+                while let Some((field, value)) = fields.next_field()? {
+                    if let (1, ProtoValue::Bytes(b)) = (field, value) {
+                        let mut shape = vec![];
+                        let mut dims = ProtoReader::new(b);
+                        while let Some((field, value)) = dims.next_field()? {
+                            if field == 1 {
+                                shape.extend(repeated_varint(value)?.map(|v| v as usize));
+                            }
+                        }
+                        shapes.push(shape);
+                    }
+                }
+                array.flexibility = ShapeFlexibility::Enumerated(shapes);
+            }
+            (31, ProtoValue::Bytes(b)) => {
+                let mut ranges = vec![];
+                let mut fields = ProtoReader::new(b);
+                while let Some((field, value)) = fields.next_field()? {
+                    if let (1, ProtoValue::Bytes(b)) = (field, value) {
+                        let (mut lower, mut upper) = (0, None);
+                        let mut bounds = ProtoReader::new(b);
+                        while let Some((field, value)) = bounds.next_field()? {
+                            match (field, value) {
+                                (1, ProtoValue::Varint(v)) => lower = v as usize,
+                                // negative for unbounded
+                                (2, ProtoValue::Varint(v)) => {
+                                    upper = (v as i64 >= 0).then_some(v as usize)
+                                }
+                                _ => {}
+                            }
+                        }
+                        ranges.push((lower, upper));
+                    }
+                }
+                array.flexibility = ShapeFlexibility::Range(ranges);
+            }
+            _ => {}
+        }
+    }
+    Ok(array)
+}
+
+/// Values of a repeated varint field, packed or not.
+fn repeated_varint(value: ProtoValue<'_>) -> Result<impl Iterator<Item = u64>, CoreMLError> {
+    let mut out = vec![];
+    match value {
+        ProtoValue::Varint(v) => out.push(v),
+        ProtoValue::Bytes(packed) => {
+            let mut packed = ProtoReader::new(packed);
+            while !packed.is_empty() {
+                out.push(packed.varint()?);
+            }
+        }
+        _ => {}
+    }
+    Ok(out.into_iter())
+}
+
+fn parse_metadata(buf: &[u8]) -> Result<ModelMetadata, CoreMLError> {
+    let mut metadata = ModelMetadata::default();
+    let mut reader = ProtoReader::new(buf);
+    while let Some((field, value)) = reader.next_field()? {
+        match (field, value) {
+            (1, ProtoValue::Bytes(b)) => metadata.short_description = non_empty(string(b)?),
+            (2, ProtoValue::Bytes(b)) => metadata.version = non_empty(string(b)?),
+            (3, ProtoValue::Bytes(b)) => metadata.author = non_empty(string(b)?),
+            (4, ProtoValue::Bytes(b)) => metadata.license = non_empty(string(b)?),
+            (100, ProtoValue::Bytes(b)) => {
+                // map entries are messages of { key = 1; value = 2; }
+                let (mut key, mut val) = (String::new(), String::new());
+                let mut entry = ProtoReader::new(b);
+                while let Some((field, value)) = entry.next_field()? {
+                    match (field, value) {
+                        (1, ProtoValue::Bytes(b)) => key = string(b)?,
+                        (2, ProtoValue::Bytes(b)) => val = string(b)?,
+                        _ => {}
+                    }
+                }
+                metadata.user_defined.insert(key, val);
+            }
+            _ => {}
+        }
+    }
+    Ok(metadata)
+}
+
+fn non_empty(s: String) -> Option<String> {
+    (!s.is_empty()).then_some(s)
+}
+
+pub(crate) fn string(buf: &[u8]) -> Result<String, CoreMLError> {
+    String::from_utf8(buf.to_vec())
+        .map_err(|err| CoreMLError::BadSpec(format!("invalid utf8 string: {err}")))
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum ProtoValue<'a> {
+    Varint(u64),
+    /// Skipped, none of the decoded fields are 64 bit fixed
+    Fixed64,
+    Bytes(&'a [u8]),
+    Fixed32(u32),
+}
+
+/// Minimal protobuf wire format reader.
+pub(crate) struct ProtoReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ProtoReader<'a> {
+    pub(crate) fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    pub(crate) fn next_field(&mut self) -> Result<Option<(u32, ProtoValue<'a>)>, CoreMLError> {
+        if self.pos >= self.buf.len() {
+            return Ok(None);
+        }
+        let key = self.varint()?;
+        let field = (key >> 3) as u32;
+        let value = match key & 7 {
+            0 => ProtoValue::Varint(self.varint()?),
+            1 => {
+                self.take(8)?;
+                ProtoValue::Fixed64
+            }
+            2 => {
+                let len = self.varint()? as usize;
+                ProtoValue::Bytes(self.take(len)?)
+            }
+            5 => ProtoValue::Fixed32(u32::from_le_bytes(self.take(4)?.try_into().unwrap())),
+            wire => {
+                return Err(CoreMLError::BadSpec(format!(
+                    "unsupported wire type {wire} for field {field} at {}",
+                    self.pos
+                )))
+            }
+        };
+        Ok(Some((field, value)))
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.pos >= self.buf.len()
+    }
+
+    pub(crate) fn varint(&mut self) -> Result<u64, CoreMLError> {
+        let mut out = 0u64;
+        for shift in (0..64).step_by(7) {
+            let Some(&b) = self.buf.get(self.pos) else {
+                return Err(CoreMLError::BadSpec("truncated varint".to_string()));
+            };
+            self.pos += 1;
+            out |= ((b & 0x7f) as u64) << shift;
+            if b & 0x80 == 0 {
+                return Ok(out);
+            }
+        }
+        Err(CoreMLError::BadSpec("varint too long".to_string()))
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], CoreMLError> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.buf.len())
+            .ok_or_else(|| CoreMLError::BadSpec(format!("truncated field at {}", self.pos)))?;
+        let out = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(out)
+    }
+}
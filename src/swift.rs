@@ -2,7 +2,7 @@ use std::collections::HashMap;
 
 use swift::{BatchOutput, ComputePlatform, ModelOutput};
 
-use crate::mlarray::MLArray;
+use crate::{mlarray::MLArray, mlmodel::CoreMLError};
 
 #[swift_bridge::bridge]
 pub mod swift {
@@ -21,7 +21,6 @@ pub mod swift {
         fn rust_vec_free_f32(ptr: *mut f32, len: usize);
         fn rust_vec_free_i32(ptr: *mut i32, len: usize);
         fn rust_vec_free_u16(ptr: *mut u16, len: usize);
-        fn rust_vec_free_u8(ptr: *mut u8, len: usize);
     }
 
     extern "Swift" {
@@ -41,6 +40,10 @@ pub mod swift {
             compute: ComputePlatform,
             compiled: bool,
         ) -> BatchModel;
+        #[swift_bridge(swift_name = "osVersionString")]
+        pub fn os_version() -> String;
+        #[swift_bridge(swift_name = "compileModelAt")]
+        pub fn compile_model_at(path: String) -> String;
     }
 
     extern "Swift" {
@@ -67,9 +70,21 @@ pub mod swift {
             len: usize,
             idx: isize,
         ) -> bool;
+        fn bindInputU16(
+            &self,
+            shape: Vec<usize>,
+            featureName: String,
+            data: *mut u16,
+            len: usize,
+            idx: isize,
+        ) -> bool;
+        fn clearInputs(&mut self);
+        #[swift_bridge(swift_name = "getCompiledPath")]
+        fn compiled_path(&self) -> Option<String>;
+        #[swift_bridge(swift_name = "computePlanJson")]
+        fn compute_plan_json(&self) -> String;
         #[swift_bridge(swift_name = "hasFailedToLoad")]
         fn failed(&self) -> bool;
-
     }
 
     extern "Swift" {
@@ -110,11 +125,16 @@ pub mod swift {
 
         #[swift_bridge(swift_name = "getCompiledPath")]
         fn compiled_path(&self) -> Option<String>;
+        #[swift_bridge(swift_name = "computePlanJson")]
+        fn compute_plan_json(&self) -> String;
 
         fn load(&mut self) -> bool;
         fn unload(&mut self) -> bool;
         fn description(&self) -> ModelDescription;
         fn predict(&self) -> ModelOutput;
+        fn clearInputs(&mut self);
+        #[swift_bridge(swift_name = "predictClassifier")]
+        fn predict_classifier(&self) -> ModelOutput;
         #[swift_bridge(swift_name = "hasFailedToLoad")]
         fn failed(&self) -> bool;
     }
@@ -128,6 +148,14 @@ pub mod swift {
         fn output_type(&self, name: String) -> String;
         fn output_shape(&self, name: String) -> Vec<usize>;
         fn input_shape(&self, name: String) -> Vec<usize>;
+        fn input_type(&self, name: String) -> String;
+        fn required_input_names(&self) -> Vec<String>;
+        fn metadata(&self, key: String) -> Option<String>;
+        fn user_defined_keys(&self) -> Vec<String>;
+        fn user_defined_value(&self, key: String) -> Option<String>;
+        fn predicted_probabilities_name(&self) -> Option<String>;
+        fn class_labels_string(&self) -> Vec<String>;
+        fn class_labels_int(&self) -> Vec<i64>;
     }
 
     extern "Swift" {
@@ -137,6 +165,8 @@ pub mod swift {
         fn outputF32(&self, name: String) -> Vec<f32>;
         fn outputU16(&self, name: String) -> Vec<u16>;
         fn outputI32(&self, name: String) -> Vec<i32>;
+        fn dictionaryKeys(&self, name: String) -> Vec<String>;
+        fn dictionaryValues(&self, name: String) -> Vec<f64>;
         fn getError(&self) -> Option<String>;
     }
 }
@@ -182,12 +212,6 @@ fn rust_vec_free_u16(ptr: *mut u16, len: usize) {
     }
 }
 
-fn rust_vec_free_u8(ptr: *mut u8, len: usize) {
-    unsafe {
-        _ = Vec::from_raw_parts(ptr, len, len);
-    }
-}
-
 fn rust_vec_free_i32(ptr: *mut i32, len: usize) {
     unsafe {
         _ = Vec::from_raw_parts(ptr, len, len);
@@ -198,6 +222,34 @@ pub struct MLModelOutput {
     pub outputs: HashMap<String, MLArray>,
 }
 
+impl MLModelOutput {
+    /// Output `name`, erroring with the available names if there is none.
+    pub fn get(&self, name: &str) -> Result<&MLArray, CoreMLError> {
+        self.outputs.get(name).ok_or_else(|| {
+            let mut names: Vec<&str> = self.outputs.keys().map(|k| k.as_str()).collect();
+            names.sort();
+            CoreMLError::UnknownError(format!("no output named '{name}', outputs are {names:?}"))
+        })
+    }
+}
+
 pub struct MLBatchModelOutput {
     pub outputs: Vec<HashMap<String, MLArray>>,
 }
+
+impl MLBatchModelOutput {
+    /// Output `name` of every batch item stacked along a new leading axis.
+    pub fn stack(&self, name: &str) -> Result<MLArray, CoreMLError> {
+        let arrays = self
+            .outputs
+            .iter()
+            .enumerate()
+            .map(|(idx, item)| {
+                item.get(name).ok_or_else(|| {
+                    CoreMLError::UnknownError(format!("batch item {idx} has no output '{name}'"))
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        crate::batch::stack(&arrays)
+    }
+}
@@ -0,0 +1,237 @@
+//! Synthetic model inputs for tests and benchmarks, generated from a model description.
+//!
+//! ```no_run
+//! # use coreml_rs::{spec::ModelSpec, synthetic::{Fill, InputGenerator, ShapeChoice}};
+//! let spec = ModelSpec::open("model.mlpackage").unwrap();
+//! let inputs = InputGenerator::new(Fill::Normal { mean: 0.0, std: 1.0 })
+//!     .seed(42)
+//!     .shape(ShapeChoice::Largest)
+//!     .generate_for_spec(&spec)
+//!     .unwrap();
+//! ```
+//!
+//! Inputs of a loaded model are described by `CoreMLModelWithState::input_specs`, which
+//! only knows their default shape. Image inputs can't be generated, they are not passed
+//! as arrays.
+
+use crate::{
+    mlarray::MLArray,
+    mlmodel::CoreMLError,
+    spec::{ArrayDataType, FeatureType, ModelSpec, ShapeFlexibility},
+};
+use half::f16;
+use ndarray::{Array, IxDyn};
+
+/// Values of the generated arrays, rounded for integer inputs.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Fill {
+    #[default]
+    Zeros,
+    Ones,
+    /// Uniformly distributed in `[low, high)`
+    Uniform {
+        low: f32,
+        high: f32,
+    },
+    Normal {
+        mean: f32,
+        std: f32,
+    },
+}
+
+/// Which shape to use for inputs accepting more than one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ShapeChoice {
+    /// The default shape of the input
+    #[default]
+    Default,
+    /// The enumerated shape with the fewest elements, or the lower bound of ranges
+    Smallest,
+    /// The enumerated shape with the most elements, or the upper bound of ranges,
+    /// unbounded dimensions keep their default size
+    Largest,
+}
+
+/// A multi array input to generate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputSpec {
+    pub name: String,
+    pub data_type: ArrayDataType,
+    pub shape: Vec<usize>,
+    pub flexibility: ShapeFlexibility,
+    pub is_optional: bool,
+}
+
+impl InputSpec {
+    /// Every input of `spec`, failing for inputs that are not multi arrays.
+    pub fn from_spec(spec: &ModelSpec) -> Result<Vec<Self>, CoreMLError> {
+        spec.description
+            .inputs
+            .iter()
+            .map(|input| match &input.feature_type {
+                FeatureType::MultiArray(array) => Ok(Self {
+                    name: input.name.clone(),
+                    data_type: array.data_type,
+                    shape: array.shape.clone(),
+                    flexibility: array.flexibility.clone(),
+                    is_optional: input.is_optional,
+                }),
+                _ => Err(unsupported(&input.name, "not a multi array")),
+            })
+            .collect()
+    }
+
+    /// Shape to generate, always one the input accepts.
+    pub fn choose_shape(&self, choice: ShapeChoice) -> Vec<usize> {
+        match (&self.flexibility, choice) {
+            (ShapeFlexibility::Enumerated(shapes), ShapeChoice::Smallest) => shapes
+                .iter()
+                .min_by_key(|s| s.iter().product::<usize>())
+                .cloned()
+                .unwrap_or_else(|| self.shape.clone()),
+            (ShapeFlexibility::Enumerated(shapes), ShapeChoice::Largest) => shapes
+                .iter()
+                .max_by_key(|s| s.iter().product::<usize>())
+                .cloned()
+                .unwrap_or_else(|| self.shape.clone()),
+            (ShapeFlexibility::Enumerated(shapes), ShapeChoice::Default) => {
+                if self.shape.is_empty() {
+                    shapes.first().cloned().unwrap_or_default()
+                } else {
+                    self.shape.clone()
+                }
+            }
+            (ShapeFlexibility::Range(ranges), _) => ranges
+                .iter()
+                .enumerate()
+                .map(|(i, &(lower, upper))| {
+                    let default = self.shape.get(i).copied().unwrap_or(lower).max(lower);
+                    match choice {
+                        ShapeChoice::Default => upper.map_or(default, |u| default.min(u)),
+                        ShapeChoice::Smallest => lower,
+                        ShapeChoice::Largest => upper.unwrap_or(default),
+                    }
+                })
+                .collect(),
+            (ShapeFlexibility::Fixed, _) => self.shape.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct InputGenerator {
+    fill: Fill,
+    seed: u64,
+    shape: ShapeChoice,
+    optional: bool,
+}
+
+impl InputGenerator {
+    pub fn new(fill: Fill) -> Self {
+        Self {
+            fill,
+            seed: 0,
+            shape: ShapeChoice::Default,
+            optional: false,
+        }
+    }
+
+    /// Seed of random fills, the same seed generates the same inputs.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn shape(mut self, shape: ShapeChoice) -> Self {
+        self.shape = shape;
+        self
+    }
+
+    /// Also generate optional inputs, skipped by default.
+    pub fn optional(mut self, optional: bool) -> Self {
+        self.optional = optional;
+        self
+    }
+
+    pub fn generate_for_spec(
+        &self,
+        spec: &ModelSpec,
+    ) -> Result<Vec<(String, MLArray)>, CoreMLError> {
+        self.generate(&InputSpec::from_spec(spec)?)
+    }
+
+    /// An array for every input, named after it.
+    pub fn generate(&self, inputs: &[InputSpec]) -> Result<Vec<(String, MLArray)>, CoreMLError> {
+        let mut rng = SplitMix64(self.seed);
+        inputs
+            .iter()
+            .filter(|input| self.optional || !input.is_optional)
+            .map(|input| {
+                let shape = IxDyn(&input.choose_shape(self.shape));
+                let array: MLArray = match input.data_type {
+                    ArrayDataType::Float32 => {
+                        Array::from_shape_simple_fn(shape, || self.value(&mut rng)).into()
+                    }
+                    ArrayDataType::Float64 => {
+                        Array::from_shape_simple_fn(shape, || self.value(&mut rng) as f64).into()
+                    }
+                    ArrayDataType::Float16 => {
+                        Array::from_shape_simple_fn(shape, || f16::from_f32(self.value(&mut rng)))
+                            .into()
+                    }
+                    ArrayDataType::Int32 => {
+                        Array::from_shape_simple_fn(shape, || self.value(&mut rng).round() as i32)
+                            .into()
+                    }
+                    ArrayDataType::Int8 => {
+                        Array::from_shape_simple_fn(shape, || self.value(&mut rng).round() as i8)
+                            .into()
+                    }
+                    ty => {
+                        return Err(unsupported(
+                            &input.name,
+                            &format!("{ty:?} elements are not supported"),
+                        ))
+                    }
+                };
+                Ok((input.name.clone(), array))
+            })
+            .collect()
+    }
+
+    fn value(&self, rng: &mut SplitMix64) -> f32 {
+        match self.fill {
+            Fill::Zeros => 0.0,
+            Fill::Ones => 1.0,
+            Fill::Uniform { low, high } => low + (high - low) * rng.next_f64() as f32,
+            Fill::Normal { mean, std } => {
+                // Box-Muller, 1 - u keeps the log finite
+                let (u, v) = (1.0 - rng.next_f64(), rng.next_f64());
+                let z = (-2.0 * u.ln()).sqrt() * (std::f64::consts::TAU * v).cos();
+                mean + std * z as f32
+            }
+        }
+    }
+}
+
+fn unsupported(name: &str, reason: &str) -> CoreMLError {
+    CoreMLError::UnknownError(format!("can't synthesize input '{name}', {reason}"))
+}
+
+/// Small seeded generator, good enough for test data.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `[0, 1)`.
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
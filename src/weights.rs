@@ -0,0 +1,328 @@
+//! Reader for the blob storage files ML Program packages keep their weights in,
+//! usually `Data/com.apple.CoreML/weights/weight.bin`.
+//!
+//! The file starts with a 64 byte header, followed by one 64 byte metadata
+//! record per blob, each followed by its 64 byte aligned data:
+//!
+//! ```text
+//! header:   u32 count, u32 version, 7 x u64 reserved
+//! metadata: u32 sentinel (0xDEADBEEF), u32 dtype, u64 size, u64 data offset,
+//!           u64 padding bits, 4 x u64 reserved
+//! ```
+//!
+//! Weights are referenced from `model.mil` by the offset of their metadata
+//! record. Shapes only live in `model.mil`, so blobs are read as flat arrays.
+
+use crate::{mlarray::MLArray, mlmodel::CoreMLError, mlpackage::MLPackage};
+use half::{bf16, f16};
+use ndarray::Array;
+use std::{
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
+
+const HEADER_SIZE: usize = 64;
+const METADATA_SIZE: usize = 64;
+const ALIGNMENT: usize = 64;
+const SENTINEL: u32 = 0xDEADBEEF;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BlobDataType {
+    Float16,
+    Float32,
+    UInt8,
+    Int8,
+    BFloat16,
+    Int16,
+    UInt16,
+    Int4,
+    UInt1,
+    UInt2,
+    UInt4,
+    UInt3,
+    UInt6,
+    Int32,
+    UInt32,
+    Float8E4M3FN,
+    Float8E5M2,
+    Unknown(u32),
+}
+
+impl BlobDataType {
+    pub fn from_raw(raw: u32) -> Self {
+        match raw {
+            1 => Self::Float16,
+            2 => Self::Float32,
+            3 => Self::UInt8,
+            4 => Self::Int8,
+            5 => Self::BFloat16,
+            6 => Self::Int16,
+            7 => Self::UInt16,
+            8 => Self::Int4,
+            9 => Self::UInt1,
+            10 => Self::UInt2,
+            11 => Self::UInt4,
+            12 => Self::UInt3,
+            13 => Self::UInt6,
+            14 => Self::Int32,
+            15 => Self::UInt32,
+            16 => Self::Float8E4M3FN,
+            17 => Self::Float8E5M2,
+            raw => Self::Unknown(raw),
+        }
+    }
+
+    /// Bits per element, `None` for unknown types.
+    pub fn bits(&self) -> Option<usize> {
+        Some(match self {
+            Self::UInt1 => 1,
+            Self::UInt2 => 2,
+            Self::UInt3 => 3,
+            Self::Int4 | Self::UInt4 => 4,
+            Self::UInt6 => 6,
+            Self::UInt8 | Self::Int8 | Self::Float8E4M3FN | Self::Float8E5M2 => 8,
+            Self::Float16 | Self::BFloat16 | Self::Int16 | Self::UInt16 => 16,
+            Self::Float32 | Self::Int32 | Self::UInt32 => 32,
+            Self::Unknown(_) => return None,
+        })
+    }
+
+    /// Sub-byte unsigned types hold palette indices into a lookup table stored as another blob.
+    pub fn is_palettized(&self) -> bool {
+        matches!(
+            self,
+            Self::UInt1 | Self::UInt2 | Self::UInt3 | Self::UInt4 | Self::UInt6
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlobInfo {
+    /// Offset of the metadata record, the value `model.mil` refers to the blob by
+    pub offset: u64,
+    pub dtype: BlobDataType,
+    /// Offset of the data from the start of the file
+    pub data_offset: u64,
+    /// Size of the data in bytes
+    pub size: u64,
+    /// Unused trailing bits of sub-byte types
+    pub padding_bits: u64,
+}
+
+impl BlobInfo {
+    /// Number of elements stored in the blob.
+    pub fn len(&self) -> usize {
+        match self.dtype.bits() {
+            Some(bits) => {
+                ((self.size * 8).saturating_sub(self.padding_bits) / bits as u64) as usize
+            }
+            None => 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WeightFile {
+    buf: Vec<u8>,
+    version: u32,
+    blobs: Vec<BlobInfo>,
+}
+
+impl WeightFile {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, CoreMLError> {
+        Self::from_bytes(std::fs::read(path).map_err(CoreMLError::IoError)?)
+    }
+
+    /// Weight file of an ML Program package, `None` if it keeps its weights in the spec.
+    pub fn from_package(package: &MLPackage) -> Result<Option<Self>, CoreMLError> {
+        match package.weights_dir()? {
+            Some(dir) if dir.join("weight.bin").is_file() => {
+                Self::open(dir.join("weight.bin")).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    pub fn from_bytes(buf: Vec<u8>) -> Result<Self, CoreMLError> {
+        let (version, blobs) = read_index(buf.len() as u64, |offset, record| {
+            let start = offset as usize;
+            record.copy_from_slice(&buf[start..start + METADATA_SIZE]);
+            Ok(())
+        })?;
+        Ok(Self {
+            buf,
+            version,
+            blobs,
+        })
+    }
+
+    /// Metadata of every blob in the file at `path`, read without the blob data so large
+    /// weight files can be checked cheaply.
+    pub fn read_blobs(path: impl AsRef<Path>) -> Result<Vec<BlobInfo>, CoreMLError> {
+        let mut file = std::fs::File::open(path).map_err(CoreMLError::IoError)?;
+        let len = file.metadata().map_err(CoreMLError::IoError)?.len();
+        let (_, blobs) = read_index(len, |offset, record| {
+            file.seek(SeekFrom::Start(offset))?;
+            file.read_exact(record)
+        })?;
+        Ok(blobs)
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn blobs(&self) -> &[BlobInfo] {
+        &self.blobs
+    }
+
+    /// Blob whose metadata record starts at `offset`, as referenced from `model.mil`.
+    pub fn blob_at(&self, offset: u64) -> Option<&BlobInfo> {
+        self.blobs.iter().find(|blob| blob.offset == offset)
+    }
+
+    /// Total size in bytes of the blob data, excluding headers and alignment.
+    pub fn data_size(&self) -> u64 {
+        self.blobs.iter().map(|blob| blob.size).sum()
+    }
+
+    pub fn bytes(&self, blob: &BlobInfo) -> &[u8] {
+        let start = blob.data_offset as usize;
+        &self.buf[start..start + blob.size as usize]
+    }
+
+    /// Reads the blob into a flat array.
+    ///
+    /// bf16 is widened to f32, int4 to i8 and palette indices to u8, fp8 types are not supported.
+    pub fn read(&self, blob: &BlobInfo) -> Result<MLArray, CoreMLError> {
+        let bytes = self.bytes(blob);
+        let array = match blob.dtype {
+            BlobDataType::Float32 => vec_to_array(le_chunks(bytes, f32::from_le_bytes)),
+            BlobDataType::Float16 => {
+                vec_to_array(le_chunks(bytes, |b| f16::from_bits(u16::from_le_bytes(b))))
+            }
+            BlobDataType::BFloat16 => vec_to_array(le_chunks(bytes, |b| {
+                bf16::from_bits(u16::from_le_bytes(b)).to_f32()
+            })),
+            BlobDataType::Int32 => vec_to_array(le_chunks(bytes, i32::from_le_bytes)),
+            BlobDataType::UInt32 => vec_to_array(le_chunks(bytes, u32::from_le_bytes)),
+            BlobDataType::Int16 => vec_to_array(le_chunks(bytes, i16::from_le_bytes)),
+            BlobDataType::UInt16 => vec_to_array(le_chunks(bytes, u16::from_le_bytes)),
+            BlobDataType::Int8 => vec_to_array(bytes.iter().map(|b| *b as i8).collect()),
+            BlobDataType::UInt8 => vec_to_array(bytes.to_vec()),
+            BlobDataType::Int4 => vec_to_array(
+                unpack_bits(bytes, 4, blob.len())
+                    .into_iter()
+                    .map(|v| ((v << 4) as i8) >> 4)
+                    .collect(),
+            ),
+            BlobDataType::UInt1
+            | BlobDataType::UInt2
+            | BlobDataType::UInt3
+            | BlobDataType::UInt4
+            | BlobDataType::UInt6 => {
+                let bits = blob.dtype.bits().unwrap_or(8);
+                vec_to_array(unpack_bits(bytes, bits, blob.len()))
+            }
+            dtype => {
+                return Err(CoreMLError::BadWeightFile(format!(
+                    "reading {dtype:?} blobs is not supported"
+                )))
+            }
+        };
+        Ok(array)
+    }
+}
+
+/// Parses the header and the metadata records of a file of `len` bytes, `read_at` fills a
+/// buffer with the bytes at an offset that is known to be within the file.
+fn read_index(
+    len: u64,
+    mut read_at: impl FnMut(u64, &mut [u8; METADATA_SIZE]) -> std::io::Result<()>,
+) -> Result<(u32, Vec<BlobInfo>), CoreMLError> {
+    if len < HEADER_SIZE as u64 {
+        return Err(CoreMLError::BadWeightFile(format!(
+            "file of {len} bytes is too small for a header"
+        )));
+    }
+    let mut record = [0u8; METADATA_SIZE];
+    read_at(0, &mut record).map_err(CoreMLError::IoError)?;
+    let count = read_u32(&record, 0);
+    let version = read_u32(&record, 4);
+
+    // a corrupt count can't make us allocate more records than fit in the file
+    let capacity = (len as usize - HEADER_SIZE) / METADATA_SIZE;
+    let mut blobs = Vec::with_capacity((count as usize).min(capacity));
+    let mut offset = HEADER_SIZE as u64;
+    for i in 0..count {
+        if offset + METADATA_SIZE as u64 > len {
+            return Err(CoreMLError::BadWeightFile(format!(
+                "metadata of blob {i} at {offset} is past the end of the file"
+            )));
+        }
+        read_at(offset, &mut record).map_err(CoreMLError::IoError)?;
+        let sentinel = read_u32(&record, 0);
+        if sentinel != SENTINEL {
+            return Err(CoreMLError::BadWeightFile(format!(
+                "bad sentinel {sentinel:#x} for blob {i} at {offset}"
+            )));
+        }
+        let blob = BlobInfo {
+            offset,
+            dtype: BlobDataType::from_raw(read_u32(&record, 4)),
+            size: read_u64(&record, 8),
+            data_offset: read_u64(&record, 16),
+            padding_bits: read_u64(&record, 24),
+        };
+        let end = blob
+            .data_offset
+            .checked_add(blob.size)
+            .filter(|end| *end <= len)
+            .ok_or_else(|| {
+                CoreMLError::BadWeightFile(format!(
+                    "data of blob {i} at {} with size {} is past the end of the file",
+                    blob.data_offset, blob.size
+                ))
+            })?;
+        offset = end.next_multiple_of(ALIGNMENT as u64);
+        blobs.push(blob);
+    }
+    Ok((version, blobs))
+}
+
+fn vec_to_array<T: crate::mlarray::MLType>(v: Vec<T>) -> MLArray {
+    Array::from_vec(v).into_dyn().into()
+}
+
+fn read_u32(buf: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(buf[at..at + 4].try_into().unwrap())
+}
+
+fn read_u64(buf: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(buf[at..at + 8].try_into().unwrap())
+}
+
+fn le_chunks<const N: usize, T>(bytes: &[u8], f: impl Fn([u8; N]) -> T) -> Vec<T> {
+    bytes
+        .chunks_exact(N)
+        .map(|c| f(c.try_into().unwrap()))
+        .collect()
+}
+
+/// Sub-byte elements are packed least significant bit first.
+fn unpack_bits(bytes: &[u8], bits: usize, len: usize) -> Vec<u8> {
+    let mask = (1u16 << bits) - 1;
+    (0..len)
+        .map(|i| {
+            let bit = i * bits;
+            let (byte, shift) = (bit / 8, bit % 8);
+            let lo = bytes[byte] as u16;
+            let hi = bytes.get(byte + 1).copied().unwrap_or(0) as u16;
+            (((lo | (hi << 8)) >> shift) & mask) as u8
+        })
+        .collect()
+}
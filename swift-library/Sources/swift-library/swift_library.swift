@@ -50,10 +50,21 @@ class BatchModel: @unchecked Sendable {
 		self.failedToLoad = failedToLoad
 	}
 
+	func getCompiledPath() -> RustString? {
+		return self.compiledPath?.absoluteString.intoRustString()
+	}
+
 	func hasFailedToLoad() -> Bool {
 		return self.failedToLoad
 	}
 
+	func computePlanJson() -> RustString {
+		return loadComputePlanJson(
+			compiledPath: self.compiledPath, asset: self.modelCompiledAsset,
+			computeUnits: self.computeUnits
+		).intoRustString()
+	}
+
 	func description() -> ModelDescription {
 		return ModelDescription(desc: self.model?.modelDescription)
 	}
@@ -112,7 +123,41 @@ class BatchModel: @unchecked Sendable {
 				dataPointer: data, shape: arr, dataType: MLMultiArrayDataType.float32,
 				strides: stride, deallocator: deallocMultiArrayRust)
 			let value = MLFeatureValue(multiArray: array)
-			if self.inputs.count <= idx {
+			while self.inputs.count <= idx {
+				self.inputs.append(BatchModelInput.init())
+			}
+			self.inputs[idx].dict[featureName.toString()] = value
+			return true
+		} catch {
+			print("Unexpected input error; \(error)")
+			return false
+		}
+	}
+
+	func bindInputU16(
+		shape: RustVec<UInt>, featureName: RustString, data: UnsafeMutablePointer<UInt16>,
+		len: UInt, idx: Int
+	) -> Bool {
+		do {
+			var arr: [NSNumber] = []
+			var stride: [NSNumber] = []
+			var m: UInt = 1
+			for i in shape.reversed() {
+				stride.append(NSNumber(value: m))
+				m = i * m
+			}
+			stride.reverse()
+			for s in shape {
+				arr.append(NSNumber(value: s))
+			}
+			let deallocMultiArrayRust = { (_ ptr: UnsafeMutableRawPointer) in
+				rust_vec_free_u16(ptr.assumingMemoryBound(to: UInt16.self), len)
+			}
+			let array = try MLMultiArray.init(
+				dataPointer: data, shape: arr, dataType: MLMultiArrayDataType.float16,
+				strides: stride, deallocator: deallocMultiArrayRust)
+			let value = MLFeatureValue(multiArray: array)
+			while self.inputs.count <= idx {
 				self.inputs.append(BatchModelInput.init())
 			}
 			self.inputs[idx].dict[featureName.toString()] = value
@@ -123,6 +168,10 @@ class BatchModel: @unchecked Sendable {
 		}
 	}
 
+	func clearInputs() {
+		self.inputs = []
+	}
+
 	func predict() -> BatchOutput {
 		do {
 			let opts = MLPredictionOptions.init()
@@ -193,6 +242,19 @@ class ModelDescription {
 		}
 		return RustVec.init()
 	}
+	/// Element type of a multi array input, `f32`, `f16`, `i32` or `f64`, empty otherwise.
+	func input_type(name: RustString) -> RustString {
+		if failedToLoad() { return "".intoRustString() }
+		let res = self.description!.inputDescriptionsByName[name.toString()]
+		guard let arr = res?.multiArrayConstraint else { return "".intoRustString() }
+		switch arr.dataType {
+		case .float32: return "f32".intoRustString()
+		case .float16: return "f16".intoRustString()
+		case .int32: return "i32".intoRustString()
+		case .double: return "f64".intoRustString()
+		default: return "".intoRustString()
+		}
+	}
 	func input_shape(name: RustString) -> RustVec<UInt> {
 		if !failedToLoad() {
 			let res = self.description?.inputDescriptionsByName[name.toString()]
@@ -208,6 +270,16 @@ class ModelDescription {
 		return RustVec.init()
 	}
 
+	func required_input_names() -> RustVec<RustString> {
+		let ret = RustVec<RustString>()
+		if !failedToLoad() {
+			for (key, value) in self.description!.inputDescriptionsByName where !value.isOptional {
+				ret.push(value: key.intoRustString())
+			}
+		}
+		return ret
+	}
+
 	func output_names() -> RustVec<RustString> {
 		if !failedToLoad() {
 			let ret = RustVec<RustString>()
@@ -218,6 +290,76 @@ class ModelDescription {
 		}
 		return RustVec.init()
 	}
+
+	func metadata(key: RustString) -> RustString? {
+		if failedToLoad() { return nil }
+		var metadataKey: MLModelMetadataKey
+		switch key.toString() {
+		case "author":
+			metadataKey = .author
+		case "description":
+			metadataKey = .description
+		case "version":
+			metadataKey = .versionString
+		case "license":
+			metadataKey = .license
+		default:
+			return nil
+		}
+		guard let value = self.description!.metadata[metadataKey] as? String, !value.isEmpty
+		else { return nil }
+		return value.intoRustString()
+	}
+
+	func userDefined() -> [String: String] {
+		if failedToLoad() { return [:] }
+		return self.description!.metadata[.creatorDefinedKey] as? [String: String] ?? [:]
+	}
+
+	func user_defined_keys() -> RustVec<RustString> {
+		let ret = RustVec<RustString>()
+		for (key, _) in userDefined() {
+			ret.push(value: key.intoRustString())
+		}
+		return ret
+	}
+
+	func user_defined_value(key: RustString) -> RustString? {
+		return userDefined()[key.toString()]?.intoRustString()
+	}
+
+	func predicted_probabilities_name() -> RustString? {
+		if failedToLoad() { return nil }
+		return self.description!.predictedProbabilitiesName?.intoRustString()
+	}
+
+	func classLabels() -> [Any] {
+		if failedToLoad() { return [] }
+		if #available(macOS 14.0, *) {
+			return self.description!.classLabels ?? []
+		}
+		return []
+	}
+
+	func class_labels_string() -> RustVec<RustString> {
+		let ret = RustVec<RustString>()
+		for label in classLabels() {
+			if let label = label as? String {
+				ret.push(value: label.intoRustString())
+			}
+		}
+		return ret
+	}
+
+	func class_labels_int() -> RustVec<Int64> {
+		let ret = RustVec<Int64>()
+		for label in classLabels() {
+			if let label = label as? NSNumber {
+				ret.push(value: label.int64Value)
+			}
+		}
+		return ret
+	}
 }
 
 class ModelOutput {
@@ -288,6 +430,28 @@ class ModelOutput {
 		}
 		return v
 	}
+	// probability dictionaries of classifiers, keys and values in the same (key sorted) order
+	func sortedDictionary(name: String) -> [(String, Double)] {
+		if hasFailedToLoad() { return [] }
+		guard let value = self.output?[name] as? MLFeatureValue else { return [] }
+		return value.dictionaryValue.map { ("\($0.key)", $0.value.doubleValue) }.sorted {
+			$0.0 < $1.0
+		}
+	}
+	func dictionaryKeys(name: RustString) -> RustVec<RustString> {
+		let ret = RustVec<RustString>()
+		for (key, _) in sortedDictionary(name: name.toString()) {
+			ret.push(value: key.intoRustString())
+		}
+		return ret
+	}
+	func dictionaryValues(name: RustString) -> RustVec<Double> {
+		let ret = RustVec<Double>()
+		for (_, value) in sortedDictionary(name: name.toString()) {
+			ret.push(value: value)
+		}
+		return ret
+	}
 	func outputU16(name: RustString) -> RustVec<UInt16> {
 		if hasFailedToLoad() { return RustVec.init() }
 		let output = self.output!
@@ -321,11 +485,8 @@ func initWithCompiledAsset(
 		computeUnits = .cpuAndGPU
 		break
 	}
-	let data = Data.init(
-		bytesNoCopy: ptr, count: len,
-		deallocator: Data.Deallocator.custom { ptr, len in
-			rust_vec_free_u8(ptr.assumingMemoryBound(to: UInt8.self), UInt(len))
-		})
+	// owned by Rust, which keeps it alive for as long as the model
+	let data = Data.init(bytesNoCopy: ptr, count: len, deallocator: .none)
 	do {
 		let m = Model.init(failedToLoad: false)
 		m.modelCompiledAsset = try MLModelAsset.init(specification: data)
@@ -352,11 +513,8 @@ func initWithCompiledAssetBatch(
 		computeUnits = .cpuAndGPU
 		break
 	}
-	let data = Data.init(
-		bytesNoCopy: ptr, count: len,
-		deallocator: Data.Deallocator.custom { ptr, len in
-			rust_vec_free_u8(ptr.assumingMemoryBound(to: UInt8.self), UInt(len))
-		})
+	// owned by Rust, which keeps it alive for as long as the model
+	let data = Data.init(bytesNoCopy: ptr, count: len, deallocator: .none)
 	do {
 		let m = BatchModel.init(failedToLoad: false)
 		m.modelCompiledAsset = try MLModelAsset.init(specification: data)
@@ -368,6 +526,134 @@ func initWithCompiledAssetBatch(
 	}
 }
 
+final class ComputePlanBox: @unchecked Sendable {
+	var json: String = ""
+}
+
+func errorJson(_ message: String) -> String {
+	let data = try? JSONSerialization.data(withJSONObject: ["error": message])
+	return data.flatMap { String(data: $0, encoding: .utf8) } ?? "{\"error\": \"unknown\"}"
+}
+
+@available(macOS 14.4, *)
+func computeDeviceName(_ device: MLComputeDevice) -> String {
+	switch device {
+	case .cpu: return "cpu"
+	case .gpu: return "gpu"
+	case .neuralEngine: return "neuralEngine"
+	@unknown default: return "unknown"
+	}
+}
+
+@available(macOS 14.4, *)
+func computePlanOperations(_ plan: MLComputePlan) -> [[String: Any]] {
+	var ops: [[String: Any]] = []
+	func addUsage(_ entry: inout [String: Any], _ usage: MLComputePlanDeviceUsage?) {
+		guard let usage else { return }
+		entry["preferred"] = computeDeviceName(usage.preferred)
+		entry["supported"] = usage.supported.map(computeDeviceName)
+	}
+	func visitBlock(_ block: MLModelStructure.Program.Block, function: String) {
+		for op in block.operations {
+			var entry: [String: Any] = [
+				"function": function,
+				"name": op.outputs.map { $0.name }.joined(separator: ","),
+				"operator": op.operatorName,
+			]
+			addUsage(&entry, plan.deviceUsage(for: op))
+			if let cost = plan.estimatedCost(of: op) {
+				entry["estimatedCost"] = cost.weight
+			}
+			ops.append(entry)
+			for nested in op.blocks {
+				visitBlock(nested, function: function)
+			}
+		}
+	}
+	func visitStructure(_ structure: MLModelStructure) {
+		switch structure {
+		case .program(let program):
+			for (name, function) in program.functions {
+				visitBlock(function.block, function: name)
+			}
+		case .neuralNetwork(let network):
+			for layer in network.layers {
+				var entry: [String: Any] = ["name": layer.name, "operator": layer.type]
+				addUsage(&entry, plan.deviceUsage(for: layer))
+				ops.append(entry)
+			}
+		case .pipeline(let pipeline):
+			for model in pipeline.subModels {
+				visitStructure(model)
+			}
+		default:
+			break
+		}
+	}
+	visitStructure(plan.modelStructure)
+	return ops
+}
+
+/// Compute plan as `{"operations": [...]}`, or `{"error": "..."}` if it is not available.
+func loadComputePlanJson(compiledPath: URL?, asset: MLModelAsset?, computeUnits: MLComputeUnits)
+	-> String
+{
+	guard #available(macOS 14.4, *) else {
+		return errorJson("MLComputePlan requires macOS 14.4 or newer")
+	}
+	let config = MLModelConfiguration.init()
+	config.computeUnits = computeUnits
+	let box = ComputePlanBox()
+	let semaphore = DispatchSemaphore(value: 0)
+	Task {
+		do {
+			var plan: MLComputePlan? = nil
+			if let compiledPath {
+				plan = try await MLComputePlan.load(contentsOf: compiledPath, configuration: config)
+			} else if let asset {
+				plan = try await MLComputePlan.load(asset: asset, configuration: config)
+			}
+			if let plan {
+				let data = try JSONSerialization.data(withJSONObject: [
+					"operations": computePlanOperations(plan)
+				])
+				box.json = String(data: data, encoding: .utf8) ?? errorJson("invalid utf8")
+			} else {
+				box.json = errorJson("model has neither a compiled path nor an asset")
+			}
+		} catch {
+			box.json = errorJson(error.localizedDescription)
+		}
+		semaphore.signal()
+	}
+	semaphore.wait()
+	return box.json
+}
+
+// accepts both plain paths and the `file://` urls handed out by getCompiledPath
+func urlFromRustPath(_ path: String) -> URL {
+	if path.hasPrefix("file://") {
+		return URL(string: path)!
+	}
+	return URL(fileURLWithPath: path)
+}
+
+/// `{"path": "..."}` of the compiled model, or `{"error": "..."}`.
+func compileModelAt(path: RustString) -> RustString {
+	let url = urlFromRustPath(path.toString())
+	do {
+		let compiled = try MLModel.compileModel(at: url)
+		let data = try JSONSerialization.data(withJSONObject: ["path": compiled.path])
+		return (String(data: data, encoding: .utf8) ?? errorJson("invalid utf8")).intoRustString()
+	} catch {
+		return errorJson(error.localizedDescription).intoRustString()
+	}
+}
+
+func osVersionString() -> RustString {
+	return ProcessInfo.processInfo.operatingSystemVersionString.intoRustString()
+}
+
 func initWithPath(path: RustString, compute: ComputePlatform, compiled: Bool) -> Model {
 	var computeUnits: MLComputeUnits
 	switch compute {
@@ -383,9 +669,9 @@ func initWithPath(path: RustString, compute: ComputePlatform, compiled: Bool) ->
 	}
 	var compiledPath: URL
 	if compiled {
-		compiledPath = URL(string: path.toString())!
+		compiledPath = urlFromRustPath(path.toString())
 	} else {
-		let url = URL(string: path.toString())!
+		let url = urlFromRustPath(path.toString())
 		do {
 			compiledPath = try MLModel.compileModel(at: url)
 		} catch {
@@ -413,9 +699,9 @@ func initWithPathBatch(path: RustString, compute: ComputePlatform, compiled: Boo
 	}
 	var compiledPath: URL
 	if compiled {
-		compiledPath = URL(string: path.toString())!
+		compiledPath = urlFromRustPath(path.toString())
 	} else {
-		let url = URL(string: path.toString())!
+		let url = urlFromRustPath(path.toString())
 		do {
 			compiledPath = try MLModel.compileModel(at: url)
 		} catch {
@@ -461,6 +747,13 @@ class Model: @unchecked Sendable {
 		return self.failedToLoad
 	}
 
+	func computePlanJson() -> RustString {
+		return loadComputePlanJson(
+			compiledPath: self.compiledPath, asset: self.modelCompiledAsset,
+			computeUnits: self.computeUnits
+		).intoRustString()
+	}
+
 	func load() -> Bool {
 		if hasFailedToLoad() { return false }
 		let config = MLModelConfiguration.init()
@@ -547,6 +840,30 @@ class Model: @unchecked Sendable {
 		}
 	}
 
+	func clearInputs() {
+		self.dict = [:]
+	}
+
+	// classifier outputs are dictionaries/strings, so they can't use output backings
+	func predictClassifier() -> ModelOutput {
+		if hasFailedToLoad() {
+			return ModelOutput(
+				output: nil, error: RuntimeError("Failed to load model; can't run predict"))
+		}
+		do {
+			let input = try MLDictionaryFeatureProvider.init(dictionary: self.dict)
+			let output = try self.model!.prediction(from: input)
+			self.dict = [:]
+			var outputs: [String: Any] = [:]
+			for name in output.featureNames {
+				outputs[name] = output.featureValue(for: name)
+			}
+			return ModelOutput(output: outputs, error: nil, cpy: true)
+		} catch {
+			return ModelOutput(output: nil, error: error)
+		}
+	}
+
 	func bindInputF32(
 		shape: RustVec<UInt>, featureName: RustString, data: UnsafeMutablePointer<Float32>,
 		len: UInt
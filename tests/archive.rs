@@ -0,0 +1,74 @@
+use coreml_rs::{archive::extract_mlpackage, mlmodel::CoreMLError, mlpackage::MLPackageBuilder};
+use std::{
+    io::{Cursor, Write},
+    path::Path,
+};
+use zip::write::SimpleFileOptions;
+
+/// Zips a valid package under `model.mlpackage/` plus the `extra` entries, which are
+/// symlinks to the given target or files.
+fn archive(extra: &[(&str, Option<&str>)]) -> Vec<u8> {
+    let dir = tempdir::TempDir::new("coreml-archive-src").unwrap();
+    let package = dir.path().join("model.mlpackage");
+    MLPackageBuilder::new(b"spec".to_vec())
+        .write(&package)
+        .unwrap();
+
+    let mut zip = zip::ZipWriter::new(Cursor::new(vec![]));
+    let options = SimpleFileOptions::default();
+    let mut stack = vec![package.clone()];
+    while let Some(dir) = stack.pop() {
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                stack.push(path);
+                continue;
+            }
+            let name = Path::new("model.mlpackage").join(path.strip_prefix(&package).unwrap());
+            zip.start_file(name.to_string_lossy(), options).unwrap();
+            zip.write_all(&std::fs::read(&path).unwrap()).unwrap();
+        }
+    }
+    for (name, target) in extra {
+        match target {
+            Some(target) => zip.add_symlink(*name, *target, options).unwrap(),
+            None => {
+                zip.start_file(*name, options).unwrap();
+                zip.write_all(b"x").unwrap();
+            }
+        }
+    }
+    zip.finish().unwrap().into_inner()
+}
+
+#[test]
+pub fn extracts_package() {
+    let dir = tempdir::TempDir::new("coreml-archive").unwrap();
+    let extracted = extract_mlpackage(Cursor::new(archive(&[])), dir.path()).unwrap();
+    assert!(extracted.path().join("Manifest.json").is_file());
+    assert!(extracted.path().starts_with(dir.path()));
+}
+
+#[test]
+pub fn rejects_escaping_entries() {
+    let cases = [
+        ("model.mlpackage/../../escaped", None),
+        ("/tmp/escaped", None),
+        ("model.mlpackage/Data/escaped", Some("/etc")),
+    ];
+    for entry in cases {
+        let root = tempdir::TempDir::new("coreml-archive").unwrap();
+        let dest = root.path().join("dest");
+        let res = extract_mlpackage(Cursor::new(archive(&[entry])), &dest);
+        assert!(
+            matches!(res, Err(CoreMLError::BadArchive(_))),
+            "{entry:?}: {res:?}"
+        );
+        // nothing got extracted, inside or outside of the destination
+        assert!(!root.path().join("escaped").exists(), "{entry:?}");
+        assert!(
+            !dest.exists() || std::fs::read_dir(&dest).unwrap().next().is_none(),
+            "{entry:?}"
+        );
+    }
+}
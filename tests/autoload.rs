@@ -0,0 +1,134 @@
+mod common;
+
+use common::MockModel;
+use coreml_rs::{
+    autoload::{AutoLoadModel, AutoLoadOptions, Transition},
+    lifecycle::ModelLifecycle,
+    mlmodel::CoreMLModelLoader,
+    CoreMLModelOptions,
+};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+fn recorded() -> (Arc<Mutex<Vec<Transition>>>, AutoLoadOptions) {
+    let transitions = Arc::new(Mutex::new(vec![]));
+    let hook = transitions.clone();
+    let opts = AutoLoadOptions {
+        on_transition: Some(Arc::new(move |t| hook.lock().unwrap().push(t.clone()))),
+        ..Default::default()
+    };
+    (transitions, opts)
+}
+
+#[test]
+pub fn loads_on_first_use() {
+    let (transitions, opts) = recorded();
+    let model = ModelLifecycle::<MockModel>::from_buf(b"ok model".to_vec(), Default::default());
+    let model = AutoLoadModel::new(model, opts);
+    assert!(!model.is_loaded());
+
+    let len = model
+        .with(|model| Ok(model.model()?.bytes().unwrap().len()))
+        .unwrap();
+    assert_eq!(len, 8);
+    assert!(model.is_loaded());
+    model.with(|_| Ok(())).unwrap();
+
+    let transitions = transitions.lock().unwrap();
+    assert_eq!(transitions.len(), 1);
+    assert!(matches!(transitions[0], Transition::Loaded { .. }));
+}
+
+#[test]
+pub fn failed_load_is_reported() {
+    let (transitions, opts) = recorded();
+    let model = ModelLifecycle::<MockModel>::from_buf(b"bad".to_vec(), Default::default());
+    let model = AutoLoadModel::new(model, opts);
+    assert!(model.with(|_| Ok(())).is_err());
+    assert!(!model.is_loaded());
+    assert!(matches!(
+        transitions.lock().unwrap()[..],
+        [Transition::LoadFailed { .. }]
+    ));
+}
+
+#[test]
+pub fn unloads_when_idle() {
+    let (transitions, mut opts) = recorded();
+    opts.idle_timeout = Some(Duration::from_millis(50));
+    let model = ModelLifecycle::<MockModel>::from_buf(b"ok model".to_vec(), Default::default());
+    let model = AutoLoadModel::new(model, opts);
+
+    model.with(|_| Ok(())).unwrap();
+    // kept loaded while in use
+    for _ in 0..5 {
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(model.is_loaded());
+        model.with(|_| Ok(())).unwrap();
+    }
+    std::thread::sleep(Duration::from_millis(300));
+    assert!(!model.is_loaded());
+
+    // and loaded again on the next use
+    model.with(|_| Ok(())).unwrap();
+    assert!(model.is_loaded());
+    let transitions = transitions.lock().unwrap();
+    assert!(matches!(
+        transitions[..],
+        [
+            Transition::Loaded { .. },
+            Transition::Unloaded { idle },
+            Transition::Loaded { .. }
+        ] if idle >= Duration::from_millis(50)
+    ));
+}
+
+#[test]
+pub fn manual_unload_is_reported() {
+    let (transitions, opts) = recorded();
+    let model = ModelLifecycle::<MockModel>::from_buf(b"ok model".to_vec(), Default::default());
+    let model = AutoLoadModel::new(model, opts);
+    // nothing to report while not loaded
+    model.unload().unwrap();
+
+    model.with(|_| Ok(())).unwrap();
+    model.unload().unwrap();
+    assert!(!model.is_loaded());
+    assert!(matches!(
+        transitions.lock().unwrap()[..],
+        [Transition::Loaded { .. }, Transition::Unloaded { .. }]
+    ));
+}
+
+#[test]
+pub fn unloads_to_disk_when_idle() {
+    let dir = std::env::temp_dir().join(format!("coreml-rs-autoload-{}", std::process::id()));
+    let model = ModelLifecycle::<MockModel>::from_buf(
+        b"ok model".to_vec(),
+        CoreMLModelOptions {
+            cache_dir: dir.clone(),
+            ..Default::default()
+        },
+    );
+    let model = AutoLoadModel::new(
+        model,
+        AutoLoadOptions {
+            idle_timeout: Some(Duration::from_millis(20)),
+            unload_to_disk: true,
+            ..Default::default()
+        },
+    );
+    model.with(|_| Ok(())).unwrap();
+    std::thread::sleep(Duration::from_millis(200));
+    assert!(!model.is_loaded());
+    assert!(model
+        .with(|model| Ok(matches!(model.loader(), CoreMLModelLoader::BufferToDisk(_))))
+        .unwrap());
+    assert!(model
+        .with(|model| Ok(model.model()?.bytes() == Some(&b"ok model"[..])))
+        .unwrap());
+    drop(model);
+    _ = std::fs::remove_dir_all(dir);
+}
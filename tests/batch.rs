@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+
+use coreml_rs::{
+    batch::{split_batch, stack, BatchBuilder},
+    mlarray::MLArray,
+    mlbatchmodel::MLBatchModelOutput,
+    mlmodel::CoreMLError,
+};
+use half::f16;
+use ndarray::Array;
+
+fn item(names: &[&str]) -> HashMap<String, MLArray> {
+    names
+        .iter()
+        .map(|name| (name.to_string(), Array::<f32, _>::zeros(vec![1]).into()))
+        .collect()
+}
+
+#[test]
+pub fn builder_push_and_clear() {
+    let mut batch = BatchBuilder::new();
+    assert!(batch.is_empty());
+    assert_eq!(batch.push(item(&["a"])), 0);
+    assert_eq!(batch.push(item(&["a"])), 1);
+    assert_eq!(batch.len(), 2);
+    batch.clear();
+    assert!(batch.is_empty());
+}
+
+#[test]
+pub fn builder_validate() {
+    let required = vec!["a".to_string(), "b".to_string()];
+    let mut batch = BatchBuilder::new();
+    assert!(matches!(
+        batch.validate(&required),
+        Err(CoreMLError::BadInputShape(_))
+    ));
+
+    batch.push(item(&["a", "b"]));
+    batch.push(item(&["b", "c"]));
+    let Err(CoreMLError::BadInputShape(err)) = batch.validate(&required) else {
+        panic!("expected item 1 to be missing an input");
+    };
+    assert!(err.contains("item 1") && err.contains("\"a\""), "{err}");
+
+    batch.clear();
+    batch.push(item(&["a", "b", "c"]));
+    batch.validate(&required).unwrap();
+    assert_eq!(batch.drain().count(), 1);
+    assert!(batch.is_empty());
+}
+
+#[test]
+pub fn split_and_stack() {
+    let input: MLArray = Array::from_shape_fn((3, 2, 2), |(n, i, j)| (n * 4 + i * 2 + j) as f32)
+        .into_dyn()
+        .into();
+
+    let items = split_batch(input, 0, true).unwrap();
+    assert_eq!(items.len(), 3);
+    assert!(items.iter().all(|item| item.shape() == [1, 2, 2]));
+
+    let output = MLBatchModelOutput {
+        outputs: items
+            .into_iter()
+            .map(|item| HashMap::from([("y".to_string(), item)]))
+            .collect(),
+    };
+    let MLArray::Float32Array(stacked) = output.stack("y").unwrap() else {
+        panic!("expected an f32 array");
+    };
+    assert_eq!(stacked.shape(), &[3, 1, 2, 2]);
+    assert_eq!(
+        stacked.iter().copied().collect::<Vec<_>>(),
+        (0..12).map(|v| v as f32).collect::<Vec<_>>()
+    );
+    assert!(output.stack("missing").is_err());
+
+    // splitting along an inner axis drops it and keeps the items row major
+    let input: MLArray = Array::from_shape_fn((2, 3), |(i, j)| f16::from_f32((i * 3 + j) as f32))
+        .into_dyn()
+        .into();
+    let items = split_batch(input, 1, false).unwrap();
+    let MLArray::Float16Array(stacked) = stack(&items.iter().collect::<Vec<_>>()).unwrap() else {
+        panic!("expected an f16 array");
+    };
+    assert_eq!(stacked.shape(), &[3, 2]);
+    assert_eq!(stacked[[2, 1]], f16::from_f32(5.0));
+
+    assert!(split_batch(Array::<f32, _>::zeros(vec![2]).into_dyn().into(), 1, false).is_err());
+    let mixed: Vec<MLArray> = vec![
+        Array::<f32, _>::zeros(vec![2]).into_dyn().into(),
+        Array::<f32, _>::zeros(vec![3]).into_dyn().into(),
+    ];
+    assert!(stack(&mixed.iter().collect::<Vec<_>>()).is_err());
+}
@@ -0,0 +1,77 @@
+use coreml_rs::{
+    cache::{CacheKey, CompiledModelCache},
+    CoreMLModelOptions,
+};
+use std::{path::Path, time::Duration};
+
+/// A fake compiled model with a 1000 byte weight file.
+fn compiled_model(dir: &Path, name: &str) -> std::path::PathBuf {
+    let path = dir.join(name).join("model.mlmodelc");
+    std::fs::create_dir_all(path.join("weights")).unwrap();
+    std::fs::write(path.join("coremldata.bin"), name).unwrap();
+    std::fs::write(path.join("weights/weight.bin"), [0u8; 1000]).unwrap();
+    path
+}
+
+#[test]
+pub fn evicts_least_recently_used() {
+    let dir = tempdir::TempDir::new("coreml-cache").unwrap();
+    let opts = CoreMLModelOptions {
+        cache_dir: dir.path().to_path_buf(),
+        compiled_cache_limit: Some(3700),
+        ..Default::default()
+    };
+    let cache = CompiledModelCache::from_options(&opts);
+    let keys: Vec<_> = ["a", "b", "c", "d"]
+        .iter()
+        .map(|name| CacheKey::from_bytes(name.as_bytes(), &opts))
+        .collect();
+    let insert = |i: usize| {
+        let compiled = compiled_model(&dir.path().join("src"), &i.to_string());
+        cache.insert(&keys[i], compiled).unwrap();
+        // keep the last used times apart
+        std::thread::sleep(Duration::from_millis(20));
+    };
+
+    for i in 0..3 {
+        insert(i);
+    }
+    assert!(cache.size().unwrap() <= 3700);
+    // a is used again, so b is now the least recently used
+    assert!(cache.get(&keys[0]).is_some());
+    std::thread::sleep(Duration::from_millis(20));
+    insert(3);
+    assert!(cache.size().unwrap() <= 3700);
+    let cached: Vec<_> = keys.iter().map(|k| cache.entry_path(k).exists()).collect();
+    assert_eq!(cached, [true, false, true, true]);
+
+    // only the newest entry fits the smaller limit
+    let freed = CompiledModelCache::new(cache.root())
+        .with_max_size(1300)
+        .gc()
+        .unwrap();
+    assert!(freed >= 2000);
+    let cached: Vec<_> = keys.iter().map(|k| cache.entry_path(k).exists()).collect();
+    assert_eq!(cached, [false, false, false, true]);
+    assert!(cache.get(&keys[3]).is_some());
+}
+
+#[test]
+pub fn options_debug_lists_every_option() {
+    let opts = CoreMLModelOptions {
+        compiled_cache_limit: Some(4096),
+        warm_up: 2,
+        ..Default::default()
+    };
+    let debug = format!("{opts:?}");
+    for field in [
+        "compute_platform",
+        "cache_dir",
+        "compiled_cache_limit: Some(4096)",
+        "buffer_compression: Zlib(9)",
+        "warm_up: 2",
+        "warm_up_fill: Zeros",
+    ] {
+        assert!(debug.contains(field), "{field} missing from {debug}");
+    }
+}
@@ -0,0 +1,27 @@
+use coreml_rs::{
+    classifier::{classify_logits, softmax, Label},
+    mlarray::MLArray,
+};
+use ndarray::Array;
+
+#[test]
+pub fn classify_raw_logits() {
+    let probs = softmax(&[1.0, 2.0, 3.0]);
+    assert!((probs.iter().sum::<f32>() - 1.0).abs() < 1e-6);
+
+    let logits: MLArray = Array::from_shape_vec((1, 3), vec![0.5f32, 3.0, -1.0])
+        .unwrap()
+        .into_dyn()
+        .into();
+    let labels = ["cat".into(), "dog".into(), "bird".into()];
+    let top = classify_logits(&logits, Some(&labels), 2).unwrap();
+    assert_eq!(top.len(), 2);
+    assert_eq!(top[0].0, Label::from("dog"));
+    assert_eq!(top[1].0, Label::from("cat"));
+    assert!(top[0].1 > top[1].1);
+
+    let top = classify_logits(&logits, None, 1).unwrap();
+    assert_eq!(top[0].0, Label::Int64(1));
+
+    assert!(classify_logits(&logits, Some(&labels[..2]), 1).is_err());
+}
@@ -0,0 +1,125 @@
+//! Helpers shared by the integration tests, each test crate uses only some of them.
+#![allow(dead_code)]
+
+use coreml_rs::{
+    diskbuffer::MappedBuffer,
+    lifecycle::ModelKind,
+    mlarray::MLArray,
+    mlmodel::{CoreMLError, CoreMLModelInfo},
+    synthetic::Fill,
+};
+use ndarray::{Array, IxDyn};
+
+/// Stands in for a CoreML model, holding on to the model bytes like the backend does
+/// until it is dropped.
+///
+/// Behaves according to its source: buffers starting with `ok` and any path load, paths
+/// compile to `<path>c`, and warming up fails for buffers ending with `cold`.
+#[derive(Debug)]
+pub struct MockModel {
+    pub source: MockSource,
+    pub warm_ups: usize,
+}
+
+#[derive(Debug)]
+pub enum MockSource {
+    Owned(Box<[u8]>),
+    Mapped(MappedBuffer),
+    /// The compiled path, as `compiled_path` reports it
+    Path(String),
+}
+
+impl MockModel {
+    fn new(source: MockSource) -> Self {
+        Self {
+            source,
+            warm_ups: 0,
+        }
+    }
+
+    /// The model bytes, `None` if loaded from a path.
+    pub fn bytes(&self) -> Option<&[u8]> {
+        match &self.source {
+            MockSource::Owned(buf) => Some(buf),
+            MockSource::Mapped(buf) => Some(buf),
+            MockSource::Path(_) => None,
+        }
+    }
+
+    pub fn is_mapped(&self) -> bool {
+        matches!(self.source, MockSource::Mapped(_))
+    }
+}
+
+impl ModelKind for MockModel {
+    fn from_path(path: String, _: &CoreMLModelInfo, compiled: bool) -> Self {
+        let compiled = if compiled { path } else { format!("{path}c") };
+        Self::new(MockSource::Path(format!("file://{compiled}")))
+    }
+
+    fn from_buffer(buf: Vec<u8>, _: &CoreMLModelInfo) -> Self {
+        Self::new(MockSource::Owned(buf.into_boxed_slice()))
+    }
+
+    fn from_mapped(buf: MappedBuffer, _: &CoreMLModelInfo) -> Self {
+        Self::new(MockSource::Mapped(buf))
+    }
+
+    fn load(&mut self) -> bool {
+        self.bytes().is_none_or(|buf| buf.starts_with(b"ok"))
+    }
+
+    fn compiled_path(&self) -> Option<String> {
+        match &self.source {
+            MockSource::Path(path) => Some(path.clone()),
+            _ => None,
+        }
+    }
+
+    fn warm_up(&mut self, n: usize, _: Fill) -> Result<(), CoreMLError> {
+        if self.bytes().is_some_and(|buf| buf.ends_with(b"cold")) {
+            return Err(CoreMLError::UnknownErrorStatic("too cold"));
+        }
+        self.warm_ups += n;
+        Ok(())
+    }
+}
+
+/// Protobuf encoding, to build model specs field by field.
+pub fn varint(mut v: u64, out: &mut Vec<u8>) {
+    while v >= 0x80 {
+        out.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
+
+pub fn varint_field(field: u64, v: u64) -> Vec<u8> {
+    let mut out = vec![];
+    varint(field << 3, &mut out);
+    varint(v, &mut out);
+    out
+}
+
+pub fn bytes_field(field: u64, data: &[u8]) -> Vec<u8> {
+    let mut out = vec![];
+    varint(field << 3 | 2, &mut out);
+    varint(data.len() as u64, &mut out);
+    out.extend(data);
+    out
+}
+
+/// Payload of a packed repeated varint field.
+pub fn packed(values: &[u64]) -> Vec<u8> {
+    let mut out = vec![];
+    for v in values {
+        varint(*v, &mut out);
+    }
+    out
+}
+
+pub fn f32_array(shape: &[usize], values: &[f32]) -> MLArray {
+    Array::from_shape_vec(IxDyn(shape), values.to_vec())
+        .unwrap()
+        .into()
+}
@@ -0,0 +1,14 @@
+use coreml_rs::{compile_model, mlmodel::CoreMLError};
+
+#[test]
+pub fn missing_model() {
+    let dir = tempdir::TempDir::new("coreml-compile").unwrap();
+    let dest = dir.path().join("model.mlmodelc");
+    let Err(CoreMLError::CompileError(err)) =
+        compile_model(dir.path().join("missing.mlpackage"), &dest)
+    else {
+        panic!("expected a compile error");
+    };
+    assert!(err.contains("missing.mlpackage"), "{err}");
+    assert!(!dest.exists());
+}
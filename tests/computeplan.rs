@@ -0,0 +1,54 @@
+use coreml_rs::computeplan::{ComputeDevice, ComputePlan};
+
+const PLAN: &str = r#"{"operations": [
+    {"function": "main", "name": "w", "operator": "const"},
+    {"function": "main", "name": "x_1", "operator": "conv", "preferred": "neuralEngine",
+     "supported": ["cpu", "gpu", "neuralEngine"], "estimatedCost": 0.6},
+    {"function": "main", "name": "x_2", "operator": "relu", "preferred": "neuralEngine",
+     "supported": ["cpu", "neuralEngine"], "estimatedCost": 0.1},
+    {"function": "main", "name": "y", "operator": "ios17.gather", "preferred": "cpu",
+     "supported": ["cpu"], "estimatedCost": 0.3}
+]}"#;
+
+#[test]
+pub fn summaries() {
+    let plan = ComputePlan::from_json(PLAN).unwrap();
+    assert_eq!(plan.operations.len(), 4);
+    assert_eq!(plan.placed().count(), 3);
+
+    let counts = plan.device_counts();
+    assert_eq!(counts[&ComputeDevice::NeuralEngine], 2);
+    assert_eq!(counts[&ComputeDevice::Cpu], 1);
+    assert!(!counts.contains_key(&ComputeDevice::Gpu));
+
+    assert!((plan.fraction_on(ComputeDevice::NeuralEngine) - 2.0 / 3.0).abs() < 1e-9);
+    let cost = plan.cost_fraction_on(ComputeDevice::NeuralEngine).unwrap();
+    assert!((cost - 0.7).abs() < 1e-9);
+
+    let fallbacks: Vec<_> = plan.not_on(ComputeDevice::NeuralEngine).collect();
+    assert_eq!(fallbacks.len(), 1);
+    assert_eq!(fallbacks[0].operator, "ios17.gather");
+}
+
+#[test]
+pub fn neural_network_without_costs() {
+    let plan = ComputePlan::from_json(
+        r#"{"operations": [{"name": "fc", "operator": "innerProduct", "preferred": "gpu",
+            "supported": ["cpu", "gpu", "someFutureDevice"]}]}"#,
+    )
+    .unwrap();
+    assert_eq!(plan.operations[0].function, "");
+    assert_eq!(plan.operations[0].supported[2], ComputeDevice::Unknown);
+    assert_eq!(plan.fraction_on(ComputeDevice::Gpu), 1.0);
+    assert_eq!(plan.cost_fraction_on(ComputeDevice::Gpu), None);
+    assert_eq!(ComputePlan::default().fraction_on(ComputeDevice::Cpu), 0.0);
+}
+
+#[test]
+pub fn json_round_trip() {
+    let plan = ComputePlan::from_json(PLAN).unwrap();
+    let json = plan.to_json().unwrap();
+    assert!(json.contains("\"estimatedCost\""));
+    assert_eq!(ComputePlan::from_json(&json).unwrap(), plan);
+    assert!(ComputePlan::from_json("{\"error\": \"nope\"}").is_err());
+}
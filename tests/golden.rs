@@ -0,0 +1,227 @@
+mod common;
+
+use common::f32_array;
+use coreml_rs::{
+    golden::{GoldenCase, GoldenFixture, Mismatch, Tolerance, Tolerances},
+    mlarray::MLArray,
+    npy,
+};
+use half::f16;
+use ndarray::{Array, IxDyn};
+use std::{collections::HashMap, io::Cursor};
+
+#[test]
+pub fn npy_round_trip() {
+    let arrays: Vec<MLArray> = vec![
+        f32_array(&[2, 3], &[0.0, 1.5, -2.0, 3.25, 4.0, 5.0]),
+        Array::from_shape_vec(IxDyn(&[3]), vec![f16::ONE, f16::NEG_ONE, f16::MAX])
+            .unwrap()
+            .into(),
+        Array::from_shape_vec(IxDyn(&[2]), vec![0.1f64, -1e300])
+            .unwrap()
+            .into(),
+        Array::from_shape_vec(IxDyn(&[1, 2]), vec![-7i8, 7])
+            .unwrap()
+            .into(),
+        Array::from_shape_vec(IxDyn(&[2]), vec![u32::MAX, 0])
+            .unwrap()
+            .into(),
+        Array::from_elem(IxDyn(&[]), 42i32).into(),
+    ];
+    for array in arrays {
+        let buf = npy::to_npy(&array);
+        // data starts 64 byte aligned, like numpy writes it
+        assert_eq!((buf.len() - array.nbytes()) % 64, 0);
+        let read = npy::from_npy(&buf).unwrap();
+        assert_eq!(format!("{read:?}"), format!("{array:?}"));
+    }
+}
+
+#[test]
+pub fn read_numpy_files() {
+    // np.save of np.array([[1, 2], [3, 4]], dtype="<f2"), and a version 2 header
+    let header = "{'descr': '<f2', 'fortran_order': False, 'shape': (2, 2), }";
+    let mut v1 = b"\x93NUMPY\x01\x00".to_vec();
+    let header = format!("{header:<117}\n");
+    v1.extend((header.len() as u16).to_le_bytes());
+    v1.extend(header.as_bytes());
+    for v in [1.0f32, 2.0, 3.0, 4.0] {
+        v1.extend(f16::from_f32(v).to_le_bytes());
+    }
+    let MLArray::Float16Array(array) = npy::from_npy(&v1).unwrap() else {
+        panic!("expected f16 array");
+    };
+    assert_eq!(array.shape(), [2, 2]);
+    assert_eq!(array[[1, 0]], f16::from_f32(3.0));
+
+    let header = "{\"descr\": \"<i4\", \"fortran_order\": False, \"shape\": (3,)}\n";
+    let mut v2 = b"\x93NUMPY\x02\x00".to_vec();
+    v2.extend((header.len() as u32).to_le_bytes());
+    v2.extend(header.as_bytes());
+    v2.extend([1i32, -2, 3].iter().flat_map(|v| v.to_le_bytes()));
+    let MLArray::Int32Array(array) = npy::from_npy(&v2).unwrap() else {
+        panic!("expected i32 array");
+    };
+    assert_eq!(array.as_slice().unwrap(), [1, -2, 3]);
+
+    let fortran = String::from_utf8(v1.clone())
+        .unwrap_or_default()
+        .replace("False", "True ");
+    assert!(npy::from_npy(fortran.as_bytes()).is_err());
+    assert!(npy::from_npy(&v1[..v1.len() - 1]).is_err());
+    assert!(npy::from_npy(b"not numpy").is_err());
+
+    // shapes whose size overflows are rejected, not wrapped around
+    let header = "{'descr': '<f4', 'fortran_order': False, 'shape': (4611686018427387904, 4), }\n";
+    let mut huge = b"\x93NUMPY\x01\x00".to_vec();
+    huge.extend((header.len() as u16).to_le_bytes());
+    huge.extend(header.as_bytes());
+    let Err(err) = npy::from_npy(&huge) else {
+        panic!("expected the shape to be rejected");
+    };
+    assert!(err.to_string().contains("too large"), "{err}");
+}
+
+#[test]
+pub fn npz_sizes_are_not_trusted() {
+    let array = f32_array(&[2], &[1.0, 2.0]);
+    let mut npz = Cursor::new(vec![]);
+    npy::write_npz(&mut npz, [("x", &array)]).unwrap();
+    // claim a huge uncompressed size in the zip64 fields of the local and central headers
+    let mut npz = npz.into_inner();
+    let mut patched = 0;
+    for i in 0..npz.len() - 12 {
+        if npz[i..i + 2] == [1, 0] && matches!(npz[i + 2..i + 4], [16 | 24 | 28, 0]) {
+            npz[i + 4..i + 12].copy_from_slice(&(u64::MAX / 2).to_le_bytes());
+            patched += 1;
+        }
+    }
+    assert_eq!(patched, 2);
+    // the entry fails on its size or reads back as written, but doesn't reserve the claim
+    if let Ok(arrays) = npy::read_npz(Cursor::new(npz)) {
+        let [(_, MLArray::Float32Array(x))] = &arrays[..] else {
+            panic!("expected one f32 array");
+        };
+        assert_eq!(x.as_slice().unwrap(), [1.0, 2.0]);
+    }
+}
+
+fn fixture() -> GoldenFixture {
+    let mut case = GoldenCase::default();
+    case.inputs
+        .insert("x".to_string(), f32_array(&[2], &[1.0, 2.0]));
+    case.outputs
+        .insert("probs".to_string(), f32_array(&[4], &[0.1, 0.2, 0.3, 0.4]));
+    case.outputs.insert(
+        "logits".to_string(),
+        f32_array(&[2, 2], &[1.0, -1.0, 2.0, -2.0]),
+    );
+    GoldenFixture { cases: vec![case] }
+}
+
+#[test]
+pub fn fixture_round_trip() {
+    let mut buf = Cursor::new(vec![]);
+    fixture().write(&mut buf).unwrap();
+    buf.set_position(0);
+    let read = GoldenFixture::read(&mut buf).unwrap();
+    assert_eq!(read.cases.len(), 1);
+    assert_eq!(
+        read.cases[0].outputs.keys().collect::<Vec<_>>(),
+        ["logits", "probs"]
+    );
+    assert_eq!(
+        format!("{:?}", read.cases[0].inputs["x"]),
+        format!("{:?}", fixture().cases[0].inputs["x"])
+    );
+
+    // entries are plain npy files, named for np.load
+    buf.set_position(0);
+    let names: Vec<_> = npy::read_npz(buf)
+        .unwrap()
+        .into_iter()
+        .map(|(n, _)| n)
+        .collect();
+    assert_eq!(names, ["inputs/0/x", "outputs/0/logits", "outputs/0/probs"]);
+
+    // case indices past the entry count would allocate for cases that can't exist
+    let x = f32_array(&[1], &[1.0]);
+    for entry in ["outputs/3/y", "outputs/18446744073709551615/y"] {
+        let mut buf = Cursor::new(vec![]);
+        npy::write_npz(&mut buf, [("inputs/0/x", &x), (entry, &x)]).unwrap();
+        buf.set_position(0);
+        assert!(GoldenFixture::read(buf).is_err(), "{entry}");
+    }
+}
+
+#[test]
+pub fn compare_outputs() {
+    let fixture = fixture();
+    let outputs = |probs: &[f32], logits: Option<MLArray>| {
+        let mut outputs = HashMap::from([("probs".to_string(), f32_array(&[4], probs))]);
+        if let Some(logits) = logits {
+            outputs.insert("logits".to_string(), logits);
+        }
+        vec![outputs]
+    };
+    let logits = || f32_array(&[2, 2], &[1.0, -1.0, 2.0, -2.0]);
+
+    let report = fixture.compare(
+        &outputs(&[0.1, 0.2, 0.3, 0.4], Some(logits())),
+        &Tolerances::default(),
+    );
+    assert!(report.passed(), "{report}");
+    let metrics = report.outputs[0].metrics.unwrap();
+    assert_eq!(metrics.mae, 0.0);
+    assert!((metrics.cosine - 1.0).abs() < 1e-9);
+
+    // probs off by 0.01 on average, 0.04 at most
+    let drifted = outputs(&[0.1, 0.2, 0.3, 0.44], Some(logits()));
+    let report = fixture.compare(&drifted, &Tolerances::default());
+    assert!(!report.passed());
+    let failure = report.failures().next().unwrap();
+    assert_eq!((failure.case, failure.name.as_str()), (0, "probs"));
+    assert!(matches!(
+        failure.mismatches[..],
+        [
+            Mismatch::Exceeds { metric: "mae", .. },
+            Mismatch::Exceeds {
+                metric: "cosine",
+                ..
+            }
+        ]
+    ));
+    let metrics = failure.metrics.unwrap();
+    assert!((metrics.mae - 0.01).abs() < 1e-6);
+    assert!((metrics.max_abs - 0.04).abs() < 1e-6);
+    assert!((metrics.rmse - 0.02).abs() < 1e-6);
+
+    let loose = Tolerances::default().output(
+        "probs",
+        Tolerance {
+            max_mae: Some(0.05),
+            max_abs: Some(0.05),
+            min_cosine: Some(0.99),
+            ..Default::default()
+        },
+    );
+    assert!(fixture.compare(&drifted, &loose).passed());
+
+    let report = fixture.compare(
+        &outputs(
+            &[0.1, 0.2, 0.3, 0.4],
+            Some(f32_array(&[4], &[1.0, -1.0, 2.0, -2.0])),
+        ),
+        &Tolerances::default(),
+    );
+    assert!(matches!(
+        report.outputs[0].mismatches[..],
+        [Mismatch::Shape { .. }]
+    ));
+    let report = fixture.compare(
+        &outputs(&[0.1, 0.2, 0.3, 0.4], None),
+        &Tolerances::default(),
+    );
+    assert_eq!(report.outputs[0].mismatches, [Mismatch::Missing]);
+    assert!(report.to_string().starts_with("1 of 2 outputs passed"));
+}
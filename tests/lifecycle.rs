@@ -0,0 +1,277 @@
+mod common;
+
+use common::MockModel;
+use coreml_rs::{
+    diskbuffer::BufferCompression,
+    lifecycle::{ModelLifecycle, ModelState},
+    mlmodel::{CoreMLError, CoreMLModelLoader},
+    CoreMLModelOptions,
+};
+
+#[test]
+pub fn load_and_unload() {
+    let mut model = ModelLifecycle::<MockModel>::from_buf(b"ok model".to_vec(), Default::default());
+    assert!(matches!(model.state(), ModelState::Unloaded));
+    assert!(matches!(model.model(), Err(CoreMLError::ModelNotLoaded)));
+
+    model.load().unwrap();
+    assert!(matches!(model.state(), ModelState::Loaded(_)));
+    assert_eq!(model.model().unwrap().bytes(), Some(&b"ok model"[..]));
+    // loading again keeps the model
+    model.load().unwrap();
+
+    model.unload();
+    assert!(matches!(model.state(), ModelState::Unloaded));
+    assert!(matches!(model.loader(), CoreMLModelLoader::Buffer(_)));
+    model.unload();
+    model.load().unwrap();
+}
+
+#[test]
+pub fn ensure_loaded() {
+    let mut model = ModelLifecycle::<MockModel>::from_buf(b"ok model".to_vec(), Default::default());
+    assert!(!model.is_loaded());
+    assert!(model.ensure_loaded().unwrap().bytes().is_some());
+    assert!(model.is_loaded());
+    model.unload();
+    assert!(!model.is_loaded());
+
+    let mut model = ModelLifecycle::<MockModel>::from_buf(b"bad".to_vec(), Default::default());
+    assert!(model.ensure_loaded().is_err());
+    assert!(!model.is_loaded());
+}
+
+#[test]
+pub fn errors_are_small_and_shareable() {
+    fn assert_send_sync<T: Send + Sync + 'static>() {}
+    assert_send_sync::<CoreMLError>();
+    assert!(std::mem::size_of::<CoreMLError>() <= 32);
+}
+
+#[test]
+pub fn failed_load_can_be_retried() {
+    let mut model = ModelLifecycle::<MockModel>::from_buf(b"bad".to_vec(), Default::default());
+    let Err(CoreMLError::FailedToLoad(err)) = model.load() else {
+        panic!("expected the load to fail");
+    };
+    let ModelState::Failed(reason) = model.state() else {
+        panic!("expected the failed state");
+    };
+    assert_eq!(reason, &CoreMLError::FailedToLoad(err).to_string());
+    assert!(model.model_mut().is_err());
+    assert!(model.load().is_err());
+}
+
+#[test]
+pub fn model_path_reloads_from_compiled_path() {
+    let mut model = ModelLifecycle::<MockModel>::new("/models/a.mlpackage", Default::default());
+    model.load().unwrap();
+    model.unload();
+    let CoreMLModelLoader::CompiledPath(path) = model.loader() else {
+        panic!("expected the compiled path");
+    };
+    assert_eq!(path.to_str(), Some("/models/a.mlpackagec"));
+    // CoreML decides whether a compiled model loads, not the layout validator
+    model.load().unwrap();
+    assert!(matches!(model.state(), ModelState::Loaded(_)));
+}
+
+#[test]
+pub fn unload_to_disk() {
+    let dir = tempdir::TempDir::new("coreml-lifecycle").unwrap();
+    for compression in [
+        BufferCompression::None,
+        BufferCompression::Zlib(6),
+        BufferCompression::Zstd(3),
+        BufferCompression::Lz4,
+    ] {
+        let opts = CoreMLModelOptions {
+            cache_dir: dir.path().to_path_buf(),
+            buffer_compression: compression,
+            ..Default::default()
+        };
+        let mut model = ModelLifecycle::<MockModel>::from_buf(b"ok model".to_vec(), opts);
+        model.load().unwrap();
+        model.unload_to_disk().unwrap();
+        assert!(matches!(model.state(), ModelState::Unloaded));
+        let CoreMLModelLoader::BufferToDisk(path) = model.loader() else {
+            panic!("expected the buffer on disk");
+        };
+        assert_eq!(path.parent(), Some(dir.path().join("buffers").as_path()));
+
+        model.load().unwrap();
+        assert_eq!(model.model().unwrap().bytes(), Some(&b"ok model"[..]));
+    }
+}
+
+#[test]
+pub fn buffers_on_disk_are_named_by_content() {
+    let dir = tempdir::TempDir::new("coreml-lifecycle").unwrap();
+    let opts = CoreMLModelOptions {
+        cache_dir: dir.path().to_path_buf(),
+        ..Default::default()
+    };
+    let mut paths = vec![];
+    for buf in [&b"ok model a"[..], b"ok model b", b"ok model a"] {
+        let mut model = ModelLifecycle::<MockModel>::from_buf(buf.to_vec(), opts.clone());
+        model.load().unwrap();
+        model.unload_to_disk().unwrap();
+        let CoreMLModelLoader::BufferToDisk(path) = model.loader() else {
+            panic!("expected the buffer on disk");
+        };
+        paths.push(path.clone());
+    }
+    assert_ne!(paths[0], paths[1]);
+    assert_eq!(paths[0], paths[2]);
+}
+
+#[test]
+pub fn corrupt_buffers_on_disk_fail_to_load() {
+    let dir = tempdir::TempDir::new("coreml-lifecycle").unwrap();
+    let opts = CoreMLModelOptions {
+        cache_dir: dir.path().to_path_buf(),
+        buffer_compression: BufferCompression::None,
+        ..Default::default()
+    };
+    let mut model = ModelLifecycle::<MockModel>::from_buf(b"ok model".to_vec(), opts);
+    model.load().unwrap();
+    model.unload_to_disk().unwrap();
+    let CoreMLModelLoader::BufferToDisk(path) = model.loader() else {
+        panic!("expected the buffer on disk");
+    };
+    let path = path.clone();
+    let mut file = std::fs::read(&path).unwrap();
+    *file.last_mut().unwrap() ^= 1;
+    std::fs::write(&path, &file).unwrap();
+    let Err(CoreMLError::FailedToLoad(err)) = model.load() else {
+        panic!("expected the load to fail");
+    };
+    assert!(err.contains("checksum mismatch"), "{err}");
+
+    std::fs::write(&path, b"not a buffer").unwrap();
+    assert!(model.load().is_err());
+}
+
+#[test]
+pub fn corrupt_buffer_lengths_fail_to_load() {
+    let dir = tempdir::TempDir::new("coreml-lifecycle").unwrap();
+    for compression in [
+        BufferCompression::Zlib(6),
+        BufferCompression::Zstd(3),
+        BufferCompression::Lz4,
+    ] {
+        let opts = CoreMLModelOptions {
+            cache_dir: dir.path().to_path_buf(),
+            buffer_compression: compression,
+            ..Default::default()
+        };
+        let mut model = ModelLifecycle::<MockModel>::from_buf(b"ok model".to_vec(), opts);
+        model.load().unwrap();
+        model.unload_to_disk().unwrap();
+        let CoreMLModelLoader::BufferToDisk(path) = model.loader() else {
+            panic!("expected the buffer on disk");
+        };
+        let path = path.clone();
+        // the uncompressed length follows the magic, version and codec
+        let mut file = std::fs::read(&path).unwrap();
+        for len in [u64::MAX, 7, 9] {
+            file[8..16].copy_from_slice(&len.to_le_bytes());
+            std::fs::write(&path, &file).unwrap();
+            assert!(
+                matches!(model.load(), Err(CoreMLError::FailedToLoad(_))),
+                "{compression:?} with length {len}"
+            );
+        }
+    }
+}
+
+#[test]
+pub fn damaged_buffers_on_disk_are_rewritten() {
+    let dir = tempdir::TempDir::new("coreml-lifecycle").unwrap();
+    for compression in [BufferCompression::None, BufferCompression::Zstd(3)] {
+        let opts = CoreMLModelOptions {
+            cache_dir: dir.path().to_path_buf(),
+            buffer_compression: compression,
+            ..Default::default()
+        };
+        let mut model = ModelLifecycle::<MockModel>::from_buf(b"ok model".to_vec(), opts.clone());
+        model.load().unwrap();
+        model.unload_to_disk().unwrap();
+        let CoreMLModelLoader::BufferToDisk(path) = model.loader() else {
+            panic!("expected the buffer on disk");
+        };
+        // keep the header, drop part of the payload
+        let file = std::fs::read(path).unwrap();
+        std::fs::write(path, &file[..file.len() - 2]).unwrap();
+
+        let mut other = ModelLifecycle::<MockModel>::from_buf(b"ok model".to_vec(), opts);
+        other.load().unwrap();
+        other.unload_to_disk().unwrap();
+        other.load().unwrap();
+        assert_eq!(other.model().unwrap().bytes(), Some(&b"ok model"[..]));
+        model.load().unwrap();
+        assert_eq!(model.model().unwrap().bytes(), Some(&b"ok model"[..]));
+    }
+}
+
+#[test]
+pub fn cache_dir_must_be_a_dir() {
+    let dir = tempdir::TempDir::new("coreml-lifecycle").unwrap();
+    let file = dir.path().join("model_cache");
+    std::fs::write(&file, b"").unwrap();
+    let opts = CoreMLModelOptions {
+        cache_dir: file,
+        ..Default::default()
+    };
+    let mut model = ModelLifecycle::<MockModel>::from_buf(b"ok model".to_vec(), opts);
+    model.load().unwrap();
+    assert!(model.unload_to_disk().is_err());
+    assert!(model.is_loaded());
+}
+
+#[test]
+pub fn uncompressed_buffers_are_mapped() {
+    let dir = tempdir::TempDir::new("coreml-lifecycle").unwrap();
+    for (compression, mapped) in [
+        (BufferCompression::None, true),
+        (BufferCompression::Lz4, false),
+    ] {
+        let opts = CoreMLModelOptions {
+            cache_dir: dir.path().to_path_buf(),
+            buffer_compression: compression,
+            ..Default::default()
+        };
+        let mut model = ModelLifecycle::<MockModel>::from_buf(b"ok model".to_vec(), opts);
+        model.load().unwrap();
+        model.unload_to_disk().unwrap();
+        model.load().unwrap();
+        let model = model.model().unwrap();
+        assert_eq!(model.is_mapped(), mapped);
+        assert_eq!(model.bytes(), Some(&b"ok model"[..]));
+    }
+}
+
+#[test]
+pub fn warm_up_on_load() {
+    let mut model = ModelLifecycle::<MockModel>::from_buf(b"ok model".to_vec(), Default::default());
+    model.load().unwrap();
+    assert_eq!(model.model().unwrap().warm_ups, 0);
+
+    let opts = CoreMLModelOptions {
+        warm_up: 3,
+        ..Default::default()
+    };
+    let mut model = ModelLifecycle::<MockModel>::from_buf(b"ok model".to_vec(), opts.clone());
+    model.load().unwrap();
+    assert_eq!(model.model().unwrap().warm_ups, 3);
+    model.unload();
+    model.load().unwrap();
+    assert_eq!(model.model().unwrap().warm_ups, 3);
+
+    let mut model = ModelLifecycle::<MockModel>::from_buf(b"ok cold".to_vec(), opts);
+    let Err(CoreMLError::FailedToLoad(err)) = model.load() else {
+        panic!("expected the warm up to fail the load");
+    };
+    assert!(err.contains("too cold"), "{err}");
+    assert!(matches!(model.state(), ModelState::Failed(_)));
+}
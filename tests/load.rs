@@ -1,13 +1,21 @@
 use std::{path::PathBuf, str::FromStr};
 
-use coreml_rs::{mlmodel::CoreMLError, CoreMLModelOptions, CoreMLModelWithState};
+use coreml_rs::{
+    lifecycle::ModelState,
+    mlbatchmodel::CoreMLBatchModelWithState,
+    mlmodel::{CoreMLError, CoreMLModelLoader},
+    synthetic::{Fill, InputGenerator},
+    CoreMLModelOptions, CoreMLModelWithState,
+};
 use sha2::{Digest, Sha256};
 
 #[test]
 pub fn load_empty() {
-    let m = CoreMLModelWithState::from_buf(vec![], CoreMLModelOptions::default());
+    let mut m = CoreMLModelWithState::from_buf(vec![], CoreMLModelOptions::default());
     let res = m.load();
-    assert!(matches!(res, Err(CoreMLError::FailedToLoadStatic(_, _))));
+    assert!(matches!(res, Err(CoreMLError::FailedToLoad(_))));
+    assert!(matches!(m.state(), ModelState::Failed(_)));
+    assert!(matches!(m.predict(), Err(CoreMLError::ModelNotLoaded)));
 }
 
 pub fn unzip_to_path_from_hash(buf: &[u8]) -> Option<PathBuf> {
@@ -45,29 +53,123 @@ pub fn reload_from_compiled_path() {
     let model_path = "./demo/model.zip";
     let buf = std::fs::read(model_path).unwrap();
     let path = unzip_to_path_from_hash(&buf).unwrap();
-    let m = CoreMLModelWithState::new(&path, CoreMLModelOptions::default());
-    let res = m.load();
-    assert!(!matches!(res, Err(CoreMLError::FailedToLoadStatic(_, _))));
-    let m = res.unwrap();
-    let res = m.unload();
-    assert!(!matches!(res, Err(CoreMLError::FailedToLoadStatic(_, _))));
+    let mut m = CoreMLModelWithState::new(&path, CoreMLModelOptions::default());
+    m.load().unwrap();
+    m.unload();
     _ = std::fs::remove_dir_all(path);
-    let m = res.unwrap();
-    let res = m.load();
-    assert!(!matches!(res, Err(CoreMLError::FailedToLoadStatic(_, _))));
+    m.load().unwrap();
 }
 
 #[test]
 pub fn reload_from_buf() {
     let model_path = "./demo/model_3.mlmodel";
     let buf = std::fs::read(model_path).unwrap();
-    let m = CoreMLModelWithState::from_buf(buf, CoreMLModelOptions::default());
-    let res = m.load();
-    assert!(!matches!(res, Err(CoreMLError::FailedToLoadStatic(_, _))));
-    let m = res.unwrap();
-    let res = m.unload();
-    assert!(!matches!(res, Err(CoreMLError::FailedToLoadStatic(_, _))));
-    let m = res.unwrap();
-    let res = m.load();
-    assert!(!matches!(res, Err(CoreMLError::FailedToLoadStatic(_, _))));
+    let mut m = CoreMLModelWithState::from_buf(buf, CoreMLModelOptions::default());
+    m.load().unwrap();
+    m.unload();
+    assert!(matches!(m.state(), ModelState::Unloaded));
+    m.load().unwrap();
+}
+
+#[test]
+pub fn warm_up() {
+    let model_path = "./demo/model_3.mlmodel";
+    let buf = std::fs::read(model_path).unwrap();
+    let mut m = CoreMLModelWithState::from_buf(buf.clone(), CoreMLModelOptions::default());
+    assert!(matches!(m.warm_up(1), Err(CoreMLError::ModelNotLoaded)));
+    m.load().unwrap();
+    m.warm_up(2).unwrap();
+
+    // inputs bound before are dropped, not mixed into the warm up
+    let specs = m.input_specs().unwrap();
+    for (name, input) in InputGenerator::new(Fill::Ones).generate(&specs).unwrap() {
+        m.add_input(name, input).unwrap();
+    }
+    m.warm_up(1).unwrap();
+
+    let opts = CoreMLModelOptions {
+        warm_up: 1,
+        warm_up_fill: Fill::Uniform {
+            low: -1.0,
+            high: 1.0,
+        },
+        ..Default::default()
+    };
+    let mut m = CoreMLModelWithState::from_buf(buf, opts);
+    m.load().unwrap();
+}
+
+#[test]
+pub fn batch_warm_up() {
+    let model_path = "./demo/model_3.mlmodel";
+    let buf = std::fs::read(model_path).unwrap();
+    let mut m = CoreMLBatchModelWithState::from_buf(buf.clone(), CoreMLModelOptions::default());
+    assert!(matches!(m.warm_up(1), Err(CoreMLError::ModelNotLoaded)));
+    m.load().unwrap();
+    m.warm_up(2).unwrap();
+    assert_eq!(m.batch_len(), 0);
+
+    // inputs bound before are dropped, not mixed into the warm up
+    let mut single = CoreMLModelWithState::from_buf(buf, CoreMLModelOptions::default());
+    single.load().unwrap();
+    let specs = single.input_specs().unwrap();
+    for idx in 0..2 {
+        for (name, input) in InputGenerator::new(Fill::Ones).generate(&specs).unwrap() {
+            m.add_input(name, input, idx).unwrap();
+        }
+    }
+    m.warm_up(1).unwrap();
+    assert_eq!(m.batch_len(), 0);
+}
+
+#[test]
+pub fn reload_from_disk() {
+    let model_path = "./demo/model_3.mlmodel";
+    let buf = std::fs::read(model_path).unwrap();
+    let cache_dir = tempdir::TempDir::new("coreml-cache").unwrap();
+    let opts = CoreMLModelOptions {
+        cache_dir: cache_dir.path().to_path_buf(),
+        ..Default::default()
+    };
+    let mut m = CoreMLModelWithState::from_buf(buf, opts);
+    m.load().unwrap();
+    m.unload_to_disk().unwrap();
+    let CoreMLModelLoader::BufferToDisk(path) = m.loader() else {
+        panic!("expected the buffer on disk");
+    };
+    assert!(path.starts_with(cache_dir.path().join("buffers")));
+    m.load().unwrap();
+}
+
+#[test]
+pub fn reload_from_compiled_cache() {
+    let model_path = "./demo/model.zip";
+    let buf = std::fs::read(model_path).unwrap();
+    let path = unzip_to_path_from_hash(&buf).unwrap();
+    let cache_dir = tempdir::TempDir::new("coreml-cache").unwrap();
+    let opts = CoreMLModelOptions {
+        cache_dir: cache_dir.path().to_path_buf(),
+        ..Default::default()
+    };
+    let mut m = CoreMLModelWithState::new_cached(&path, opts.clone());
+    m.load().unwrap();
+    m.unload();
+    // reuses the cached compiled model
+    m.load().unwrap();
+    m.unload();
+
+    // recompiles when the cache got purged
+    _ = std::fs::remove_dir_all(cache_dir.path().join("compiled"));
+    assert!(m.load().is_ok());
+    _ = std::fs::remove_dir_all(path);
+}
+
+#[test]
+pub fn reload_from_archive_buf() {
+    let model_path = "./demo/model.zip";
+    let buf = std::fs::read(model_path).unwrap();
+    let mut m = CoreMLModelWithState::from_archive_buf(buf, CoreMLModelOptions::default());
+    m.load().unwrap();
+    m.unload();
+    assert!(m.load().is_ok());
 }
@@ -0,0 +1,94 @@
+mod common;
+
+use common::MockModel;
+use coreml_rs::{diskbuffer::BufferCompression, lifecycle::ModelLifecycle, CoreMLModelOptions};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicIsize, Ordering},
+};
+
+/// Counts the bytes allocated and not freed yet.
+struct Counting;
+
+static LIVE: AtomicIsize = AtomicIsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        LIVE.fetch_add(layout.size() as isize, Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE.fetch_sub(layout.size() as isize, Ordering::SeqCst);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+fn live() -> isize {
+    LIVE.load(Ordering::SeqCst)
+}
+
+const MODEL_SIZE: usize = 4 << 20;
+const CYCLES: usize = 20;
+// slack for allocations the allocator keeps around, far below the model size
+const TOLERANCE: isize = 64 << 10;
+
+/// Unloads with `unload` and checks the heap gets back to where it was after every cycle.
+fn assert_released(model: &mut ModelLifecycle<MockModel>, to_disk: bool, mapped: bool) {
+    let unload = |model: &mut ModelLifecycle<MockModel>| {
+        if to_disk {
+            model.unload_to_disk().unwrap();
+        } else {
+            model.unload();
+        }
+    };
+    // the first cycle may move the buffer to the disk
+    model.load().unwrap();
+    unload(model);
+
+    let baseline = live();
+    for _ in 0..CYCLES {
+        model.load().unwrap();
+        assert_eq!(model.model().unwrap().is_mapped(), mapped);
+        if !mapped {
+            assert!(live() - baseline >= MODEL_SIZE as isize);
+        }
+        unload(model);
+        assert!(
+            live() - baseline <= TOLERANCE,
+            "{} bytes still allocated after unload",
+            live() - baseline
+        );
+    }
+}
+
+#[test]
+pub fn unload_releases_model_memory() {
+    let dir = tempdir::TempDir::new("coreml-memory").unwrap();
+    let mut buf = vec![0; MODEL_SIZE];
+    buf[..2].copy_from_slice(b"ok");
+
+    let mut model = ModelLifecycle::<MockModel>::from_buf(buf.clone(), Default::default());
+    assert_released(&mut model, false, false);
+    drop(model);
+
+    for (compression, mapped) in [
+        (BufferCompression::None, true),
+        (BufferCompression::Zstd(1), false),
+        (BufferCompression::Lz4, false),
+    ] {
+        let opts = CoreMLModelOptions {
+            cache_dir: dir.path().to_path_buf(),
+            buffer_compression: compression,
+            ..Default::default()
+        };
+        let before = live();
+        let mut model = ModelLifecycle::<MockModel>::from_buf(buf.clone(), opts);
+        assert_released(&mut model, true, mapped);
+        // the buffer moved to the disk, the model holds no copy of it anymore
+        assert!(live() - before <= TOLERANCE);
+    }
+}
@@ -0,0 +1,99 @@
+mod common;
+
+use common::f32_array;
+use coreml_rs::{
+    metrics::{self, Metrics},
+    mlarray::{self, MLArray},
+};
+use half::f16;
+use ndarray::{Array, IxDyn};
+
+fn close(a: f64, b: f64) -> bool {
+    (a - b).abs() < 1e-9
+}
+
+#[test]
+pub fn metrics() {
+    let lhs = f32_array(&[2, 2], &[1.0, 2.0, 3.0, 4.0]);
+    let rhs = f32_array(&[2, 2], &[1.0, 2.0, 3.0, 6.0]);
+    let m = Metrics::between(&lhs, &rhs).unwrap();
+    assert!(close(m.mae, 0.5));
+    assert!(close(m.mse, 1.0));
+    assert!(close(m.rmse, 1.0));
+    assert!(close(m.max_abs, 2.0));
+    assert!(close(m.relative, 2.0 / 50f64.sqrt()));
+    assert!(close(m.cosine, 38.0 / (30f64.sqrt() * 50f64.sqrt())));
+    assert!(close(metrics::mae(&lhs, &rhs).unwrap(), 0.5));
+    assert!(close(
+        metrics::psnr(&lhs, &rhs, 255.0).unwrap(),
+        20.0 * 255f64.log10()
+    ));
+
+    assert!(close(metrics::mse(&lhs, &lhs).unwrap(), 0.0));
+    assert_eq!(metrics::psnr(&lhs, &lhs, 1.0).unwrap(), f64::INFINITY);
+    assert!(close(metrics::cosine_similarity(&lhs, &lhs).unwrap(), 1.0));
+
+    let zeros = f32_array(&[2, 2], &[0.0; 4]);
+    assert_eq!(metrics::relative_error(&zeros, &zeros).unwrap(), 0.0);
+    assert_eq!(
+        metrics::relative_error(&lhs, &zeros).unwrap(),
+        f64::INFINITY
+    );
+    assert_eq!(metrics::cosine_similarity(&zeros, &zeros).unwrap(), 1.0);
+    assert_eq!(metrics::cosine_similarity(&lhs, &zeros).unwrap(), 0.0);
+
+    let nan = f32_array(&[2, 2], &[1.0, f32::NAN, 3.0, 4.0]);
+    assert!(metrics::max_abs_error(&nan, &lhs).unwrap().is_nan());
+}
+
+#[test]
+pub fn promotes_f16() {
+    let half: MLArray =
+        Array::from_shape_vec(IxDyn(&[3]), [0.5f32, 1.0, 1.5].map(f16::from_f32).to_vec())
+            .unwrap()
+            .into();
+    let ints: MLArray = Array::from_shape_vec(IxDyn(&[3]), vec![0i32, 1, 2])
+        .unwrap()
+        .into();
+    let floats = f32_array(&[3], &[0.5, 1.0, 1.5]);
+    assert_eq!(metrics::max_abs_error(&half, &floats).unwrap(), 0.0);
+    assert!(close(metrics::mae(&half, &ints).unwrap(), 1.0 / 3.0));
+}
+
+#[test]
+pub fn allclose() {
+    let rhs = f32_array(&[3], &[1.0, 100.0, 0.0]);
+    let lhs = f32_array(&[3], &[1.001, 100.5, 1e-6]);
+    assert!(metrics::allclose(&lhs, &rhs, 1e-2, 1e-5).unwrap());
+    assert!(!metrics::allclose(&lhs, &rhs, 1e-3, 1e-5).unwrap());
+    assert!(!metrics::allclose(&lhs, &rhs, 1e-2, 1e-7).unwrap());
+    let nan = f32_array(&[3], &[1.0, 100.0, f32::NAN]);
+    assert!(!metrics::allclose(&nan, &nan, 1.0, 1.0).unwrap());
+
+    let empty = f32_array(&[0, 3], &[]);
+    assert!(metrics::allclose(&empty, &empty, 0.0, 0.0).unwrap());
+}
+
+#[test]
+pub fn rejects_mismatched_arrays() {
+    let a = f32_array(&[2, 2], &[1.0; 4]);
+    let b = f32_array(&[4], &[1.0; 4]);
+    assert!(Metrics::between(&a, &b).is_err());
+    assert!(metrics::allclose(&a, &b, 0.0, 0.0).is_err());
+
+    let empty = f32_array(&[0], &[]);
+    assert!(metrics::mae(&empty, &empty).is_err());
+    assert!(metrics::psnr(&empty, &empty, 1.0).is_err());
+}
+
+#[test]
+pub fn mean_absolute_error_of_slices() {
+    assert_eq!(mlarray::mean_absolute_error([1u8, 5], [3u8, 4]), 1.5);
+    assert_eq!(mlarray::mean_absolute_error::<f32>([], []), 0.0);
+}
+
+#[test]
+#[should_panic(expected = "different lengths")]
+pub fn mean_absolute_error_needs_equal_lengths() {
+    mlarray::mean_absolute_error([1.0f32, 2.0], [1.0f32]);
+}
@@ -0,0 +1,154 @@
+use std::path::{Path, PathBuf};
+
+use coreml_rs::{
+    mlmodel::CoreMLError,
+    mlmodelc::{CompiledModelKind, MLModelC, Problem},
+};
+
+const METADATA: &str = r#"[{
+    "specificationVersion": 7,
+    "shortDescription": "test model",
+    "modelType": {"name": "MLModelType_mlProgram"},
+    "userDefinedMetadata": {"com.github.apple.coremltools.version": "8.0"},
+    "inputSchema": [{"name": "x", "type": "MultiArray", "dataType": "Float32",
+        "formattedType": "MultiArray (Float32 1 × 3)", "shape": "[1, 3]", "isOptional": "0",
+        "hasShapeFlexibility": "0"}],
+    "outputSchema": [{"name": "y", "type": "MultiArray", "dataType": "Float16", "shape": "[1, 3]"},
+        {"name": "label", "type": "String", "isOptional": "1"}]
+}]"#;
+
+const MIL: &str = r#"program(1.0)
+{
+    func main<ios16>(tensor<fp32, [1, 3]> x) {
+        tensor<fp32, [3]> w = const()[name = string("w"), val = tensor<fp32, [3]>(BLOBFILE(path = string("@model_path/weights/weight.bin"), offset = uint64(64)))];
+        tensor<fp32, [1, 3]> y = mul(x = x, y = w)[name = string("y")];
+    } -> (y);
+}"#;
+
+fn weight_file() -> Vec<u8> {
+    let mut buf = vec![0u8; 64];
+    buf[0] = 1;
+    buf.extend(0xDEADBEEFu32.to_le_bytes());
+    buf.extend(2u32.to_le_bytes());
+    buf.extend(12u64.to_le_bytes());
+    buf.extend(128u64.to_le_bytes());
+    buf.resize(128, 0);
+    buf.extend([0u8; 12]);
+    buf.resize(192, 0);
+    buf
+}
+
+fn write(dir: &Path, rel: &str, contents: impl AsRef<[u8]>) {
+    let path = dir.join(rel);
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(path, contents).unwrap();
+}
+
+fn program(dir: &Path) -> PathBuf {
+    let path = dir.join("model.mlmodelc");
+    write(&path, "coremldata.bin", [1, 2, 3]);
+    write(&path, "metadata.json", METADATA);
+    write(&path, "model.mil", MIL);
+    write(&path, "weights/weight.bin", weight_file());
+    path
+}
+
+#[test]
+pub fn valid_program() {
+    let dir = tempdir::TempDir::new("coreml-mlmodelc").unwrap();
+    let model = MLModelC::open(program(dir.path())).unwrap();
+    assert_eq!(model.kind(), CompiledModelKind::MLProgram);
+    assert_eq!(model.problems(), vec![]);
+    model.validate().unwrap();
+
+    let metadata = model.metadata();
+    assert_eq!(metadata.model_type(), Some("MLModelType_mlProgram"));
+    assert_eq!(metadata.specification_version, Some(7));
+    assert_eq!(metadata.short_description.as_deref(), Some("test model"));
+    assert_eq!(model.inputs()[0].name, "x");
+    assert_eq!(model.inputs()[0].shape(), Some(vec![1, 3]));
+    assert!(!model.inputs()[0].is_optional());
+    assert_eq!(model.outputs()[0].data_type, "Float16");
+    assert_eq!(model.outputs()[1].shape(), None);
+    assert!(model.outputs()[1].is_optional());
+}
+
+#[test]
+pub fn missing_and_corrupt_files() {
+    let dir = tempdir::TempDir::new("coreml-mlmodelc").unwrap();
+    let path = program(dir.path());
+    std::fs::remove_file(path.join("coremldata.bin")).unwrap();
+    // weights from another compile of the model
+    let mut weights = weight_file();
+    weights.truncate(130);
+    write(&path, "weights/weight.bin", weights);
+
+    let model = MLModelC::open(&path).unwrap();
+    let problems = model.problems();
+    assert_eq!(problems.len(), 2, "{problems:?}");
+    assert_eq!(problems[0], Problem::Missing("coremldata.bin".into()));
+    assert!(matches!(&problems[1], Problem::Corrupt(p, _) if p == Path::new("weights/weight.bin")));
+    let Err(CoreMLError::BadCompiledModel(err)) = model.validate() else {
+        panic!("expected the model to be invalid");
+    };
+    assert!(err.contains("coremldata.bin is missing"), "{err}");
+
+    std::fs::remove_dir_all(path.join("weights")).unwrap();
+    write(&path, "coremldata.bin", [1]);
+    let problems = MLModelC::open(&path).unwrap().problems();
+    assert_eq!(
+        problems,
+        vec![Problem::Missing("weights/weight.bin".into())]
+    );
+
+    // stale weights without the blob model.mil refers to
+    write(&path, "model.mil", MIL.replace("uint64(64)", "uint64(256)"));
+    write(&path, "weights/weight.bin", weight_file());
+    let problems = MLModelC::open(&path).unwrap().problems();
+    assert!(
+        matches!(&problems[..], [Problem::Corrupt(_, reason)] if reason.contains("offset 256")),
+        "{problems:?}"
+    );
+
+    // weight paths can't leave the model
+    for file in ["../weight.bin", "/etc/weight.bin"] {
+        write(&path, "model.mil", MIL.replace("weights/weight.bin", file));
+        let problems = MLModelC::open(&path).unwrap().problems();
+        assert!(
+            matches!(&problems[..], [Problem::Corrupt(p, reason)]
+                if p == Path::new("model.mil") && reason.contains("outside of the model")),
+            "{problems:?}"
+        );
+    }
+
+    std::fs::remove_file(path.join("metadata.json")).unwrap();
+    assert!(matches!(
+        MLModelC::open(&path),
+        Err(CoreMLError::BadCompiledModel(_))
+    ));
+}
+
+#[test]
+pub fn neural_network_pipeline() {
+    let dir = tempdir::TempDir::new("coreml-mlmodelc").unwrap();
+    let path = dir.path().join("pipeline.mlmodelc");
+    write(&path, "coremldata.bin", [1]);
+    write(&path, "metadata.json", "[{}]");
+    write(&path, "model0/coremldata.bin", [1]);
+    write(&path, "model0/model.espresso.net", "{\"layers\": []}");
+    write(&path, "model0/model.espresso.shape", "{\"layer_shapes\": ");
+    write(&path, "model1/coremldata.bin", [1]);
+
+    let model = MLModelC::open(&path).unwrap();
+    assert_eq!(model.kind(), CompiledModelKind::Pipeline);
+    assert!(model.inputs().is_empty());
+    let problems = model.problems();
+    assert_eq!(problems.len(), 2, "{problems:?}");
+    assert!(
+        matches!(&problems[0], Problem::Corrupt(p, _) if p == Path::new("model0/model.espresso.shape"))
+    );
+    assert_eq!(
+        problems[1],
+        Problem::Missing("model0/model.espresso.weights".into())
+    );
+}
@@ -0,0 +1,22 @@
+use coreml_rs::mlpackage::{MLPackage, MLPackageBuilder};
+
+#[test]
+pub fn write_and_read_package() {
+    let dir = tempdir::TempDir::new("coreml-mlpackage").unwrap();
+    let path = dir.path().join("model.mlpackage");
+    MLPackageBuilder::new(b"spec".to_vec())
+        .weight("weight.bin", vec![0; 64])
+        .write(&path)
+        .unwrap();
+
+    let package = MLPackage::open(&path).unwrap();
+    package.validate().unwrap();
+    assert_eq!(package.read_spec().unwrap(), b"spec");
+    assert_eq!(package.manifest().root_item().unwrap().name, "model.mlmodel");
+    let weights = package.weight_files().unwrap();
+    assert_eq!(weights.len(), 1);
+    assert_eq!(weights[0].1, 64);
+
+    std::fs::remove_file(package.spec_path().unwrap()).unwrap();
+    assert!(package.validate().is_err());
+}
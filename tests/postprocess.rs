@@ -0,0 +1,204 @@
+use coreml_rs::{
+    mlarray::MLArray,
+    postprocess::{self, BoundingBox, BoxFormat, Detection},
+};
+use ndarray::{Array, ArrayD, Dimension, IxDyn};
+
+/// Small xorshift generator so the property tests are reproducible without extra deps.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn f32(&mut self, lo: f32, hi: f32) -> f32 {
+        lo + (self.next() % 1_000_000) as f32 / 1_000_000.0 * (hi - lo)
+    }
+
+    fn array(&mut self, shape: &[usize]) -> ArrayD<f32> {
+        let len = shape.iter().product();
+        // coarse values so ties are common
+        let data = (0..len).map(|_| self.below(8) as f32 - 4.0).collect();
+        Array::from_shape_vec(IxDyn(shape), data).unwrap()
+    }
+
+    fn shape(&mut self) -> Vec<usize> {
+        (0..1 + self.below(4)).map(|_| 1 + self.below(5)).collect()
+    }
+}
+
+fn expect_f32(array: MLArray) -> ArrayD<f32> {
+    let MLArray::Float32Array(array) = array else {
+        panic!("expected an f32 array");
+    };
+    array
+}
+
+#[test]
+pub fn argmax_matches_naive() {
+    let mut rng = Rng(0x9E3779B97F4A7C15);
+    for _ in 0..200 {
+        let shape = rng.shape();
+        let axis = rng.below(shape.len());
+        let input = rng.array(&shape);
+
+        let MLArray::UInt32Array(out) = postprocess::argmax(&input.clone().into(), axis).unwrap()
+        else {
+            panic!("expected a u32 array");
+        };
+        let mut out_shape = shape.clone();
+        out_shape.remove(axis);
+        assert_eq!(out.shape(), &out_shape[..]);
+
+        for (idx, value) in out.indexed_iter() {
+            let mut full: Vec<usize> = idx.slice().to_vec();
+            full.insert(axis, 0);
+            let mut best = 0;
+            for i in 0..shape[axis] {
+                full[axis] = i;
+                let v = input[IxDyn(&full)];
+                full[axis] = best;
+                if v > input[IxDyn(&full)] {
+                    best = i;
+                }
+            }
+            assert_eq!(*value as usize, best);
+        }
+    }
+}
+
+#[test]
+pub fn softmax_matches_naive() {
+    let mut rng = Rng(0xDEADBEEF);
+    for _ in 0..200 {
+        let shape = rng.shape();
+        let axis = rng.below(shape.len());
+        let input = rng.array(&shape);
+        let out = expect_f32(postprocess::softmax(&input.clone().into(), axis).unwrap());
+        assert_eq!(out.shape(), input.shape());
+
+        for (idx, value) in out.indexed_iter() {
+            let mut full: Vec<usize> = idx.slice().to_vec();
+            let sum: f32 = (0..shape[axis])
+                .map(|i| {
+                    full[axis] = i;
+                    input[IxDyn(&full)].exp()
+                })
+                .sum();
+            let expected = input[idx.clone()].exp() / sum;
+            assert!((value - expected).abs() < 1e-5, "{value} != {expected}");
+        }
+    }
+}
+
+#[test]
+pub fn sigmoid_and_threshold_match_naive() {
+    let mut rng = Rng(42);
+    for _ in 0..50 {
+        let shape = rng.shape();
+        let input = rng.array(&shape);
+        let out = expect_f32(postprocess::sigmoid(&input.clone().into()));
+        let MLArray::UInt8Array(mask) = postprocess::threshold(&out.clone().into(), 0.5) else {
+            panic!("expected a u8 array");
+        };
+        for ((x, s), m) in input.iter().zip(out.iter()).zip(mask.iter()) {
+            assert!((s - 1.0 / (1.0 + (-x).exp())).abs() < 1e-6);
+            assert_eq!(*m, (*x > 0.0) as u8);
+        }
+    }
+}
+
+#[test]
+pub fn decode_boxes() {
+    let boxes = Array::from_shape_vec(vec![1, 2, 4], vec![10., 10., 4., 2., 0., 0., 1., 1.])
+        .unwrap()
+        .into();
+    let scores = Array::from_shape_vec(vec![1, 2, 3], vec![0.1, 0.7, 0.2, 0.3, 0.1, 0.1])
+        .unwrap()
+        .into();
+    let detections = postprocess::decode_boxes(&boxes, &scores, BoxFormat::Cxcywh, 0.5).unwrap();
+    assert_eq!(
+        detections,
+        vec![Detection {
+            bbox: BoundingBox {
+                x1: 8.,
+                y1: 9.,
+                x2: 12.,
+                y2: 11.
+            },
+            score: 0.7,
+            class: 1
+        }]
+    );
+
+    let scores = Array::<f32, _>::zeros(vec![3, 3]).into();
+    assert!(postprocess::decode_boxes(&boxes, &scores, BoxFormat::Xyxy, 0.5).is_err());
+}
+
+fn naive_nms(detections: &[Detection], iou_threshold: f32, class_agnostic: bool) -> Vec<Detection> {
+    // a box is kept unless a kept box with a higher score (or the same score and lower index)
+    // overlaps it, evaluated in score order
+    let mut order: Vec<usize> = (0..detections.len()).collect();
+    order.sort_by(|a, b| detections[*b].score.total_cmp(&detections[*a].score));
+    let mut kept = vec![false; detections.len()];
+    for (n, &i) in order.iter().enumerate() {
+        kept[i] = order[..n].iter().all(|&j| {
+            !kept[j]
+                || (!class_agnostic && detections[j].class != detections[i].class)
+                || detections[j].bbox.iou(&detections[i].bbox) <= iou_threshold
+        });
+    }
+    order
+        .into_iter()
+        .filter(|i| kept[*i])
+        .map(|i| detections[i])
+        .collect()
+}
+
+#[test]
+pub fn nms_matches_naive() {
+    let mut rng = Rng(7);
+    for _ in 0..200 {
+        let detections: Vec<Detection> = (0..rng.below(30))
+            .map(|_| {
+                let (x, y) = (rng.f32(0., 50.), rng.f32(0., 50.));
+                let (w, h) = (rng.f32(1., 20.), rng.f32(1., 20.));
+                Detection {
+                    bbox: BoundingBox::decode([x, y, w, h], BoxFormat::Xywh),
+                    score: rng.below(10) as f32 / 10.0,
+                    class: rng.below(3),
+                }
+            })
+            .collect();
+        let iou = rng.f32(0.1, 0.9);
+
+        let kept = postprocess::nms(detections.clone(), iou);
+        assert_eq!(kept, naive_nms(&detections, iou, false));
+        for (i, a) in kept.iter().enumerate() {
+            for b in &kept[i + 1..] {
+                assert!(a.class != b.class || a.bbox.iou(&b.bbox) <= iou);
+            }
+        }
+
+        let kept = postprocess::nms_class_agnostic(detections.clone(), iou);
+        assert_eq!(kept, naive_nms(&detections, iou, true));
+    }
+}
+
+#[test]
+pub fn iou() {
+    let a = BoundingBox::decode([0., 0., 2., 2.], BoxFormat::Xywh);
+    let b = BoundingBox::decode([1., 1., 2., 2.], BoxFormat::Xywh);
+    assert!((a.iou(&b) - 1.0 / 7.0).abs() < 1e-6);
+    assert_eq!(a.iou(&a), 1.0);
+    let empty = BoundingBox::decode([0., 0., 0., 0.], BoxFormat::Xywh);
+    assert_eq!(empty.iou(&empty), 0.0);
+}
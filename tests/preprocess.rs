@@ -0,0 +1,66 @@
+use coreml_rs::{
+    mlarray::MLArray,
+    preprocess::{ChannelOrder, ImageBuffer, ImagePreprocessor, Layout},
+};
+
+#[test]
+pub fn layout_and_channel_order() {
+    // 2x1 image, a red and a blue pixel
+    let pixels = [255u8, 0, 0, 0, 0, 255];
+    let image = ImageBuffer::new(&pixels, 2, 1, 3);
+
+    let (input, _) = ImagePreprocessor::new(2, 1)
+        .scale_bias(1.0 / 255.0, [0.0; 3])
+        .process(&image)
+        .unwrap();
+    let MLArray::Float32Array(input) = input else {
+        panic!("expected an f32 input");
+    };
+    assert_eq!(input.shape(), &[1, 3, 1, 2]);
+    assert_eq!(input.as_slice().unwrap(), &[1.0, 0.0, 0.0, 0.0, 0.0, 1.0]);
+
+    let (input, _) = ImagePreprocessor::new(2, 1)
+        .layout(Layout::Nhwc)
+        .channel_order(ChannelOrder::Bgr)
+        .process(&image)
+        .unwrap();
+    let MLArray::Float32Array(input) = input else {
+        panic!("expected an f32 input");
+    };
+    assert_eq!(input.shape(), &[1, 1, 2, 3]);
+    assert_eq!(input.as_slice().unwrap(), &[0.0, 0.0, 255.0, 255.0, 0.0, 0.0]);
+
+    // size mismatch without a resize mode
+    assert!(ImagePreprocessor::new(3, 3).process(&image).is_err());
+}
+
+#[test]
+pub fn letterbox() {
+    let pixels = [255u8, 0, 0, 0, 0, 255];
+    let image = ImageBuffer::new(&pixels, 2, 1, 3);
+
+    let (input, letterbox) = ImagePreprocessor::new(4, 4)
+        .letterbox(7)
+        .process(&image)
+        .unwrap();
+    assert_eq!(letterbox.pad_y, 1.0);
+    assert_eq!(letterbox.scale_x, 2.0);
+    assert_eq!(letterbox.to_source(2.0, 1.0), (1.0, 0.0));
+    let MLArray::Float32Array(input) = input else {
+        panic!("expected an f32 input");
+    };
+    assert_eq!(input[[0, 0, 0, 0]], 7.0);
+    assert_eq!(input[[0, 0, 1, 0]], 255.0);
+    assert_eq!(input[[0, 2, 2, 3]], 255.0);
+}
+
+#[test]
+pub fn rejects_empty_targets() {
+    let pixels = [0u8; 12];
+    let image = ImageBuffer::new(&pixels, 2, 2, 3);
+    for (width, height) in [(0, 4), (4, 0)] {
+        let preprocessor = ImagePreprocessor::new(width, height);
+        assert!(preprocessor.clone().letterbox(0).process(&image).is_err());
+        assert!(preprocessor.stretch().process(&image).is_err());
+    }
+}
@@ -0,0 +1,192 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use coreml_rs::{
+    mlarray::MLArray,
+    mlmodel::CoreMLError,
+    scheduler::{BatchPredictor, BatchScheduler, BatchSchedulerOptions, Features},
+};
+use ndarray::Array;
+
+/// Doubles the "x" input and records the size of every batch it ran.
+struct Doubler {
+    batches: Arc<Mutex<Vec<usize>>>,
+}
+
+impl BatchPredictor for Doubler {
+    fn run_batch(&mut self, inputs: Vec<Features>) -> Result<Vec<Features>, CoreMLError> {
+        self.batches.lock().unwrap().push(inputs.len());
+        inputs
+            .into_iter()
+            .map(|mut features| {
+                let Some(MLArray::Float32Array(x)) = features.remove("x") else {
+                    return Err(CoreMLError::BadInputShape("missing x".to_string()));
+                };
+                Ok(HashMap::from([("y".to_string(), (x * 2.0).into())]))
+            })
+            .collect()
+    }
+}
+
+fn input(value: f32) -> Features {
+    HashMap::from([("x".to_string(), Array::from_elem(vec![1], value).into())])
+}
+
+fn value(output: &Features) -> f32 {
+    let Some(MLArray::Float32Array(y)) = output.get("y") else {
+        panic!("missing y");
+    };
+    y[[0]]
+}
+
+#[test]
+pub fn routes_outputs_to_callers() {
+    let batches = Arc::new(Mutex::new(vec![]));
+    let scheduler = BatchScheduler::new(
+        Doubler {
+            batches: batches.clone(),
+        },
+        BatchSchedulerOptions {
+            max_batch_size: 4,
+            max_latency: Duration::from_millis(50),
+        },
+    );
+
+    std::thread::scope(|s| {
+        for t in 0..4 {
+            let scheduler = &scheduler;
+            s.spawn(move || {
+                for i in 0..10 {
+                    let v = (t * 100 + i) as f32;
+                    let output = scheduler.submit(input(v)).wait().unwrap();
+                    assert_eq!(value(&output), v * 2.0);
+                }
+            });
+        }
+    });
+    drop(scheduler);
+
+    let batches = batches.lock().unwrap();
+    assert_eq!(batches.iter().sum::<usize>(), 40);
+    assert!(batches.iter().all(|n| *n >= 1 && *n <= 4));
+    // concurrent callers share batches
+    assert!(batches.len() < 40);
+}
+
+#[test]
+pub fn fills_batches_before_the_deadline() {
+    let batches = Arc::new(Mutex::new(vec![]));
+    let scheduler = BatchScheduler::new(
+        Doubler {
+            batches: batches.clone(),
+        },
+        BatchSchedulerOptions {
+            max_batch_size: 3,
+            max_latency: Duration::from_secs(10),
+        },
+    );
+    let pending: Vec<_> = (0..6).map(|i| scheduler.submit(input(i as f32))).collect();
+    for (i, prediction) in pending.into_iter().enumerate() {
+        assert_eq!(value(&prediction.wait().unwrap()), i as f32 * 2.0);
+    }
+    assert_eq!(*batches.lock().unwrap(), vec![3, 3]);
+}
+
+#[test]
+pub fn errors_reach_every_caller() {
+    let scheduler = BatchScheduler::new(
+        Doubler {
+            batches: Default::default(),
+        },
+        BatchSchedulerOptions {
+            max_batch_size: 2,
+            max_latency: Duration::from_secs(10),
+        },
+    );
+    let a = scheduler.submit(HashMap::new());
+    let b = scheduler.submit(input(1.0));
+    assert!(a.wait().is_err());
+    assert!(b.wait().is_err());
+}
+
+/// Doubles like [`Doubler`], but rejects requests without "x" before batching them.
+struct Checked(Doubler);
+
+impl BatchPredictor for Checked {
+    fn run_batch(&mut self, inputs: Vec<Features>) -> Result<Vec<Features>, CoreMLError> {
+        self.0.run_batch(inputs)
+    }
+
+    fn check(&self, inputs: &Features) -> Result<(), CoreMLError> {
+        if !inputs.contains_key("x") {
+            return Err(CoreMLError::BadInputShape("missing x".to_string()));
+        }
+        Ok(())
+    }
+}
+
+#[test]
+pub fn rejected_requests_fail_alone() {
+    let batches = Arc::new(Mutex::new(vec![]));
+    let scheduler = BatchScheduler::new(
+        Checked(Doubler {
+            batches: batches.clone(),
+        }),
+        BatchSchedulerOptions {
+            max_batch_size: 2,
+            max_latency: Duration::from_secs(10),
+        },
+    );
+    let a = scheduler.submit(HashMap::new());
+    let b = scheduler.submit(input(1.0));
+    let err = a.wait().unwrap_err().to_string();
+    assert!(err.contains("missing x"), "{err}");
+    assert_eq!(value(&b.wait().unwrap()), 2.0);
+    assert_eq!(*batches.lock().unwrap(), vec![1]);
+}
+
+/// Panics on batches containing a negative "x", doubles the rest.
+struct Panicky(Doubler);
+
+impl BatchPredictor for Panicky {
+    fn run_batch(&mut self, inputs: Vec<Features>) -> Result<Vec<Features>, CoreMLError> {
+        if inputs.iter().any(|features| value_of(features, "x") < 0.0) {
+            panic!("negative input");
+        }
+        self.0.run_batch(inputs)
+    }
+}
+
+fn value_of(features: &Features, name: &str) -> f32 {
+    let Some(MLArray::Float32Array(x)) = features.get(name) else {
+        panic!("missing {name}");
+    };
+    x[[0]]
+}
+
+#[test]
+pub fn panics_fail_their_batch() {
+    let scheduler = BatchScheduler::new(
+        Panicky(Doubler {
+            batches: Default::default(),
+        }),
+        BatchSchedulerOptions {
+            max_batch_size: 2,
+            max_latency: Duration::from_secs(10),
+        },
+    );
+    let a = scheduler.submit(input(1.0));
+    let b = scheduler.submit(input(-1.0));
+    let err = a.wait().unwrap_err().to_string();
+    assert!(err.contains("negative input"), "{err}");
+    assert!(b.wait().is_err());
+
+    // the worker keeps serving later batches
+    let c = scheduler.submit(input(2.0));
+    let d = scheduler.submit(input(3.0));
+    assert_eq!(value(&c.wait().unwrap()), 4.0);
+    assert_eq!(value(&d.wait().unwrap()), 6.0);
+}
@@ -0,0 +1,63 @@
+mod common;
+
+use common::{bytes_field, packed};
+use coreml_rs::{classifier::Label, spec::ModelSpec};
+
+#[test]
+pub fn parse_metadata() {
+    let entry = [bytes_field(1, b"classes"), bytes_field(2, b"cat,dog")].concat();
+    let metadata = [
+        bytes_field(1, b"segmentation"),
+        bytes_field(2, b"1.2.0"),
+        bytes_field(3, b"aftershoot"),
+        bytes_field(100, &entry),
+    ]
+    .concat();
+    let input = bytes_field(1, b"image");
+    let description = [
+        bytes_field(1, &input),
+        bytes_field(10, &bytes_field(1, b"mask")),
+        bytes_field(100, &metadata),
+    ]
+    .concat();
+    let spec = [vec![1 << 3, 8], bytes_field(2, &description)].concat();
+
+    let spec = ModelSpec::from_bytes(&spec).unwrap();
+    assert_eq!(spec.specification_version, 8);
+    assert_eq!(spec.description.inputs[0].name, "image");
+    assert_eq!(spec.description.outputs[0].name, "mask");
+    let metadata = spec.metadata();
+    assert_eq!(metadata.version.as_deref(), Some("1.2.0"));
+    assert_eq!(metadata.author.as_deref(), Some("aftershoot"));
+    assert_eq!(metadata.license, None);
+    assert_eq!(metadata.user_defined["classes"], "cat,dog");
+
+    assert!(ModelSpec::from_bytes(&[2 << 3 | 2, 10]).is_err());
+}
+
+#[test]
+pub fn parse_class_labels() {
+    let int_labels = |vector: &[u8]| {
+        let classifier = bytes_field(101, &bytes_field(1, vector));
+        ModelSpec::from_bytes(&bytes_field(403, &classifier))
+            .unwrap()
+            .class_labels
+    };
+    let expected = Some(vec![Label::Int64(3), Label::Int64(300), Label::Int64(-1)]);
+    assert_eq!(int_labels(&packed(&[3, 300, u64::MAX])), expected);
+
+    // the same labels unpacked, one varint field each
+    let classifier = bytes_field(101, &[vec![1 << 3, 3], vec![1 << 3, 0xac, 0x02]].concat());
+    let spec = ModelSpec::from_bytes(&bytes_field(400, &classifier)).unwrap();
+    assert_eq!(
+        spec.class_labels,
+        Some(vec![Label::Int64(3), Label::Int64(300)])
+    );
+
+    // string labels of the last model of a pipeline classifier
+    let strings = [bytes_field(1, b"cat"), bytes_field(1, b"dog")].concat();
+    let model = bytes_field(404, &bytes_field(100, &strings));
+    let pipeline = bytes_field(1, &[bytes_field(1, &[]), bytes_field(1, &model)].concat());
+    let spec = ModelSpec::from_bytes(&bytes_field(200, &pipeline)).unwrap();
+    assert_eq!(spec.class_labels, Some(vec!["cat".into(), "dog".into()]));
+}
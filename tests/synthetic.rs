@@ -0,0 +1,193 @@
+mod common;
+
+use common::{bytes_field, packed, varint_field};
+use coreml_rs::{
+    mlarray::MLArray,
+    spec::{ArrayDataType, ModelSpec, ShapeFlexibility},
+    synthetic::{Fill, InputGenerator, InputSpec, ShapeChoice},
+};
+
+fn input(name: &str, array: &[u8], optional: bool) -> Vec<u8> {
+    let mut feature_type = bytes_field(5, array);
+    if optional {
+        feature_type.extend(varint_field(1000, 1));
+    }
+    [
+        bytes_field(1, name.as_bytes()),
+        bytes_field(3, &feature_type),
+    ]
+    .concat()
+}
+
+/// `image` f32 [1, 3, 8, 8] or [1, 3, 16, 16], `tokens` i32 [1, 4..=32] defaulting to 16,
+/// optional `mask` f16 [1, 2]
+fn spec() -> ModelSpec {
+    let image = [
+        bytes_field(1, &packed(&[1, 3, 8, 8])),
+        varint_field(2, 0x10020),
+        bytes_field(
+            21,
+            &[
+                bytes_field(1, &bytes_field(1, &packed(&[1, 3, 16, 16]))),
+                bytes_field(1, &bytes_field(1, &packed(&[1, 3, 8, 8]))),
+            ]
+            .concat(),
+        ),
+    ]
+    .concat();
+    let tokens = [
+        varint_field(1, 1),
+        varint_field(1, 16),
+        varint_field(2, 0x20020),
+        bytes_field(
+            31,
+            &[
+                bytes_field(1, &[varint_field(1, 1), varint_field(2, 1)].concat()),
+                bytes_field(1, &[varint_field(1, 4), varint_field(2, 32)].concat()),
+            ]
+            .concat(),
+        ),
+    ]
+    .concat();
+    let mask = [bytes_field(1, &packed(&[1, 2])), varint_field(2, 0x10010)].concat();
+    let description = [
+        bytes_field(1, &input("image", &image, false)),
+        bytes_field(1, &input("tokens", &tokens, false)),
+        bytes_field(1, &input("mask", &mask, true)),
+    ]
+    .concat();
+    ModelSpec::from_bytes(&bytes_field(2, &description)).unwrap()
+}
+
+fn shapes(inputs: &[(String, MLArray)]) -> Vec<(&str, Vec<usize>)> {
+    inputs
+        .iter()
+        .map(|(name, input)| (name.as_str(), input.shape().to_vec()))
+        .collect()
+}
+
+#[test]
+pub fn parses_array_features() {
+    let specs = InputSpec::from_spec(&spec()).unwrap();
+    assert_eq!(specs[0].data_type, ArrayDataType::Float32);
+    assert_eq!(specs[0].shape, [1, 3, 8, 8]);
+    assert_eq!(
+        specs[0].flexibility,
+        ShapeFlexibility::Enumerated(vec![vec![1, 3, 16, 16], vec![1, 3, 8, 8]])
+    );
+    assert_eq!(specs[1].data_type, ArrayDataType::Int32);
+    assert_eq!(
+        specs[1].flexibility,
+        ShapeFlexibility::Range(vec![(1, Some(1)), (4, Some(32))])
+    );
+    assert!(!specs[1].is_optional);
+    assert_eq!(specs[2].data_type, ArrayDataType::Float16);
+    assert!(specs[2].is_optional);
+}
+
+#[test]
+pub fn chooses_accepted_shapes() {
+    let spec = spec();
+    let generate = |choice| {
+        InputGenerator::new(Fill::Zeros)
+            .shape(choice)
+            .generate_for_spec(&spec)
+            .unwrap()
+    };
+    assert_eq!(
+        shapes(&generate(ShapeChoice::Default)),
+        [("image", vec![1, 3, 8, 8]), ("tokens", vec![1, 16])]
+    );
+    assert_eq!(
+        shapes(&generate(ShapeChoice::Smallest)),
+        [("image", vec![1, 3, 8, 8]), ("tokens", vec![1, 4])]
+    );
+    assert_eq!(
+        shapes(&generate(ShapeChoice::Largest)),
+        [("image", vec![1, 3, 16, 16]), ("tokens", vec![1, 32])]
+    );
+
+    let inputs = InputGenerator::new(Fill::Zeros)
+        .optional(true)
+        .generate_for_spec(&spec)
+        .unwrap();
+    assert!(matches!(&inputs[2], (name, MLArray::Float16Array(_)) if name == "mask"));
+}
+
+#[test]
+pub fn fills() {
+    let spec = spec();
+    let image = |generator: InputGenerator| {
+        let mut inputs = generator.generate_for_spec(&spec).unwrap();
+        inputs.swap_remove(0).1.extract_to_tensor::<f32>()
+    };
+    assert!(image(InputGenerator::new(Fill::Zeros))
+        .iter()
+        .all(|v| *v == 0.0));
+    assert!(image(InputGenerator::new(Fill::Ones))
+        .iter()
+        .all(|v| *v == 1.0));
+
+    let uniform = image(
+        InputGenerator::new(Fill::Uniform {
+            low: -2.0,
+            high: 2.0,
+        })
+        .seed(1),
+    );
+    assert!(uniform.iter().all(|v| (-2.0..2.0).contains(v)));
+    assert!(uniform.iter().any(|v| *v < -1.0) && uniform.iter().any(|v| *v > 1.0));
+
+    let normal = InputGenerator::new(Fill::Normal {
+        mean: 5.0,
+        std: 0.5,
+    })
+    .shape(ShapeChoice::Largest)
+    .seed(7);
+    let values = image(normal.clone());
+    let mean = values.mean().unwrap();
+    assert!((mean - 5.0).abs() < 0.1, "{mean}");
+    // seeded fills are reproducible
+    assert_eq!(values, image(normal.clone()));
+    assert_ne!(values, image(normal.seed(8)));
+}
+
+#[test]
+pub fn rejects_unsupported_inputs() {
+    let image = [
+        bytes_field(1, b"photo"),
+        bytes_field(3, &bytes_field(4, &[])),
+    ]
+    .concat();
+    let spec = ModelSpec::from_bytes(&bytes_field(2, &bytes_field(1, &image))).unwrap();
+    assert!(InputGenerator::new(Fill::Zeros)
+        .generate_for_spec(&spec)
+        .is_err());
+
+    let unknown = InputSpec {
+        name: "x".to_string(),
+        data_type: ArrayDataType::Unknown(0x30040),
+        shape: vec![2],
+        flexibility: ShapeFlexibility::Fixed,
+        is_optional: false,
+    };
+    assert!(InputGenerator::new(Fill::Zeros)
+        .generate(&[unknown])
+        .is_err());
+}
+
+#[test]
+pub fn generates_doubles() {
+    let double = InputSpec {
+        name: "x".to_string(),
+        data_type: ArrayDataType::Float64,
+        shape: vec![2],
+        flexibility: ShapeFlexibility::Fixed,
+        is_optional: false,
+    };
+    let inputs = InputGenerator::new(Fill::Ones).generate(&[double]).unwrap();
+    let [(_, MLArray::Float64Array(x))] = &inputs[..] else {
+        panic!("expected an f64 array, got {inputs:?}");
+    };
+    assert_eq!(x.as_slice().unwrap(), &[1.0, 1.0]);
+}
@@ -0,0 +1,89 @@
+use coreml_rs::{
+    mlarray::MLArray,
+    weights::{BlobDataType, WeightFile},
+};
+
+/// Appends a blob record with 64 byte aligned data, returns the metadata offset.
+fn push_blob(buf: &mut Vec<u8>, dtype: u32, data: &[u8], padding_bits: u64) -> u64 {
+    let offset = buf.len() as u64;
+    buf.extend(0xDEADBEEFu32.to_le_bytes());
+    buf.extend(dtype.to_le_bytes());
+    buf.extend((data.len() as u64).to_le_bytes());
+    buf.extend((offset + 64).to_le_bytes());
+    buf.extend(padding_bits.to_le_bytes());
+    buf.extend([0u8; 32]);
+    buf.extend(data);
+    buf.resize(buf.len().next_multiple_of(64), 0);
+    offset
+}
+
+fn weight_file() -> (Vec<u8>, [u64; 3]) {
+    let mut buf = vec![0u8; 64];
+    buf[0] = 3; // count
+    buf[4] = 2; // version
+    let floats: Vec<u8> = [1.0f32, -2.5]
+        .iter()
+        .flat_map(|f| f.to_le_bytes())
+        .collect();
+    let f32_blob = push_blob(&mut buf, 2, &floats, 0);
+    let int4_blob = push_blob(&mut buf, 8, &[0x8F, 0x07], 4);
+    let uint2_blob = push_blob(&mut buf, 10, &[0b11100100], 0);
+    (buf, [f32_blob, int4_blob, uint2_blob])
+}
+
+#[test]
+pub fn read_weight_blobs() {
+    let (buf, [f32_blob, int4_blob, uint2_blob]) = weight_file();
+    let weights = WeightFile::from_bytes(buf).unwrap();
+    assert_eq!(weights.blobs().len(), 3);
+    assert_eq!(weights.version(), 2);
+
+    let blob = weights.blob_at(f32_blob).unwrap();
+    assert_eq!(blob.dtype, BlobDataType::Float32);
+    let MLArray::Float32Array(array) = weights.read(blob).unwrap() else {
+        panic!("expected f32 blob");
+    };
+    assert_eq!(array.as_slice().unwrap(), &[1.0, -2.5]);
+
+    let blob = weights.blob_at(int4_blob).unwrap();
+    assert_eq!(blob.len(), 3);
+    let MLArray::Int8Array(array) = weights.read(blob).unwrap() else {
+        panic!("expected int4 blob to widen to i8");
+    };
+    assert_eq!(array.as_slice().unwrap(), &[-1, -8, 7]);
+
+    let blob = weights.blob_at(uint2_blob).unwrap();
+    assert!(blob.dtype.is_palettized());
+    let MLArray::UInt8Array(array) = weights.read(blob).unwrap() else {
+        panic!("expected palette indices as u8");
+    };
+    assert_eq!(array.as_slice().unwrap(), &[0, 1, 2, 3]);
+}
+
+#[test]
+pub fn reject_truncated_weights() {
+    let mut buf = vec![0u8; 64];
+    buf[0] = 2;
+    push_blob(&mut buf, 2, &[0; 8], 0);
+    assert!(WeightFile::from_bytes(buf).is_err());
+}
+
+#[test]
+pub fn read_blobs_without_data() {
+    let dir = tempdir::TempDir::new("coreml-weights").unwrap();
+    let path = dir.path().join("weight.bin");
+    let (buf, _) = weight_file();
+    std::fs::write(&path, &buf).unwrap();
+    let weights = WeightFile::from_bytes(buf.clone()).unwrap();
+    assert_eq!(WeightFile::read_blobs(&path).unwrap(), weights.blobs());
+
+    std::fs::write(&path, &buf[..buf.len() - 64]).unwrap();
+    assert!(WeightFile::read_blobs(&path).is_err());
+
+    // a corrupt count fails instead of allocating for it
+    let mut buf = vec![0u8; 64];
+    buf[..4].copy_from_slice(&u32::MAX.to_le_bytes());
+    std::fs::write(&path, &buf).unwrap();
+    assert!(WeightFile::read_blobs(&path).is_err());
+    assert!(WeightFile::from_bytes(buf).is_err());
+}